@@ -1,162 +1,338 @@
 use crate::{
-    backends::render_template,
-    project::{config::MuFunctionConfig, MuFunction},
-    util::print_full_line,
+    backends::{
+        rename_if_exists, rename_package, render_template, with_local_id, MuFunctionBackend,
+    },
+    error::{MuCliError, Result},
+    project::{
+        config::{MuFunctionConfig, MuNetwork},
+        MuFunction,
+    },
+    util::{command_output, print_full_line, run_command},
 };
 use candid::TypeEnv;
 use candid_parser::{typing, IDLProg};
 use serde::{Deserialize, Serialize};
 use serde_json::json;
 use std::{
-    collections::HashMap,
+    collections::{BTreeMap, HashMap},
     fs,
     net::TcpStream,
-    process::{Child, Command, Stdio},
+    process::{Child, Command},
     sync::{LazyLock, Mutex},
     thread::sleep,
     time::Duration,
 };
 
+/// The type of functions deployed as ICP canisters.
+pub const FUNCTION_TYPE: &str = "icp";
+
 static DFX_CONFIG_FILENAME: &str = "dfx.json";
 
 static DFX_PROCESS: LazyLock<Mutex<Option<Child>>> = LazyLock::new(|| Mutex::new(None));
 
+pub fn create_backend<'a>(
+    root: &str,
+    function: &'a mut MuFunction,
+) -> Result<Box<dyn MuFunctionBackend + 'a>> {
+    Ok(Box::new(IcpFunction::new(root, function)?))
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(from = "MuIcpFunctionStateFile")]
+pub struct MuIcpFunctionState {
+    pub did: Option<String>,
+    /// Canister ids keyed by network name
+    pub canister_ids: BTreeMap<String, String>,
+    pub js_bindings: Option<String>,
+}
+
+/// [`MuIcpFunctionState`] as stored, including the single `canister_id` of
+/// the local network older versions of `mu` recorded.
+#[derive(Deserialize)]
+struct MuIcpFunctionStateFile {
+    did: Option<String>,
+    #[serde(default)]
+    canister_ids: BTreeMap<String, String>,
+    canister_id: Option<String>,
+    js_bindings: Option<String>,
+}
+
+impl From<MuIcpFunctionStateFile> for MuIcpFunctionState {
+    fn from(file: MuIcpFunctionStateFile) -> Self {
+        Self {
+            did: file.did,
+            canister_ids: with_local_id(file.canister_ids, file.canister_id),
+            js_bindings: file.js_bindings,
+        }
+    }
+}
+
 pub struct IcpFunction<'a> {
     function: &'a mut MuFunction,
+    state: MuIcpFunctionState,
     root: String,
 }
 
 impl<'a> IcpFunction<'a> {
-    pub fn new(root: &str, function: &'a mut MuFunction) -> Self {
-        let out = Self {
+    pub fn new(root: &str, function: &'a mut MuFunction) -> Result<Self> {
+        Ok(Self {
+            state: function.state.backend_state.get()?,
             function,
             root: root.to_string(),
-        };
-        out
+        })
+    }
+
+    /// Changes the function's state and records it.
+    fn update_state(&mut self, update: impl FnOnce(&mut MuIcpFunctionState)) {
+        update(&mut self.state);
+        self.function.state.backend_state.set(&self.state);
     }
 
-    pub fn save_dfx_config(&self) {
+    pub fn save_dfx_config(&self, network: &MuNetwork) -> Result<()> {
         IcpConfig::from(&self.function.config)
-            .save(&format!("{}/{}", self.root, DFX_CONFIG_FILENAME));
+            .with_network(network)
+            .save(&format!("{}/{}", self.root, DFX_CONFIG_FILENAME))
     }
 
-    pub fn init(&self) {
-        let data = json!({
-            "Cargo.toml": {
-                "name":  self.function.config.name,
-            }
-        });
+    /// Reads the id dfx assigned to `canister` in the dfx project at `root`.
+    ///
+    /// dfx keeps ids of ephemeral networks under `.dfx/<network>` and those
+    /// of persistent networks in the project root.
+    pub fn read_canister_id(root: &str, canister: &str, network: &MuNetwork) -> Result<String> {
+        let canister_ids_path = [
+            format!("{}/.dfx/{}/canister_ids.json", root, network.name),
+            format!("{}/canister_ids.json", root),
+        ]
+        .into_iter()
+        .find(|path| fs::metadata(path).is_ok())
+        .ok_or_else(|| {
+            MuCliError::Backend(format!(
+                "dfx did not record any canister ids for network `{}`",
+                network.name
+            ))
+        })?;
+
+        let canister_ids_json = fs::read_to_string(&canister_ids_path)
+            .map_err(|e| MuCliError::io(format!("Failed to read {}", canister_ids_path), e))?;
+        let canister_ids: HashMap<String, HashMap<String, String>> =
+            serde_json::from_str(&canister_ids_json)
+                .map_err(|e| MuCliError::parse_json(&canister_ids_path, e))?;
+
+        canister_ids
+            .get(canister)
+            .and_then(|ids| ids.get(&network.name))
+            .cloned()
+            .ok_or_else(|| {
+                MuCliError::Backend(format!(
+                    "No {} canister id for `{}` in {}",
+                    network.name, canister, canister_ids_path
+                ))
+            })
+    }
 
-        render_template("icp/function", &self.root, data);
-        self.save_dfx_config();
+    /// Moves the ids dfx recorded for canister `old_name` to `new_name`, so
+    /// the renamed function keeps its canisters.
+    fn rename_canister_ids(&self, old_name: &str) -> Result<()> {
+        let mut paths = vec![format!("{}/canister_ids.json", self.root)];
+        let dfx_dir = format!("{}/.dfx", self.root);
+        if let Ok(networks) = fs::read_dir(&dfx_dir) {
+            paths.extend(
+                networks
+                    .flatten()
+                    .map(|network| format!("{}/canister_ids.json", network.path().display())),
+            );
+        }
+
+        for path in paths {
+            let Ok(canister_ids_json) = fs::read_to_string(&path) else {
+                continue;
+            };
+            let mut canister_ids: HashMap<String, HashMap<String, String>> =
+                serde_json::from_str(&canister_ids_json)
+                    .map_err(|e| MuCliError::parse_json(&path, e))?;
+            if let Some(ids) = canister_ids.remove(old_name) {
+                canister_ids.insert(self.function.config.name.clone(), ids);
+                fs::write(&path, serde_json::to_string_pretty(&canister_ids).unwrap())
+                    .map_err(|e| MuCliError::io(format!("Failed to write {}", path), e))?;
+            }
+        }
+        Ok(())
     }
 
     fn get_did_js(&self) -> Option<String> {
-        let prog = self.function.state.unwrap_icp().did.as_ref()?;
+        let prog = self.state.did.as_ref()?;
         let ast = prog.parse::<IDLProg>().ok()?;
         let mut env = TypeEnv::new();
         let actor = typing::check_prog(&mut env, &ast).ok()?;
         Some(candid_parser::bindings::javascript::compile(&env, &actor))
     }
 
-    pub fn build(&mut self) {
-        print_full_line("Building ICP project");
-        let r = Command::new("cargo")
-            .arg("build")
-            .arg("--release")
-            .arg("--target")
-            .arg("wasm32-unknown-unknown")
-            .current_dir(&self.root)
-            .status()
-            .expect("Failed to build ICP project");
-
-        if !r.success() {
-            eprintln!("Failed to build ICP project");
-            std::process::exit(1);
+    /// Starts the local replica unless it is already running.
+    pub fn start() -> Result<()> {
+        if DFX_PROCESS.lock().unwrap().is_some() {
+            return Ok(());
         }
 
-        print_full_line("Extracting candid file");
-        let candid = Command::new("candid-extractor")
-            .arg(format!(
-                "target/wasm32-unknown-unknown/release/{}.wasm",
-                self.function.config.name.replace("-", "_")
-            ))
-            .current_dir(&self.root)
-            .stderr(Stdio::inherit())
-            .output()
-            .expect("Failed to extract candid file");
-
-        if candid.status.code().unwrap() != 0 {
-            eprintln!("Failed to extract candid file");
-            std::process::exit(1);
+        print_full_line("Starting local ICP node...");
+        let mut command = Command::new("dfx");
+        command.arg("start").current_dir(".");
+        let dfx = command
+            .spawn()
+            .map_err(|e| MuCliError::spawn(&command, e))?;
+
+        *DFX_PROCESS.lock().unwrap() = Some(dfx);
+
+        loop {
+            let conn = TcpStream::connect("localhost:4943");
+            if conn.is_ok() {
+                break;
+            }
+            sleep(Duration::from_secs(1));
         }
+        Ok(())
+    }
+}
+
+impl MuFunctionBackend for IcpFunction<'_> {
+    fn init(&mut self) -> Result<()> {
+        let data = json!({
+            "Cargo.toml": {
+                "name":  self.function.config.name,
+            }
+        });
+
+        render_template("icp/function", &self.root, data)?;
+        self.save_dfx_config(&MuNetwork::local())
+    }
+
+    fn build(&mut self) -> Result<()> {
+        print_full_line("Building ICP project");
+        run_command(
+            Command::new("cargo")
+                .arg("build")
+                .arg("--release")
+                .arg("--target")
+                .arg("wasm32-unknown-unknown")
+                .envs(&self.function.build_env)
+                .current_dir(&self.root),
+            &format!("build ICP function `{}`", self.function.config.name),
+        )?;
+
+        print_full_line("Extracting candid file");
+        let candid = command_output(
+            Command::new("candid-extractor")
+                .arg(format!(
+                    "target/wasm32-unknown-unknown/release/{}.wasm",
+                    self.function.config.name.replace("-", "_")
+                ))
+                .current_dir(&self.root),
+            "extract candid file",
+        )?;
 
         // Store DID in the state and in a file
-        self.function.state.unwrap_icp_mut().did =
-            Some(String::from_utf8_lossy(&candid.stdout).into());
+        self.update_state(|state| state.did = Some(String::from_utf8_lossy(&candid).into()));
 
         let did_path = format!("{}/{}.did", self.root, self.function.config.name);
-        fs::write(&did_path, &candid.stdout).unwrap();
+        fs::write(&did_path, &candid)
+            .map_err(|e| MuCliError::io(format!("Failed to write {}", did_path), e))?;
 
         print_full_line("Generating JavaScript bindings");
-        let js = self.get_did_js();
-
-        if js.is_none() {
-            eprintln!("Failed to generate JavaScript bindings");
-            std::process::exit(1);
-        }
-        self.function.state.unwrap_icp_mut().js_bindings = js;
+        let js = self.get_did_js().ok_or_else(|| {
+            MuCliError::Backend(format!(
+                "Failed to generate JavaScript bindings from {}",
+                did_path
+            ))
+        })?;
+        self.update_state(|state| state.js_bindings = Some(js));
+        Ok(())
     }
 
-    pub fn deploy(&mut self) {
-        Self::start();
+    fn deploy(&mut self, network: &MuNetwork) -> Result<()> {
+        if network.is_local() {
+            Self::start()?;
+        }
+        self.save_dfx_config(network)?;
 
         print_full_line("Deploying ICP project...");
-        let r = Command::new("dfx")
-            .arg("deploy")
-            .current_dir(&self.root)
-            .status()
-            .expect("Failed to deploy ICP project");
-
-        if !r.success() {
-            eprintln!("Failed to deploy ICP project");
-            std::process::exit(1);
+        run_command(
+            Command::new("dfx")
+                .arg("deploy")
+                .arg("--network")
+                .arg(&network.name)
+                .envs(&self.function.build_env)
+                .current_dir(&self.root),
+            &format!(
+                "deploy ICP function `{}` to network `{}`",
+                self.function.config.name, network.name
+            ),
+        )?;
+
+        let canister_id = Self::read_canister_id(&self.root, &self.function.config.name, network)?;
+        self.update_state(|state| {
+            state.canister_ids.insert(network.name.clone(), canister_id);
+        });
+        Ok(())
+    }
+
+    fn status(&self) -> String {
+        let state = &self.state;
+        if state.canister_ids.is_empty() {
+            return match state.did {
+                Some(_) => "built, not deployed".to_string(),
+                None => "not built".to_string(),
+            };
         }
 
-        let canister_ids_json =
-            fs::read_to_string(format!("{}/.dfx/local/canister_ids.json", self.root)).unwrap();
-        let canister_ids: HashMap<String, HashMap<String, String>> =
-            serde_json::from_str(&canister_ids_json).unwrap();
+        let deployments = state
+            .canister_ids
+            .iter()
+            .map(|(network, canister_id)| format!("{}: {}", network, canister_id))
+            .collect::<Vec<_>>();
+        format!("deployed to canisters {}", deployments.join(", "))
+    }
 
-        let canister_id = canister_ids
-            .get(self.function.config.name.as_str())
-            .unwrap()
-            .get("local")
-            .unwrap();
-        self.function.state.unwrap_icp_mut().canister_id = Some(canister_id.to_string());
+    fn deployments(&self) -> BTreeMap<String, String> {
+        self.state.canister_ids.clone()
     }
 
-    fn start() {
-        if DFX_PROCESS.lock().unwrap().is_some() {
-            return;
+    fn remove(&mut self, network: &MuNetwork) -> Result<()> {
+        if !self.state.canister_ids.contains_key(&network.name) {
+            return Ok(());
         }
 
-        print_full_line("Starting local ICP node...");
-        let dfx = Command::new("dfx")
-            .arg("start")
-            .current_dir(".")
-            .spawn()
-            .expect("Failed to start dfx");
-
-        *DFX_PROCESS.lock().unwrap() = Some(dfx);
-
-        loop {
-            let conn = TcpStream::connect("localhost:4943");
-            if conn.is_ok() {
-                break;
-            }
-            sleep(Duration::from_secs(1));
+        if network.is_local() {
+            Self::start()?;
         }
+        self.save_dfx_config(network)?;
+
+        print_full_line("Removing ICP canister...");
+        run_command(
+            Command::new("dfx")
+                .arg("canister")
+                .arg("delete")
+                .arg("--network")
+                .arg(&network.name)
+                .arg("--yes")
+                .arg(&self.function.config.name)
+                .current_dir(&self.root),
+            &format!("remove ICP canister `{}`", self.function.config.name),
+        )?;
+
+        self.update_state(|state| {
+            state.canister_ids.remove(&network.name);
+        });
+        Ok(())
+    }
+
+    fn renamed(&mut self, old_name: &str) -> Result<()> {
+        let name = &self.function.config.name;
+        rename_package(&self.root, old_name, name)?;
+        rename_if_exists(
+            &format!("{}/{}.did", self.root, old_name),
+            &format!("{}/{}.did", self.root, name),
+        )?;
+        self.rename_canister_ids(old_name)?;
+        self.save_dfx_config(&MuNetwork::local())
     }
 }
 
@@ -164,6 +340,8 @@ impl<'a> IcpFunction<'a> {
 pub struct IcpConfig {
     canisters: HashMap<String, Canister>,
     defaults: Defaults,
+    #[serde(skip_serializing_if = "HashMap::is_empty", default)]
+    networks: HashMap<String, Network>,
     output_env_file: String,
     version: u32,
 }
@@ -183,6 +361,7 @@ impl From<&MuFunctionConfig> for IcpConfig {
         Self {
             canisters,
             defaults: Default::default(),
+            networks: HashMap::new(),
             output_env_file: ".env".to_string(),
             version: 1,
         }
@@ -190,9 +369,23 @@ impl From<&MuFunctionConfig> for IcpConfig {
 }
 
 impl IcpConfig {
-    fn save(&self, path: &str) {
+    pub fn with_network(mut self, network: &MuNetwork) -> Self {
+        if let Some(url) = &network.config.icp_url {
+            self.networks.insert(
+                network.name.clone(),
+                Network {
+                    providers: vec![url.clone()],
+                    network_type: "persistent".to_string(),
+                },
+            );
+        }
+        self
+    }
+
+    pub fn save(&self, path: &str) -> Result<()> {
         let serialized = serde_json::to_string_pretty(&self).unwrap();
-        fs::write(path, serialized).unwrap();
+        fs::write(path, serialized)
+            .map_err(|e| MuCliError::io(format!("Failed to write {}", path), e))
     }
 }
 
@@ -204,6 +397,13 @@ struct Canister {
     canister_type: String,
 }
 
+#[derive(Serialize, Deserialize, Debug)]
+struct Network {
+    providers: Vec<String>,
+    #[serde(rename = "type")]
+    network_type: String,
+}
+
 #[derive(Serialize, Deserialize, Debug, Default)]
 struct Defaults {
     build: Build,
@@ -214,3 +414,49 @@ struct Build {
     args: String,
     packtool: String,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::project::{state::MuProjectState, MuFunctionType};
+
+    #[test]
+    fn loads_ids_recorded_before_networks() {
+        let state: MuProjectState = serde_json::from_str(
+            r#"{"functions":[
+                {"name":"hello","backend_state":{"type":"icp","did":null,
+                    "canister_id":"bkyz2-fmaaa-aaaaa-qaaaq-cai","js_bindings":null}},
+                {"name":"fresh","backend_state":{"type":"icp","did":null,
+                    "canister_id":null,"js_bindings":null}}
+            ]}"#,
+        )
+        .unwrap();
+
+        let hello: MuIcpFunctionState = state.functions["hello"].backend_state.get().unwrap();
+        assert_eq!(
+            hello.canister_ids,
+            BTreeMap::from([("local".to_owned(), "bkyz2-fmaaa-aaaaa-qaaaq-cai".to_owned())])
+        );
+        let fresh: MuIcpFunctionState = state.functions["fresh"].backend_state.get().unwrap();
+        assert!(fresh.canister_ids.is_empty());
+    }
+
+    #[test]
+    fn saves_ids_per_network() {
+        let mut function = MuFunction::new("hello", MuFunctionType::new(FUNCTION_TYPE));
+        let mut backend = IcpFunction::new("functions/hello", &mut function).unwrap();
+        backend.update_state(|state| {
+            state
+                .canister_ids
+                .insert("staging".to_owned(), "aaaaa-aa".to_owned());
+        });
+        let deployments = backend.deployments();
+
+        let json = serde_json::to_string(&function.state).unwrap();
+        assert!(!json.contains("canister_id\""));
+        let mut loaded = MuFunction::new("hello", MuFunctionType::new(FUNCTION_TYPE));
+        loaded.state = serde_json::from_str(&json).unwrap();
+        let loaded = IcpFunction::new("functions/hello", &mut loaded).unwrap();
+        assert_eq!(loaded.deployments(), deployments);
+    }
+}
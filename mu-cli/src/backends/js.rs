@@ -10,8 +10,9 @@ use serde_json::json;
 
 use super::render_template;
 use crate::{
+    error::{MuCliError, Result},
     project::{config::MuFrontendConfig, MuFrontendTemplate},
-    util::print_full_line,
+    util::{print_full_line, run_command},
 };
 
 pub struct JsBackend<'a> {
@@ -27,41 +28,64 @@ impl<'a> JsBackend<'a> {
         }
     }
 
-    pub fn create_frontend(&self) {
+    pub fn create_frontend(&self) -> Result<()> {
         let data: serde_json::Value = json!({
             "package.json": {
                 "name": self.config.name,
             }
         });
 
-        let template_path = match self.config.template {
+        let template_path = match &self.config.template {
             MuFrontendTemplate::Vanilla => "js/vanilla",
-            MuFrontendTemplate::Vue => unimplemented!(),
-            MuFrontendTemplate::React => unimplemented!(),
+            template @ (MuFrontendTemplate::Vue | MuFrontendTemplate::React) => {
+                return Err(MuCliError::Backend(format!(
+                    "The {:?} frontend template is not supported yet",
+                    template
+                )))
+            }
         };
 
-        render_template(template_path, &self.root, data);
+        render_template(template_path, &self.root, data)?;
 
-        std::process::Command::new("npm")
-            .arg("install")
-            .current_dir(&self.root)
-            .status()
-            .expect("Failed to install dependencies");
+        run_command(
+            std::process::Command::new("npm")
+                .arg("install")
+                .current_dir(&self.root),
+            "install frontend dependencies",
+        )?;
 
         print_full_line("Frontend created!");
+        Ok(())
+    }
+
+    /// Builds the frontend for production into `out_dir`, relative to its
+    /// root.
+    pub fn build(&self, out_dir: &str) -> Result<()> {
+        print_full_line(&format!("Building frontend {}", self.config.name));
+        run_command(
+            std::process::Command::new("npm")
+                .arg("run")
+                .arg("build")
+                .arg("--")
+                .arg("--outDir")
+                .arg(out_dir)
+                .arg("--emptyOutDir")
+                .current_dir(&self.root),
+            &format!("build frontend `{}`", self.config.name),
+        )
     }
 
-    pub fn dev(self) -> UnboundedReceiver<()> {
+    pub fn dev(self) -> Result<UnboundedReceiver<()>> {
         let (tx, rx) = mpsc::unbounded_channel();
+
+        let mut command = Command::new("npm");
+        command.arg("run").arg("dev").current_dir(&self.root);
+        let mut child = command
+            .spawn()
+            .map_err(|e| MuCliError::spawn(command.as_std(), e))?;
+
         print_full_line("Dev server started at http://localhost:5173");
         tokio::spawn(async move {
-            let mut child = Command::new("npm")
-                .arg("run")
-                .arg("dev")
-                .current_dir(&self.root)
-                .spawn()
-                .expect("Failed to start dev server");
-
             loop {
                 let conn = TcpStream::connect("localhost:5173").await;
                 if conn.is_ok() {
@@ -74,6 +98,6 @@ impl<'a> JsBackend<'a> {
 
             child.wait().await.unwrap();
         });
-        rx
+        Ok(rx)
     }
 }
@@ -0,0 +1,591 @@
+use std::{
+    fs,
+    io::{BufRead, Write},
+    path::Path,
+    process::Command,
+};
+
+use candid::{
+    utils::{ArgumentDecoder, ArgumentEncoder},
+    CandidType, Principal,
+};
+use rust_embed::Embed;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    backends::icp::{self, IcpConfig, IcpFunction},
+    error::{MuCliError, Result},
+    project::{
+        config::{MuFunctionConfig, MuNetwork},
+        state::MuKvServiceState,
+        MuFunctionType,
+    },
+    util::{pipe_command, print_full_line, run_command},
+};
+
+/// The environment variable `mu_sdk::kv::MuIcpKv::new` reads the mu-kv
+/// canister id from at build time.
+pub static KV_CANISTER_ID_ENV: &str = "MU_KV_CANISTER_ID";
+
+static KV_CANISTER_NAME: &str = "mu-kv";
+
+static KV_ROOT: &str = "services/kv";
+
+/// The most entries fetched per call; mu-kv also caps pages at 1MiB.
+const PAGE_SIZE: u32 = 1000;
+
+/// Imports are split into calls of at most this many bytes of entries,
+/// keeping them below the 2MiB message limit.
+const IMPORT_BATCH_BYTES: usize = 1024 * 1024;
+
+/// A bound on the bytes Candid adds to each imported entry, for its field
+/// tags, lengths and expiry time.
+const IMPORT_ENTRY_OVERHEAD: usize = 32;
+
+#[derive(Embed)]
+#[folder = "../mu-kv"]
+#[include = "Cargo.toml"]
+#[include = "mu-kv.did"]
+#[include = "src/*"]
+struct KvSources;
+
+/// The mu-kv canister, deployed from the sources bundled with the CLI into
+/// `services/kv`.
+pub struct KvService<'a> {
+    state: &'a mut MuKvServiceState,
+    root: String,
+}
+
+impl<'a> KvService<'a> {
+    pub fn new(state: &'a mut MuKvServiceState) -> Self {
+        Self {
+            state,
+            root: KV_ROOT.to_string(),
+        }
+    }
+
+    fn write_sources(&self) -> Result<()> {
+        for file in KvSources::iter() {
+            let destination = format!("{}/{}", self.root, file);
+            if let Some(parent) = Path::new(&destination).parent() {
+                fs::create_dir_all(parent).map_err(|e| {
+                    MuCliError::io(format!("Failed to create {}", parent.display()), e)
+                })?;
+            }
+
+            let contents = KvSources::get(&file).unwrap().data;
+            fs::write(&destination, contents)
+                .map_err(|e| MuCliError::io(format!("Failed to write {}", destination), e))?;
+        }
+        Ok(())
+    }
+
+    /// Deploys mu-kv to `network` and returns its canister id.
+    pub fn deploy(&mut self, network: &MuNetwork) -> Result<String> {
+        self.write_sources()?;
+        save_dfx_config(&self.root, network)?;
+
+        if network.is_local() {
+            IcpFunction::start()?;
+        }
+
+        print_full_line("Deploying KV service...");
+        run_command(
+            Command::new("dfx")
+                .arg("deploy")
+                .arg("--network")
+                .arg(&network.name)
+                .current_dir(&self.root),
+            &format!("deploy the KV service to network `{}`", network.name),
+        )?;
+
+        let canister_id = IcpFunction::read_canister_id(&self.root, KV_CANISTER_NAME, network)?;
+        self.state
+            .canister_ids
+            .insert(network.name.clone(), canister_id.clone());
+        Ok(canister_id)
+    }
+}
+
+fn save_dfx_config(root: &str, network: &MuNetwork) -> Result<()> {
+    IcpConfig::from(&MuFunctionConfig::new(
+        KV_CANISTER_NAME,
+        MuFunctionType::new(icp::FUNCTION_TYPE),
+    ))
+    .with_network(network)
+    .save(&format!("{}/dfx.json", root))
+}
+
+#[derive(CandidType, Serialize, Deserialize, Debug, Clone)]
+pub struct KvIndex {
+    pub name: String,
+    pub path: String,
+}
+
+#[derive(CandidType, Deserialize, Debug)]
+enum KvValue {
+    Text(String),
+    Blob(Vec<u8>),
+}
+
+#[derive(CandidType, Deserialize, Debug)]
+struct KvEntry {
+    key: String,
+    value: KvValue,
+    expires_at: Option<u64>,
+}
+
+impl KvEntry {
+    /// A bound on the size of the entry in an `import_collection` call.
+    fn import_size(&self) -> usize {
+        IMPORT_ENTRY_OVERHEAD
+            + self.key.len()
+            + match &self.value {
+                KvValue::Text(value) => value.len(),
+                KvValue::Blob(blob) => blob.len(),
+            }
+    }
+}
+
+#[derive(CandidType, Deserialize, Debug)]
+struct ExportCursor {
+    blobs: bool,
+    key: String,
+}
+
+#[derive(CandidType, Deserialize, Debug)]
+struct ExportPage {
+    entries: Vec<KvEntry>,
+    indexes: Vec<KvIndex>,
+    next: Option<ExportCursor>,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Debug)]
+pub struct KvUsage {
+    pub bytes: u64,
+    pub entries: u64,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Debug)]
+pub struct KvCallStats {
+    pub method: String,
+    pub instructions: u64,
+    /// When the call was made, in nanoseconds since the epoch.
+    pub at: u64,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Debug)]
+pub struct KvPrincipalStats {
+    pub principal: Principal,
+    pub collections: u64,
+    pub usage: KvUsage,
+}
+
+/// What mu-kv stores and the resources it uses, as reported by its `stats`
+/// query.
+#[derive(CandidType, Serialize, Deserialize, Debug)]
+pub struct KvStats {
+    pub principals: Vec<KvPrincipalStats>,
+    /// The principal the next page of `principals` starts after, if any.
+    pub next: Option<Principal>,
+    pub principal_count: u64,
+    pub collections: u64,
+    pub usage: KvUsage,
+    pub heap_memory_bytes: u64,
+    pub stable_memory_bytes: u64,
+    pub cycles: u128,
+    /// The most recent update calls, oldest first.
+    pub recent_calls: Vec<KvCallStats>,
+}
+
+#[derive(CandidType, Deserialize, Debug)]
+struct QuotaExceeded {
+    quota: KvQuota,
+    usage: KvUsage,
+}
+
+impl QuotaExceeded {
+    fn into_error(self) -> MuCliError {
+        MuCliError::Backend(format!(
+            "The write would take the owner's usage to {} bytes in {} entries, over its quota \
+             of {} bytes in {} entries",
+            self.usage.bytes, self.usage.entries, self.quota.max_bytes, self.quota.max_entries
+        ))
+    }
+}
+
+#[derive(CandidType, Deserialize, Debug)]
+struct VersionedValue {
+    value: String,
+    version: u64,
+}
+
+#[derive(CandidType, Deserialize, Debug)]
+struct Page {
+    entries: Vec<(String, String)>,
+    next: Option<String>,
+}
+
+#[derive(CandidType, Deserialize, Debug)]
+struct KvQuota {
+    max_bytes: u64,
+    max_entries: u64,
+}
+
+/// A line of an export file: either an index of the collection or one of
+/// its entries, with blobs in hex.
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum ExportLine {
+    Index {
+        index: KvIndex,
+    },
+    Text {
+        key: String,
+        value: String,
+        #[serde(skip_serializing_if = "Option::is_none", default)]
+        expires_at: Option<u64>,
+    },
+    Blob {
+        key: String,
+        blob: String,
+    },
+}
+
+impl From<KvEntry> for ExportLine {
+    fn from(entry: KvEntry) -> Self {
+        match entry.value {
+            KvValue::Text(value) => ExportLine::Text {
+                key: entry.key,
+                value,
+                expires_at: entry.expires_at,
+            },
+            KvValue::Blob(blob) => ExportLine::Blob {
+                key: entry.key,
+                blob: to_hex(&blob),
+            },
+        }
+    }
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+/// Calls the mu-kv canister deployed to a network through `dfx`, as the
+/// current dfx identity. That identity deployed mu-kv and so controls it,
+/// which gives it access to the collections of the project's functions.
+pub struct KvClient {
+    canister_id: String,
+    network: MuNetwork,
+    root: String,
+    /// The principal whose collections to work with, if not the caller.
+    owner: Option<Principal>,
+}
+
+impl KvClient {
+    pub fn new(canister_id: String, network: MuNetwork, owner: Option<Principal>) -> Result<Self> {
+        let root = KV_ROOT.to_string();
+        fs::create_dir_all(&root)
+            .map_err(|e| MuCliError::io(format!("Failed to create {}", root), e))?;
+        // dfx only knows the networks declared in the project it runs in
+        save_dfx_config(&root, &network)?;
+
+        Ok(Self {
+            canister_id,
+            network,
+            root,
+            owner,
+        })
+    }
+
+    fn call<A: ArgumentEncoder, R: for<'de> ArgumentDecoder<'de>>(
+        &self,
+        method: &str,
+        query: bool,
+        args: A,
+    ) -> Result<R> {
+        let args = candid::encode_args(args).map_err(|e| {
+            MuCliError::Backend(format!(
+                "Failed to encode the arguments of `{}`: {}",
+                method, e
+            ))
+        })?;
+
+        let mut command = Command::new("dfx");
+        command
+            .args(["canister", "call", "--network", &self.network.name])
+            .args(["--type", "raw", "--output", "raw", "--argument-file", "-"]);
+        if query {
+            command.arg("--query");
+        }
+        command
+            .arg(&self.canister_id)
+            .arg(method)
+            .current_dir(&self.root);
+        let output = pipe_command(
+            &mut command,
+            to_hex(&args).as_bytes(),
+            &format!("call `{}` on the KV service", method),
+        )?;
+
+        from_hex(String::from_utf8_lossy(&output).trim())
+            .ok_or_else(|| "the reply is not hex encoded".to_string())
+            .and_then(|reply| candid::decode_args(&reply).map_err(|e| e.to_string()))
+            .map_err(|message| {
+                MuCliError::Backend(format!(
+                    "Failed to decode the reply of `{}`: {}",
+                    method, message
+                ))
+            })
+    }
+
+    /// The names of the owner's collections.
+    pub fn list(&self) -> Result<Vec<String>> {
+        let (names,): (Vec<String>,) = self.call("list_collections", true, (self.owner,))?;
+        Ok(names)
+    }
+
+    /// What mu-kv stores for each principal and in total, and the resources
+    /// it uses, reading the principals page by page. Only controllers of
+    /// mu-kv can read them.
+    pub fn stats(&self) -> Result<KvStats> {
+        let (mut stats,): (KvStats,) =
+            self.call("stats", true, (None::<Principal>, None::<u32>))?;
+        while let Some(after) = stats.next.take() {
+            let (page,): (KvStats,) = self.call("stats", true, (Some(after), None::<u32>))?;
+            stats.principals.extend(page.principals);
+            stats.next = page.next;
+        }
+        Ok(stats)
+    }
+
+    /// Fails unless the collection `name` exists.
+    fn check_collection(&self, name: &str) -> Result<()> {
+        let (exists,): (bool,) = self.call("has_collection", true, (name, self.owner))?;
+        if !exists {
+            return Err(MuCliError::Backend(format!(
+                "Collection `{}` does not exist",
+                name
+            )));
+        }
+        Ok(())
+    }
+
+    /// The value of `key` and its version.
+    pub fn get(&self, name: &str, key: &str) -> Result<Option<(String, u64)>> {
+        self.check_collection(name)?;
+        let (value,): (Option<VersionedValue>,) =
+            self.call("query_versioned", true, (name, key, self.owner))?;
+        Ok(value.map(|value| (value.value, value.version)))
+    }
+
+    /// Sets `key` to `value`, or removes it if `value` is `None`. A value
+    /// set with a TTL expires after `ttl_seconds`.
+    pub fn set(
+        &self,
+        name: &str,
+        key: &str,
+        value: Option<&str>,
+        ttl_seconds: Option<u64>,
+    ) -> Result<()> {
+        self.check_collection(name)?;
+        let (result,): (std::result::Result<(), QuotaExceeded>,) = self.call(
+            "update_collection",
+            false,
+            (name, key, value, self.owner, ttl_seconds),
+        )?;
+        result.map_err(QuotaExceeded::into_error)
+    }
+
+    /// The keys of the collection that start with `prefix`, in order.
+    pub fn keys(&self, name: &str, prefix: &str) -> Result<Vec<String>> {
+        self.check_collection(name)?;
+        let mut keys = Vec::new();
+        let mut start_after = None;
+        loop {
+            let (page,): (Page,) = self.call(
+                "scan",
+                true,
+                (name, prefix, &start_after, PAGE_SIZE, self.owner),
+            )?;
+            keys.extend(page.entries.into_iter().map(|(key, _)| key));
+            if page.next.is_none() {
+                return Ok(keys);
+            }
+            start_after = keys.last().cloned();
+        }
+    }
+
+    /// Writes the collection `name` of the owner to `out` as JSON lines: its
+    /// indexes, then its text entries, then its blobs. Returns the number of
+    /// entries written.
+    pub fn export(&self, name: &str, out: impl Write) -> Result<usize> {
+        let mut out = std::io::BufWriter::new(out);
+        let mut write_line = |line: ExportLine| {
+            let json = serde_json::to_string(&line).unwrap();
+            writeln!(out, "{}", json).map_err(|e| MuCliError::io("Failed to write the export", e))
+        };
+
+        let mut after = None;
+        let mut exported = 0;
+        loop {
+            let (page,): (Option<ExportPage>,) = self.call(
+                "export_collection",
+                true,
+                (name, after, PAGE_SIZE, self.owner),
+            )?;
+            let page = page.ok_or_else(|| {
+                MuCliError::Backend(format!("Collection `{}` does not exist", name))
+            })?;
+
+            if exported == 0 {
+                for index in page.indexes {
+                    write_line(ExportLine::Index { index })?;
+                }
+            }
+            exported += page.entries.len();
+            for entry in page.entries {
+                write_line(entry.into())?;
+            }
+
+            match page.next {
+                Some(next) => after = Some(next),
+                None => break,
+            }
+        }
+
+        out.flush()
+            .map_err(|e| MuCliError::io("Failed to write the export", e))?;
+        Ok(exported)
+    }
+
+    /// Imports the JSON lines written by [`KvClient::export`] into the
+    /// collection `name` of the owner, creating it with the exported indexes if
+    /// it does not exist. Returns the number of entries imported. Entries
+    /// are imported in batches, so if a batch fails the earlier ones stay
+    /// imported.
+    pub fn import(&self, name: &str, source: &str, input: impl BufRead) -> Result<usize> {
+        let mut indexes = Vec::new();
+        let mut entries = Vec::new();
+        for (i, line) in input.lines().enumerate() {
+            let line = line.map_err(|e| MuCliError::io("Failed to read the import", e))?;
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            let parse_error = |message: String| MuCliError::Parse {
+                file: source.to_owned(),
+                line: i + 1,
+                column: 1,
+                message,
+            };
+            let line = serde_json::from_str(&line).map_err(|e| parse_error(e.to_string()))?;
+            match line {
+                ExportLine::Index { index } => indexes.push(index),
+                ExportLine::Text {
+                    key,
+                    value,
+                    expires_at,
+                } => entries.push(KvEntry {
+                    key,
+                    value: KvValue::Text(value),
+                    expires_at,
+                }),
+                ExportLine::Blob { key, blob } => entries.push(KvEntry {
+                    key,
+                    value: KvValue::Blob(
+                        from_hex(&blob).ok_or_else(|| parse_error("invalid hex blob".into()))?,
+                    ),
+                    expires_at: None,
+                }),
+            }
+            if let Some(entry) = entries.last() {
+                if entry.import_size() > IMPORT_BATCH_BYTES {
+                    return Err(parse_error(format!(
+                        "entry `{}` is larger than the {} bytes a single call can import",
+                        entry.key, IMPORT_BATCH_BYTES
+                    )));
+                }
+            }
+        }
+
+        let imported = entries.len();
+        for batch in import_batches(entries) {
+            let (result,): (std::result::Result<(), QuotaExceeded>,) = self.call(
+                "import_collection",
+                false,
+                (name, &indexes, batch, self.owner),
+            )?;
+            result.map_err(QuotaExceeded::into_error)?;
+        }
+        Ok(imported)
+    }
+}
+
+/// Splits entries into batches of at most [`IMPORT_BATCH_BYTES`], each of
+/// which has to fit on its own. There is always at least one batch, so even
+/// an empty export creates the collection.
+fn import_batches(entries: Vec<KvEntry>) -> Vec<Vec<KvEntry>> {
+    let mut batches = vec![Vec::new()];
+    let mut bytes = 0;
+    for entry in entries {
+        let size = entry.import_size();
+        if bytes + size > IMPORT_BATCH_BYTES {
+            batches.push(Vec::new());
+            bytes = 0;
+        }
+        bytes += size;
+        batches.last_mut().unwrap().push(entry);
+    }
+    if batches.len() > 1 && batches[0].is_empty() {
+        batches.remove(0);
+    }
+    batches
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(key: &str, size: usize) -> KvEntry {
+        KvEntry {
+            key: key.to_owned(),
+            value: KvValue::Blob(vec![0; size - key.len() - IMPORT_ENTRY_OVERHEAD]),
+            expires_at: None,
+        }
+    }
+
+    fn keys(batches: &[Vec<KvEntry>]) -> Vec<Vec<&str>> {
+        batches
+            .iter()
+            .map(|batch| batch.iter().map(|entry| entry.key.as_str()).collect())
+            .collect()
+    }
+
+    #[test]
+    fn import_batches_stay_within_the_limit() {
+        let batches = import_batches(vec![
+            entry("a", IMPORT_BATCH_BYTES - 100),
+            entry("b", 100),
+            entry("c", 101),
+            entry("d", IMPORT_BATCH_BYTES),
+        ]);
+        assert_eq!(keys(&batches), [vec!["a", "b"], vec!["c"], vec!["d"]]);
+    }
+
+    #[test]
+    fn empty_imports_still_make_a_call() {
+        assert_eq!(import_batches(Vec::new()).len(), 1);
+    }
+}
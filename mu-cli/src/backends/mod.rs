@@ -1,44 +1,177 @@
-use std::{fs, path::Path};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs,
+    path::Path,
+    sync::{LazyLock, Mutex},
+};
 
 use rust_embed::Embed;
 use serde_json::Value;
 
+use crate::{
+    error::{MuCliError, Result},
+    project::{
+        config::{MuNetwork, LOCAL_NETWORK},
+        MuFunction, MuFunctionType,
+    },
+};
+
 pub mod icp;
 pub mod js;
+pub mod kv;
+pub mod solana;
+
+/// A backend that knows how to scaffold, build and ship one kind of function.
+///
+/// Backends borrow the [`MuFunction`] they operate on so they can record
+/// whatever they produce (ids, interfaces, bindings) in its state, which
+/// each backend reads and writes as its own type.
+pub trait MuFunctionBackend {
+    /// Scaffold the function's sources in its root directory.
+    fn init(&mut self) -> Result<()>;
+
+    /// Compile the function and refresh its generated artifacts.
+    fn build(&mut self) -> Result<()>;
+
+    /// Deploy the last build to `network`.
+    fn deploy(&mut self, network: &MuNetwork) -> Result<()>;
+
+    /// Rebuild and redeploy the function to the local network during `mu dev`.
+    fn dev(&mut self) -> Result<()> {
+        self.build()?;
+        self.deploy(&MuNetwork::local())
+    }
+
+    /// A short, human readable summary of the function's deployment.
+    fn status(&self) -> String;
+
+    /// The ids the function is deployed as, keyed by network name.
+    fn deployments(&self) -> BTreeMap<String, String>;
+
+    /// Remove the deployed function from `network`.
+    fn remove(&mut self, network: &MuNetwork) -> Result<()>;
+
+    /// Update the function's sources and artifacts after it was renamed from
+    /// `old_name`. The root directory has already been moved.
+    fn renamed(&mut self, _old_name: &str) -> Result<()> {
+        Ok(())
+    }
+}
+
+pub type MuFunctionBackendFactory =
+    for<'a> fn(root: &str, function: &'a mut MuFunction) -> Result<Box<dyn MuFunctionBackend + 'a>>;
+
+static FUNCTION_BACKENDS: LazyLock<Mutex<HashMap<String, MuFunctionBackendFactory>>> =
+    LazyLock::new(Default::default);
+
+/// Registers (or replaces) the backend used for functions of type `fn_type`.
+pub fn register_function_backend(fn_type: &str, factory: MuFunctionBackendFactory) {
+    FUNCTION_BACKENDS
+        .lock()
+        .unwrap()
+        .insert(fn_type.to_owned(), factory);
+}
+
+/// Registers the backends `mu` comes with.
+pub fn register_builtin_backends() {
+    register_function_backend(icp::FUNCTION_TYPE, icp::create_backend);
+    register_function_backend(solana::FUNCTION_TYPE, solana::create_backend);
+}
+
+/// The factory of the backend registered for functions of type `fn_type`.
+pub fn find_function_backend(fn_type: &MuFunctionType) -> Result<MuFunctionBackendFactory> {
+    let backends = FUNCTION_BACKENDS.lock().unwrap();
+    backends.get(fn_type.as_str()).copied().ok_or_else(|| {
+        let mut known = backends.keys().cloned().collect::<Vec<_>>();
+        known.sort();
+        MuCliError::Backend(format!(
+            "No backend registered for `{}` functions, only for {}",
+            fn_type,
+            known.join(", ")
+        ))
+    })
+}
+
+pub fn get_function_backend<'a>(
+    root: &str,
+    function: &'a mut MuFunction,
+) -> Result<Box<dyn MuFunctionBackend + 'a>> {
+    find_function_backend(&function.state.backend_state.fn_type)?(root, function)
+}
+
+/// Adds an id recorded before ids were kept per network, which was always
+/// for the local network, to `ids`.
+pub fn with_local_id(
+    mut ids: BTreeMap<String, String>,
+    legacy_id: Option<String>,
+) -> BTreeMap<String, String> {
+    if let Some(id) = legacy_id {
+        ids.entry(LOCAL_NETWORK.to_owned()).or_insert(id);
+    }
+    ids
+}
+
+/// Renames the Cargo package in `{root}/Cargo.toml` from `old_name` to
+/// `new_name`.
+pub fn rename_package(root: &str, old_name: &str, new_name: &str) -> Result<()> {
+    let path = format!("{}/Cargo.toml", root);
+    let manifest = fs::read_to_string(&path)
+        .map_err(|e| MuCliError::io(format!("Failed to read {}", path), e))?;
+    let manifest = manifest.replacen(
+        &format!("name = \"{}\"", old_name),
+        &format!("name = \"{}\"", new_name),
+        1,
+    );
+    fs::write(&path, manifest).map_err(|e| MuCliError::io(format!("Failed to write {}", path), e))
+}
+
+/// Renames `from` to `to` if it exists.
+pub fn rename_if_exists(from: &str, to: &str) -> Result<()> {
+    if fs::metadata(from).is_err() {
+        return Ok(());
+    }
+    fs::rename(from, to)
+        .map_err(|e| MuCliError::io(format!("Failed to rename {} to {}", from, to), e))
+}
 
 #[derive(Embed)]
 #[folder = "src/backends/templates"]
 struct Templates;
 
-pub fn render_template(template_name: &str, destination: &str, data: Value) {
+pub fn render_template(template_name: &str, destination: &str, data: Value) -> Result<()> {
     let handlebars = handlebars::Handlebars::new();
     let templates = Templates::iter()
         .map(|x| Path::new(&*x).to_owned())
         .filter(|x| x.starts_with(template_name));
 
     for path in templates {
-        let relative_path = path.strip_prefix(&template_name).unwrap().to_str().unwrap();
+        let relative_path = path.strip_prefix(template_name).unwrap().to_str().unwrap();
         let template_data = data.get(relative_path);
 
         // Apply the template to the destination path
         let destination_path = format!("{}/{}", destination, relative_path);
         let destination_path = handlebars
             .render_template(&destination_path, &template_data)
-            .unwrap();
+            .map_err(|e| MuCliError::Template(e.to_string()))?;
 
         // Render file
         let template_raw = Templates::get(path.to_str().unwrap()).unwrap().data;
         let template = String::from_utf8_lossy(&template_raw);
 
         let contents = match template_data {
-            Some(data) => handlebars.render_template(&template, data).unwrap(),
+            Some(data) => handlebars
+                .render_template(&template, data)
+                .map_err(|e| MuCliError::Template(format!("{}: {}", relative_path, e)))?,
             None => template.to_string(),
         };
 
         if let Some(parent) = Path::new(&destination_path).parent() {
-            fs::create_dir_all(parent).unwrap();
+            fs::create_dir_all(parent)
+                .map_err(|e| MuCliError::io(format!("Failed to create {}", parent.display()), e))?;
         }
 
-        fs::write(destination_path, contents).unwrap();
+        fs::write(&destination_path, contents)
+            .map_err(|e| MuCliError::io(format!("Failed to write {}", destination_path), e))?;
     }
+    Ok(())
 }
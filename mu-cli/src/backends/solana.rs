@@ -0,0 +1,398 @@
+use crate::{
+    backends::{
+        rename_if_exists, rename_package, render_template, with_local_id, MuFunctionBackend,
+    },
+    error::{MuCliError, Result},
+    project::{
+        config::{read_if_exists, MuNetwork},
+        MuFunction,
+    },
+    util::{command_output, print_full_line, run_command},
+};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::{
+    collections::BTreeMap,
+    fs,
+    net::TcpStream,
+    process::{Child, Command, Stdio},
+    sync::{LazyLock, Mutex},
+    thread::sleep,
+    time::Duration,
+};
+
+/// The type of functions deployed as Solana programs.
+pub const FUNCTION_TYPE: &str = "solana";
+
+static LOCAL_VALIDATOR_URL: &str = "http://localhost:8899";
+
+/// The program's keypair, which determines its program id. It is kept
+/// outside `target/` so cleaning the build does not change the id.
+static KEYPAIR_PATH: &str = "program-keypair.json";
+
+static VALIDATOR_PROCESS: LazyLock<Mutex<Option<Child>>> = LazyLock::new(|| Mutex::new(None));
+
+pub fn create_backend<'a>(
+    root: &str,
+    function: &'a mut MuFunction,
+) -> Result<Box<dyn MuFunctionBackend + 'a>> {
+    Ok(Box::new(SolanaFunction::new(root, function)?))
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(from = "MuSolanaFunctionStateFile")]
+pub struct MuSolanaFunctionState {
+    pub idl: Option<String>,
+    /// Program ids keyed by network name
+    pub program_ids: BTreeMap<String, String>,
+}
+
+/// [`MuSolanaFunctionState`] as stored, including the single `program_id`
+/// of the local network older versions of `mu` recorded.
+#[derive(Deserialize)]
+struct MuSolanaFunctionStateFile {
+    idl: Option<String>,
+    #[serde(default)]
+    program_ids: BTreeMap<String, String>,
+    program_id: Option<String>,
+}
+
+impl From<MuSolanaFunctionStateFile> for MuSolanaFunctionState {
+    fn from(file: MuSolanaFunctionStateFile) -> Self {
+        Self {
+            idl: file.idl,
+            program_ids: with_local_id(file.program_ids, file.program_id),
+        }
+    }
+}
+
+pub struct SolanaFunction<'a> {
+    function: &'a mut MuFunction,
+    state: MuSolanaFunctionState,
+    root: String,
+}
+
+impl<'a> SolanaFunction<'a> {
+    pub fn new(root: &str, function: &'a mut MuFunction) -> Result<Self> {
+        Ok(Self {
+            state: function.state.backend_state.get()?,
+            function,
+            root: root.to_string(),
+        })
+    }
+
+    /// Changes the function's state and records it.
+    fn update_state(&mut self, update: impl FnOnce(&mut MuSolanaFunctionState)) {
+        update(&mut self.state);
+        self.function.state.backend_state.set(&self.state);
+    }
+
+    fn lib_name(&self) -> String {
+        self.function.config.name.replace("-", "_")
+    }
+
+    /// Where older versions of `mu` kept the program keypair.
+    fn legacy_keypair_path(lib_name: &str) -> String {
+        format!("target/deploy/{}-keypair.json", lib_name)
+    }
+
+    /// Moves a keypair from where older versions of `mu` kept it, named
+    /// after the program's library `lib_name`.
+    fn migrate_keypair(&self, lib_name: &str) -> Result<()> {
+        self.ignore_keypair()?;
+        if fs::metadata(format!("{}/{}", self.root, KEYPAIR_PATH)).is_ok() {
+            return Ok(());
+        }
+        rename_if_exists(
+            &format!("{}/{}", self.root, Self::legacy_keypair_path(lib_name)),
+            &format!("{}/{}", self.root, KEYPAIR_PATH),
+        )
+    }
+
+    /// Adds the keypair to the function's `.gitignore`, which functions
+    /// created by older versions of `mu` lack, so it is not committed.
+    fn ignore_keypair(&self) -> Result<()> {
+        let path = format!("{}/.gitignore", self.root);
+        let ignored = read_if_exists(&path)?.unwrap_or_default();
+        let entry = format!("/{}", KEYPAIR_PATH);
+        if ignored.lines().any(|line| line.trim() == entry) {
+            return Ok(());
+        }
+
+        let separator = if ignored.is_empty() || ignored.ends_with('\n') {
+            ""
+        } else {
+            "\n"
+        };
+        fs::write(&path, format!("{}{}{}\n", ignored, separator, entry))
+            .map_err(|e| MuCliError::io(format!("Failed to write {}", path), e))
+    }
+
+    fn program_path(&self) -> String {
+        format!("target/deploy/{}.so", self.lib_name())
+    }
+
+    /// Generates the program keypair, unless the function already has one.
+    fn generate_keypair(&self) -> Result<()> {
+        self.migrate_keypair(&self.lib_name())?;
+        if fs::metadata(format!("{}/{}", self.root, KEYPAIR_PATH)).is_ok() {
+            return Ok(());
+        }
+
+        run_command(
+            Command::new("solana-keygen")
+                .arg("new")
+                .arg("--no-bip39-passphrase")
+                .arg("--silent")
+                .arg("--outfile")
+                .arg(KEYPAIR_PATH)
+                .current_dir(&self.root),
+            "generate program keypair",
+        )
+    }
+
+    fn read_program_id(&self) -> Result<String> {
+        let address = command_output(
+            Command::new("solana")
+                .arg("address")
+                .arg("--keypair")
+                .arg(KEYPAIR_PATH)
+                .current_dir(&self.root),
+            "read program id",
+        )?;
+
+        Ok(String::from_utf8_lossy(&address).trim().to_string())
+    }
+
+    fn rpc_url(network: &MuNetwork) -> Result<String> {
+        match &network.config.solana_url {
+            Some(url) => Ok(url.clone()),
+            None if network.is_local() => Ok(LOCAL_VALIDATOR_URL.to_string()),
+            None => Err(MuCliError::Backend(format!(
+                "Network `{}` has no `solana_url` configured in mu.toml",
+                network.name
+            ))),
+        }
+    }
+
+    fn start() -> Result<()> {
+        if VALIDATOR_PROCESS.lock().unwrap().is_some() {
+            return Ok(());
+        }
+
+        print_full_line("Starting local Solana validator...");
+        let mut command = Command::new("solana-test-validator");
+        command
+            .arg("--quiet")
+            .arg("--ledger")
+            .arg(".solana/test-ledger")
+            .current_dir(".")
+            .stdout(Stdio::null());
+        let validator = command
+            .spawn()
+            .map_err(|e| MuCliError::spawn(&command, e))?;
+
+        *VALIDATOR_PROCESS.lock().unwrap() = Some(validator);
+
+        loop {
+            let conn = TcpStream::connect("localhost:8899");
+            if conn.is_ok() {
+                break;
+            }
+            sleep(Duration::from_secs(1));
+        }
+        Ok(())
+    }
+}
+
+/// Replaces the first occurrence of `from` in the file at `path` with `to`.
+fn replace_in_file(path: &str, from: &str, to: &str) -> Result<()> {
+    let contents = fs::read_to_string(path)
+        .map_err(|e| MuCliError::io(format!("Failed to read {}", path), e))?;
+    fs::write(path, contents.replacen(from, to, 1))
+        .map_err(|e| MuCliError::io(format!("Failed to write {}", path), e))
+}
+
+impl MuFunctionBackend for SolanaFunction<'_> {
+    fn init(&mut self) -> Result<()> {
+        print_full_line("Generating program keypair");
+        self.generate_keypair()?;
+        let program_id = self.read_program_id()?;
+
+        let data = json!({
+            "Cargo.toml": {
+                "name": self.function.config.name,
+            },
+            "Anchor.toml": {
+                "lib_name": self.lib_name(),
+                "program_id": program_id,
+            },
+            "src/lib.rs": {
+                "lib_name": self.lib_name(),
+                "program_id": program_id,
+            }
+        });
+
+        render_template("solana/function", &self.root, data)
+    }
+
+    fn build(&mut self) -> Result<()> {
+        print_full_line("Building Solana program");
+        run_command(
+            Command::new("cargo")
+                .arg("build-sbf")
+                .current_dir(&self.root),
+            &format!("build Solana function `{}`", self.function.config.name),
+        )?;
+
+        print_full_line("Extracting IDL");
+        let idl = command_output(
+            Command::new("anchor")
+                .arg("idl")
+                .arg("build")
+                .current_dir(&self.root),
+            "extract IDL",
+        )?;
+
+        // Store IDL in the state and in a file
+        self.update_state(|state| state.idl = Some(String::from_utf8_lossy(&idl).into()));
+
+        let idl_path = format!("{}/{}.json", self.root, self.function.config.name);
+        fs::write(&idl_path, &idl)
+            .map_err(|e| MuCliError::io(format!("Failed to write {}", idl_path), e))
+    }
+
+    fn deploy(&mut self, network: &MuNetwork) -> Result<()> {
+        let url = Self::rpc_url(network)?;
+        if network.is_local() {
+            Self::start()?;
+        }
+        self.migrate_keypair(&self.lib_name())?;
+
+        print_full_line("Deploying Solana program...");
+        run_command(
+            Command::new("solana")
+                .arg("program")
+                .arg("deploy")
+                .arg("--url")
+                .arg(&url)
+                .arg("--program-id")
+                .arg(KEYPAIR_PATH)
+                .arg(self.program_path())
+                .current_dir(&self.root),
+            &format!(
+                "deploy Solana function `{}` to network `{}`",
+                self.function.config.name, network.name
+            ),
+        )?;
+
+        let program_id = self.read_program_id()?;
+        self.update_state(|state| {
+            state.program_ids.insert(network.name.clone(), program_id);
+        });
+        Ok(())
+    }
+
+    fn status(&self) -> String {
+        let state = &self.state;
+        if state.program_ids.is_empty() {
+            return match state.idl {
+                Some(_) => "built, not deployed".to_string(),
+                None => "not built".to_string(),
+            };
+        }
+
+        let deployments = state
+            .program_ids
+            .iter()
+            .map(|(network, program_id)| format!("{}: {}", network, program_id))
+            .collect::<Vec<_>>();
+        format!("deployed as programs {}", deployments.join(", "))
+    }
+
+    fn deployments(&self) -> BTreeMap<String, String> {
+        self.state.program_ids.clone()
+    }
+
+    fn remove(&mut self, network: &MuNetwork) -> Result<()> {
+        let Some(program_id) = self.state.program_ids.get(&network.name).cloned() else {
+            return Ok(());
+        };
+
+        let url = Self::rpc_url(network)?;
+        if network.is_local() {
+            Self::start()?;
+        }
+
+        print_full_line("Closing Solana program...");
+        run_command(
+            Command::new("solana")
+                .arg("program")
+                .arg("close")
+                .arg("--url")
+                .arg(&url)
+                .arg("--bypass-warning")
+                .arg(&program_id)
+                .current_dir(&self.root),
+            &format!("close Solana program `{}`", program_id),
+        )?;
+
+        self.update_state(|state| {
+            state.program_ids.remove(&network.name);
+        });
+        Ok(())
+    }
+
+    fn renamed(&mut self, old_name: &str) -> Result<()> {
+        let name = &self.function.config.name;
+        let old_lib_name = old_name.replace("-", "_");
+        rename_package(&self.root, old_name, name)?;
+        rename_if_exists(
+            &format!("{}/{}.json", self.root, old_name),
+            &format!("{}/{}.json", self.root, name),
+        )?;
+
+        // A keypair kept where older versions put it is named after the
+        // old library, so it has to move before the name is forgotten
+        self.migrate_keypair(&old_lib_name)?;
+
+        let lib_name = self.lib_name();
+        replace_in_file(
+            &format!("{}/Anchor.toml", self.root),
+            &format!("{} = ", old_lib_name),
+            &format!("{} = ", lib_name),
+        )?;
+        replace_in_file(
+            &format!("{}/src/lib.rs", self.root),
+            &format!("pub mod {} {{", old_lib_name),
+            &format!("pub mod {} {{", lib_name),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::project::state::MuProjectState;
+
+    #[test]
+    fn loads_ids_recorded_before_networks() {
+        let state: MuProjectState = serde_json::from_str(
+            r#"{"functions":[
+                {"name":"counter","backend_state":{"type":"solana","idl":null,
+                    "program_id":"Fg6PaFpoGXkYsidMpWTK6W2BeZ7FEfcYkg476zPFsLnS"}}
+            ]}"#,
+        )
+        .unwrap();
+
+        let counter: MuSolanaFunctionState =
+            state.functions["counter"].backend_state.get().unwrap();
+        assert_eq!(
+            counter.program_ids,
+            BTreeMap::from([(
+                "local".to_owned(),
+                "Fg6PaFpoGXkYsidMpWTK6W2BeZ7FEfcYkg476zPFsLnS".to_owned()
+            )])
+        );
+    }
+}
@@ -0,0 +1,183 @@
+use std::{fmt::Display, io, process::Command};
+
+pub type Result<T> = std::result::Result<T, MuCliError>;
+
+#[derive(Debug)]
+pub enum MuCliError {
+    /// There is no `mu.toml` in the current directory.
+    ProjectNotFound,
+
+    /// An external tool the CLI shells out to is not installed.
+    ToolNotFound {
+        tool: String,
+    },
+
+    /// An external tool ran but exited unsuccessfully.
+    CommandFailed {
+        action: String,
+        code: Option<i32>,
+    },
+
+    /// `--network` names a network that is not declared in `mu.toml`.
+    UnknownNetwork(String),
+
+    /// A command names a function that is not declared in `mu.toml`.
+    UnknownFunction(String),
+
+    /// A function with this name is already declared in `mu.toml`.
+    FunctionExists(String),
+
+    /// A command names a frontend that is not declared in `mu.toml`.
+    UnknownFrontend(String),
+
+    /// A frontend with this name is already declared in `mu.toml`.
+    FrontendExists(String),
+
+    /// A project file could not be parsed.
+    Parse {
+        file: String,
+        line: usize,
+        column: usize,
+        message: String,
+    },
+
+    Io {
+        context: String,
+        source: io::Error,
+    },
+
+    Template(String),
+
+    Watch(notify::Error),
+
+    /// A backend could not complete an operation for a function.
+    Backend(String),
+}
+
+impl MuCliError {
+    pub fn io(context: impl Into<String>, source: io::Error) -> Self {
+        MuCliError::Io {
+            context: context.into(),
+            source,
+        }
+    }
+
+    /// Maps a failure to start `command` to the appropriate error.
+    pub fn spawn(command: &Command, source: io::Error) -> Self {
+        let tool = command.get_program().to_string_lossy().to_string();
+        if source.kind() == io::ErrorKind::NotFound {
+            MuCliError::ToolNotFound { tool }
+        } else {
+            MuCliError::io(format!("Failed to run `{}`", tool), source)
+        }
+    }
+
+    pub fn parse_toml(file: &str, contents: &str, error: toml::de::Error) -> Self {
+        let offset = error.span().map(|span| span.start).unwrap_or_default();
+        let before = &contents[..offset.min(contents.len())];
+        let line = before.matches('\n').count() + 1;
+        let column = before.len() - before.rfind('\n').map(|i| i + 1).unwrap_or(0) + 1;
+
+        MuCliError::Parse {
+            file: file.to_owned(),
+            line,
+            column,
+            message: error.message().to_owned(),
+        }
+    }
+
+    pub fn parse_json(file: &str, error: serde_json::Error) -> Self {
+        MuCliError::Parse {
+            file: file.to_owned(),
+            line: error.line(),
+            column: error.column(),
+            message: error.to_string(),
+        }
+    }
+
+    /// The process exit code `mu` terminates with for this error.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            MuCliError::ProjectNotFound
+            | MuCliError::UnknownNetwork(_)
+            | MuCliError::UnknownFunction(_)
+            | MuCliError::FunctionExists(_)
+            | MuCliError::UnknownFrontend(_)
+            | MuCliError::FrontendExists(_)
+            | MuCliError::Parse { .. } => 2,
+            MuCliError::ToolNotFound { .. } => 127,
+            MuCliError::CommandFailed {
+                code: Some(code), ..
+            } if *code != 0 => *code,
+            _ => 1,
+        }
+    }
+}
+
+fn install_hint(tool: &str) -> Option<&'static str> {
+    match tool {
+        "cargo" => Some("install Rust from https://rustup.rs"),
+        "dfx" => Some(
+            "install it with `sh -ci \"$(curl -fsSL https://internetcomputer.org/install.sh)\"`",
+        ),
+        "candid-extractor" => Some("run `cargo install candid-extractor`"),
+        "solana" | "solana-keygen" | "solana-test-validator" => {
+            Some("install the Solana CLI from https://docs.solanalabs.com/cli/install")
+        }
+        "anchor" => Some("install Anchor from https://www.anchor-lang.com/docs/installation"),
+        "npm" => Some("install Node.js from https://nodejs.org"),
+        _ => None,
+    }
+}
+
+impl Display for MuCliError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MuCliError::ProjectNotFound => write!(f, "No project found. Run `mu init` first."),
+            MuCliError::ToolNotFound { tool } => {
+                write!(f, "`{}` was not found in your PATH", tool)?;
+                if let Some(hint) = install_hint(tool) {
+                    write!(f, "; {}", hint)?;
+                }
+                Ok(())
+            }
+            MuCliError::UnknownNetwork(name) => write!(
+                f,
+                "Unknown network `{}`. Declare it in a `[networks.{}]` section of mu.toml.",
+                name, name
+            ),
+            MuCliError::UnknownFunction(name) => write!(
+                f,
+                "Unknown function `{}`. Run `mu function list` to see the project's functions.",
+                name
+            ),
+            MuCliError::FunctionExists(name) => {
+                write!(f, "The project already has a function named `{}`", name)
+            }
+            MuCliError::UnknownFrontend(name) => write!(
+                f,
+                "Unknown frontend `{}`. Run `mu frontend list` to see the project's frontends.",
+                name
+            ),
+            MuCliError::FrontendExists(name) => {
+                write!(f, "The project already has a frontend named `{}`", name)
+            }
+            MuCliError::CommandFailed { action, code } => match code {
+                Some(code) => write!(f, "Failed to {} (exit code {})", action, code),
+                None => write!(f, "Failed to {} (terminated by signal)", action),
+            },
+            MuCliError::Parse {
+                file,
+                line,
+                column,
+                message,
+            } => write!(f, "{}:{}:{}: {}", file, line, column, message),
+            MuCliError::Io { context, source } => write!(f, "{}: {}", context, source),
+            MuCliError::Template(message) => write!(f, "Failed to render template: {}", message),
+            MuCliError::Watch(error) => write!(f, "Failed to watch for changes: {}", error),
+            MuCliError::Backend(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl std::error::Error for MuCliError {}
@@ -1,8 +1,18 @@
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    io::{self, BufReader},
+};
+
+use backends::kv::{KvClient, KvStats};
 use clap::{Parser, Subcommand};
+use error::{MuCliError, Result};
 use project::{MuFrontendTemplate, MuFunctionType, MuProject};
-use util::print_full_line;
+use serde_json::json;
+use util::{print_error, print_full_line, print_table, OutputFormat};
 
 mod backends;
+mod error;
 mod project;
 pub mod util;
 
@@ -34,10 +44,41 @@ enum Commands {
     Build,
 
     /// Deploy the project
-    Deploy,
+    Deploy {
+        /// The network to deploy to, as declared in mu.toml
+        #[arg(long, default_value = "local")]
+        network: String,
+    },
 
     /// Run the project in development mode
     Dev,
+
+    /// Show the status of the project's functions
+    Status,
+
+    /// Work with the project's state in mu.state.json
+    State {
+        #[command(subcommand)]
+        command: State,
+    },
+
+    /// Work with the data in the KV service
+    Kv {
+        /// The network whose KV service to use, as declared in mu.toml
+        #[arg(long, default_value = "local", global = true)]
+        network: String,
+
+        /// The function or principal owning the collections, if not the dfx identity
+        #[arg(long, global = true)]
+        owner: Option<String>,
+
+        /// How to print the data
+        #[arg(long, value_enum, default_value = "table", global = true)]
+        output: OutputFormat,
+
+        #[command(subcommand)]
+        command: Kv,
+    },
 }
 
 #[derive(Subcommand)]
@@ -45,9 +86,25 @@ enum Function {
     /// Adds a new function
     Add {
         name: String,
+        /// The type of function, such as icp or solana
         #[arg(id = "TYPE")]
         fn_type: MuFunctionType,
     },
+
+    /// Lists the functions
+    List,
+
+    /// Removes a function and its sources
+    Remove {
+        name: String,
+
+        /// Also remove the function from every network it is deployed to
+        #[arg(long)]
+        uninstall: bool,
+    },
+
+    /// Renames a function, keeping its deployments
+    Rename { name: String, new_name: String },
 }
 
 #[derive(Subcommand)]
@@ -58,26 +115,210 @@ enum Frontend {
         #[arg(id = "TYPE")]
         template: MuFrontendTemplate,
     },
+
+    /// Lists the frontends
+    List,
+
+    /// Removes a frontend and its sources
+    Remove { name: String },
+}
+
+#[derive(Subcommand)]
+enum State {
+    /// Makes mu.state.json match the functions declared in mu.toml
+    Repair,
+}
+
+#[derive(Subcommand)]
+enum Kv {
+    /// Lists the collections
+    List,
+
+    /// Shows the value of a key
+    Get { collection: String, key: String },
+
+    /// Sets the value of a key
+    Set {
+        collection: String,
+        key: String,
+        value: String,
+        /// Remove the value after this many seconds
+        #[arg(long)]
+        ttl: Option<u64>,
+    },
+
+    /// Removes a key
+    Del { collection: String, key: String },
+
+    /// Lists the keys of a collection
+    Keys {
+        collection: String,
+        /// Only list keys starting with this prefix
+        #[arg(long, default_value = "")]
+        prefix: String,
+    },
+
+    /// Shows what the KV service stores and the resources it uses
+    Stats,
+
+    /// Writes a collection to stdout as JSON lines
+    Export { collection: String },
+
+    /// Imports a collection written by `mu kv export`
+    Import {
+        collection: String,
+        /// The file to read, instead of stdin
+        file: Option<String>,
+    },
 }
 
-fn get_project() -> MuProject {
-    let project = MuProject::load();
-    if project.is_none() {
-        eprintln!("No project found. Run `mu init` first.");
-        std::process::exit(1);
+fn run_kv(kv: KvClient, command: Kv, output: OutputFormat) -> Result<()> {
+    match command {
+        Kv::List => {
+            let names = kv.list()?;
+            match output {
+                OutputFormat::Table => print_table(
+                    &["COLLECTION"],
+                    &names.into_iter().map(|name| vec![name]).collect::<Vec<_>>(),
+                ),
+                OutputFormat::Json => println!("{}", json!(names)),
+            }
+        }
+        Kv::Get { collection, key } => {
+            let (value, version) = kv.get(&collection, &key)?.ok_or_else(|| {
+                MuCliError::Backend(format!(
+                    "Key `{}` not found in collection `{}`",
+                    key, collection
+                ))
+            })?;
+            match output {
+                OutputFormat::Table => print_table(
+                    &["KEY", "VERSION", "VALUE"],
+                    &[vec![key, version.to_string(), value]],
+                ),
+                OutputFormat::Json => println!(
+                    "{}",
+                    json!({ "key": key, "version": version, "value": value })
+                ),
+            }
+        }
+        Kv::Set {
+            collection,
+            key,
+            value,
+            ttl,
+        } => kv.set(&collection, &key, Some(&value), ttl)?,
+        Kv::Del { collection, key } => kv.set(&collection, &key, None, None)?,
+        Kv::Keys { collection, prefix } => {
+            let keys = kv.keys(&collection, &prefix)?;
+            match output {
+                OutputFormat::Table => print_table(
+                    &["KEY"],
+                    &keys.into_iter().map(|key| vec![key]).collect::<Vec<_>>(),
+                ),
+                OutputFormat::Json => println!("{}", json!(keys)),
+            }
+        }
+        Kv::Stats => {
+            let stats = kv.stats()?;
+            match output {
+                OutputFormat::Table => print_kv_stats(stats),
+                OutputFormat::Json => println!("{}", json!(stats)),
+            }
+        }
+        Kv::Export { collection } => {
+            kv.export(&collection, io::stdout().lock())?;
+        }
+        Kv::Import { collection, file } => {
+            let imported = match file {
+                Some(file) => {
+                    let input = File::open(&file)
+                        .map_err(|e| MuCliError::io(format!("Failed to open {}", file), e))?;
+                    kv.import(&collection, &file, BufReader::new(input))?
+                }
+                None => kv.import(&collection, "stdin", io::stdin().lock())?,
+            };
+            print_full_line(&format!(
+                "Imported {} entries into collection `{}`",
+                imported, collection
+            ));
+        }
     }
-    project.unwrap()
+    Ok(())
 }
 
-fn main() {
-    let cli = MuCli::parse();
+fn print_kv_stats(stats: KvStats) {
+    print_table(
+        &["TOTAL", ""],
+        &[
+            ("principals", stats.principal_count.to_string()),
+            ("collections", stats.collections.to_string()),
+            ("entries", stats.usage.entries.to_string()),
+            ("bytes", stats.usage.bytes.to_string()),
+            ("heap memory", stats.heap_memory_bytes.to_string()),
+            ("stable memory", stats.stable_memory_bytes.to_string()),
+            ("cycles", stats.cycles.to_string()),
+        ]
+        .map(|(name, value)| vec![name.to_string(), value]),
+    );
 
-    print_full_line("Welcome to Mu [μ]!");
+    println!();
+    print_table(
+        &["PRINCIPAL", "COLLECTIONS", "ENTRIES", "BYTES"],
+        &stats
+            .principals
+            .iter()
+            .map(|principal| {
+                vec![
+                    principal.principal.to_text(),
+                    principal.collections.to_string(),
+                    principal.usage.entries.to_string(),
+                    principal.usage.bytes.to_string(),
+                ]
+            })
+            .collect::<Vec<_>>(),
+    );
 
+    // Summarize the recent calls by method
+    let mut methods = BTreeMap::<&str, (u64, u64, u64)>::new();
+    for call in &stats.recent_calls {
+        let (calls, total, max) = methods.entry(&call.method).or_default();
+        *calls += 1;
+        *total += call.instructions;
+        *max = (*max).max(call.instructions);
+    }
+    println!();
+    print_table(
+        &[
+            "METHOD",
+            "RECENT CALLS",
+            "AVG INSTRUCTIONS",
+            "MAX INSTRUCTIONS",
+        ],
+        &methods
+            .into_iter()
+            .map(|(method, (calls, total, max))| {
+                vec![
+                    method.to_string(),
+                    calls.to_string(),
+                    (total / calls).to_string(),
+                    max.to_string(),
+                ]
+            })
+            .collect::<Vec<_>>(),
+    );
+}
+
+fn run(cli: MuCli) -> Result<()> {
     match cli.command {
         Commands::Function { command } => match command {
-            Function::Add { name, fn_type } => {
-                get_project().add_function(&name, fn_type);
+            Function::Add { name, fn_type } => MuProject::load()?.add_function(&name, fn_type),
+            Function::List => MuProject::load()?.list_functions(),
+            Function::Remove { name, uninstall } => {
+                MuProject::load()?.remove_function(&name, uninstall)
+            }
+            Function::Rename { name, new_name } => {
+                MuProject::load()?.rename_function(&name, &new_name)
             }
         },
         Commands::Init { name } => {
@@ -85,30 +326,55 @@ fn main() {
                 name
             } else {
                 std::env::current_dir()
-                    .unwrap()
+                    .map_err(|e| MuCliError::io("Failed to read the current directory", e))?
                     .file_name()
-                    .unwrap()
-                    .to_str()
-                    .unwrap()
+                    .and_then(|name| name.to_str())
+                    .unwrap_or("mu-project")
                     .to_string()
             };
 
-            MuProject::init(name);
+            MuProject::init(name)
         }
-        
+
         Commands::Frontend { command } => match command {
-            Frontend::Add { name, template } => {
-                get_project().add_frontend(&name, template);
+            Frontend::Add { name, template } => MuProject::load()?.add_frontend(&name, template),
+            Frontend::List => {
+                MuProject::load()?.list_frontends();
+                Ok(())
             }
+            Frontend::Remove { name } => MuProject::load()?.remove_frontend(&name),
         },
-        Commands::Build => {
-            get_project().build();
-        }
-        Commands::Deploy => {
-            get_project().deploy();
-        }
-        Commands::Dev => {
-            get_project().dev();
-        }
+        Commands::Build => MuProject::load()?.build(),
+        Commands::Deploy { network } => MuProject::load()?.deploy(&network),
+        Commands::Dev => MuProject::load()?.dev(),
+        Commands::Status => MuProject::load()?.status(),
+        Commands::State { command } => match command {
+            State::Repair => MuProject::repair_state(),
+        },
+        Commands::Kv {
+            network,
+            owner,
+            output,
+            command,
+        } => run_kv(
+            MuProject::load()?.kv(&network, owner.as_deref())?,
+            command,
+            output,
+        ),
+    }
+}
+
+fn main() {
+    backends::register_builtin_backends();
+    let cli = MuCli::parse();
+
+    // KV commands write data to stdout, which the banner would get mixed into
+    if !matches!(cli.command, Commands::Kv { .. }) {
+        print_full_line("Welcome to Mu [μ]!");
+    }
+
+    if let Err(e) = run(cli) {
+        print_error(&e);
+        std::process::exit(e.exit_code());
     }
 }
@@ -1,6 +1,14 @@
+use std::collections::BTreeMap;
+
 use serde::{Deserialize, Serialize};
 
 use super::{MuFrontendTemplate, MuFunctionType};
+use crate::error::{MuCliError, Result};
+
+static CONFIG_FILENAME: &str = "mu.toml";
+
+/// The network `mu dev` runs against and `mu deploy` targets by default.
+pub static LOCAL_NETWORK: &str = "local";
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct MuProjectConfig {
@@ -10,22 +18,38 @@ pub struct MuProjectConfig {
     #[serde(skip_serializing_if = "Vec::is_empty", default)]
     pub frontends: Vec<MuFrontendConfig>,
 
+    #[serde(skip_serializing_if = "BTreeMap::is_empty", default)]
+    pub networks: BTreeMap<String, MuNetworkConfig>,
+
+    #[serde(skip_serializing_if = "MuServicesConfig::is_empty", default)]
+    pub services: MuServicesConfig,
+
     pub metadata: MuProjectMetadata,
 }
 
 impl MuProjectConfig {
-    pub fn load() -> Option<MuProjectConfig> {
-        if let Ok(toml) = std::fs::read_to_string("mu.toml") {
-            let config = toml::from_str::<MuProjectConfig>(&toml).unwrap();
-            Some(config)
-        } else {
-            None
-        }
+    pub fn load() -> Result<Option<MuProjectConfig>> {
+        let Some(toml) = read_if_exists(CONFIG_FILENAME)? else {
+            return Ok(None);
+        };
+        let config = toml::from_str::<MuProjectConfig>(&toml)
+            .map_err(|e| MuCliError::parse_toml(CONFIG_FILENAME, &toml, e))?;
+        Ok(Some(config))
     }
 
-    pub fn save(&self) {
+    pub fn save(&self) -> Result<()> {
         let toml = toml::to_string(&self).unwrap();
-        std::fs::write("mu.toml", toml).unwrap();
+        std::fs::write(CONFIG_FILENAME, toml)
+            .map_err(|e| MuCliError::io(format!("Failed to write {}", CONFIG_FILENAME), e))
+    }
+}
+
+/// The contents of the file at `path`, or `None` if it does not exist.
+pub fn read_if_exists(path: &str) -> Result<Option<String>> {
+    match std::fs::read_to_string(path) {
+        Ok(contents) => Ok(Some(contents)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(MuCliError::io(format!("Failed to read {}", path), e)),
     }
 }
 
@@ -57,3 +81,53 @@ pub struct MuFrontendConfig {
     pub name: String,
     pub template: MuFrontendTemplate,
 }
+
+/// The `[services]` section of `mu.toml`: platform services `mu` deploys
+/// alongside the project's functions.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct MuServicesConfig {
+    /// Deploy a mu-kv canister for functions using `mu_sdk::kv`.
+    #[serde(default)]
+    pub kv: bool,
+}
+
+impl MuServicesConfig {
+    fn is_empty(&self) -> bool {
+        !self.kv
+    }
+}
+
+/// A `[networks.<name>]` section of `mu.toml`.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct MuNetworkConfig {
+    /// URL of the ICP replica or boundary node, e.g. `https://icp0.io`.
+    /// May be omitted for networks dfx already knows, such as `ic` and
+    /// `playground`.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub icp_url: Option<String>,
+
+    /// URL of the Solana RPC endpoint, e.g. `https://api.devnet.solana.com`.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub solana_url: Option<String>,
+}
+
+/// A network resolved by name from the project's configuration.
+#[derive(Debug, Clone)]
+pub struct MuNetwork {
+    pub name: String,
+    pub config: MuNetworkConfig,
+}
+
+impl MuNetwork {
+    pub fn local() -> MuNetwork {
+        MuNetwork {
+            name: LOCAL_NETWORK.to_owned(),
+            config: Default::default(),
+        }
+    }
+
+    /// Whether this network is run by `mu` itself on this machine.
+    pub fn is_local(&self) -> bool {
+        self.name == LOCAL_NETWORK
+    }
+}
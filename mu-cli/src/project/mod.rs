@@ -1,26 +1,51 @@
+use std::{collections::BTreeMap, convert::Infallible, fmt::Display, fs, str::FromStr};
+
+use candid::Principal;
 use clap::ValueEnum;
-use config::{MuFrontendConfig, MuFunctionConfig, MuProjectConfig, MuProjectMetadata};
+use config::{
+    MuFrontendConfig, MuFunctionConfig, MuNetwork, MuNetworkConfig, MuProjectConfig,
+    MuProjectMetadata, MuServicesConfig,
+};
 use futures::{future::join_all, StreamExt};
-use serde::{Deserialize, Serialize};
-use state::{MuBackendFunctionState, MuFunctionState, MuProjectState};
+use serde::{Deserialize, Deserializer, Serialize};
+use state::{MuFunctionState, MuProjectState, MuServicesState};
 use tokio::sync::mpsc::UnboundedReceiver;
 
 use crate::{
-    backends::{icp::IcpFunction, js::JsBackend},
-    util::{print_full_line, MyWatcher},
+    backends::{
+        find_function_backend, get_function_backend,
+        icp::{self, MuIcpFunctionState},
+        js::JsBackend,
+        kv::{KvClient, KvService, KV_CANISTER_ID_ENV},
+        MuFunctionBackend,
+    },
+    error::{MuCliError, Result},
+    util::{
+        format_time_ago, now, print_error, print_full_line, print_table, print_warning, MyWatcher,
+    },
 };
 
 pub mod config;
 pub mod state;
 
+/// The directory, relative to a frontend's root, its production build is
+/// written to.
+static FRONTEND_OUT_DIR: &str = "dist";
+
 pub struct MuProject {
     pub metadata: MuProjectMetadata,
     pub functions: Vec<MuFunction>,
     pub frontends: Vec<MuFrontend>,
+    pub networks: BTreeMap<String, MuNetworkConfig>,
+    pub services: MuServicesConfig,
+    pub services_state: MuServicesState,
+    /// State of functions that are not declared in `mu.toml`, kept until
+    /// `mu state repair` drops it.
+    pub orphaned_functions: BTreeMap<String, MuFunctionState>,
 }
 
 impl MuProject {
-    pub fn init(name: String) {
+    pub fn init(name: String) -> Result<()> {
         let metadata = MuProjectMetadata {
             name,
             version: "0.1.0".to_string(),
@@ -32,30 +57,68 @@ impl MuProject {
             metadata,
             functions: vec![],
             frontends: vec![],
+            networks: BTreeMap::new(),
+            services: Default::default(),
+            services_state: Default::default(),
+            orphaned_functions: BTreeMap::new(),
         };
 
-        project.save();
+        project.save()?;
 
         print_full_line("Project initialized.");
+        Ok(())
     }
 
-    pub fn save(&self) {
+    pub fn save(&self) -> Result<()> {
         let config = self.as_config();
-        config.save();
+        config.save()?;
 
         let state = self.as_state();
-        state.save();
+        state.save()
+    }
+
+    /// Loads the project, warning about state `mu state repair` would drop.
+    pub fn load() -> Result<MuProject> {
+        let project = Self::load_reconciled()?;
+        for name in project.orphaned_functions.keys() {
+            print_warning(&format!(
+                "mu.state.json has state for function `{}`, which is not in mu.toml. \
+                 Run `mu state repair` to drop it.",
+                name
+            ));
+        }
+        Ok(project)
     }
 
-    pub fn load() -> Option<MuProject> {
-        let config = MuProjectConfig::load()?;
-        let state = MuProjectState::load()?;
+    /// Loads the project, matching the functions declared in `mu.toml` with
+    /// their state by name. Functions without state, e.g. added to `mu.toml`
+    /// by hand, start with a fresh one.
+    fn load_reconciled() -> Result<MuProject> {
+        let config = MuProjectConfig::load()?.ok_or(MuCliError::ProjectNotFound)?;
+        let mut state = MuProjectState::load()?.unwrap_or_default();
 
         let functions = config
             .functions
             .into_iter()
-            .zip(state.functions.into_iter())
-            .map(|(config, state)| MuFunction { config, state })
+            .map(|config| {
+                let state = match state.functions.remove(&config.name) {
+                    Some(state) if state.backend_state.fn_type == config.fn_type => state,
+                    Some(state) => {
+                        print_warning(&format!(
+                            "mu.state.json has state of a {} function for `{}`, which mu.toml \
+                             declares as {}. Starting with a fresh state.",
+                            state.backend_state.fn_type, config.name, config.fn_type
+                        ));
+                        MuFunctionState::new(&config.name, config.fn_type.clone())
+                    }
+                    None => MuFunctionState::new(&config.name, config.fn_type.clone()),
+                };
+                MuFunction {
+                    config,
+                    state,
+                    build_env: BTreeMap::new(),
+                }
+            })
             .collect();
 
         let frontends = config
@@ -64,172 +127,479 @@ impl MuProject {
             .map(|config| MuFrontend { config })
             .collect();
 
-        Some(MuProject {
+        Ok(MuProject {
             metadata: config.metadata,
             functions,
             frontends,
+            networks: config.networks,
+            services: config.services,
+            services_state: state.services,
+            orphaned_functions: state.functions,
         })
     }
 
+    /// Rewrites `mu.state.json` to match `mu.toml`: drops the state of
+    /// functions that are no longer declared and creates missing entries.
+    pub fn repair_state() -> Result<()> {
+        let mut project = Self::load_reconciled()?;
+        print_full_line("Repairing mu.state.json");
+
+        for name in std::mem::take(&mut project.orphaned_functions).into_keys() {
+            println!("Dropped the state of function `{}`", name);
+        }
+        project.save()?;
+
+        print_full_line("mu.state.json matches mu.toml.");
+        Ok(())
+    }
+
     pub fn as_config(&self) -> MuProjectConfig {
         MuProjectConfig {
             frontends: self.frontends.iter().map(|f| f.config.clone()).collect(),
             functions: self.functions.iter().map(|f| f.config.clone()).collect(),
+            networks: self.networks.clone(),
+            services: self.services.clone(),
             metadata: self.metadata.clone(),
         }
     }
 
     pub fn as_state(&self) -> MuProjectState {
         MuProjectState {
-            functions: self.functions.iter().map(|f| f.state.clone()).collect(),
+            functions: self
+                .orphaned_functions
+                .clone()
+                .into_iter()
+                .chain(
+                    self.functions
+                        .iter()
+                        .map(|f| (f.config.name.clone(), f.state.clone())),
+                )
+                .collect(),
+            services: self.services_state.clone(),
         }
     }
 
-    pub fn add_function(&mut self, name: &str, fn_type: MuFunctionType) {
+    pub fn add_function(&mut self, name: &str, fn_type: MuFunctionType) -> Result<()> {
+        if self.functions.iter().any(|f| f.config.name == name) {
+            return Err(MuCliError::FunctionExists(name.to_owned()));
+        }
+        find_function_backend(&fn_type)?;
         print_full_line(&format!("Adding function: {}", name));
 
         let path = format!("functions/{}", name);
-        std::fs::create_dir_all(path).unwrap();
+        std::fs::create_dir_all(&path)
+            .map_err(|e| MuCliError::io(format!("Failed to create {}", path), e))?;
 
         let mut function = MuFunction::new(name, fn_type);
-        function.init();
+        function.init()?;
         self.functions.push(function);
 
-        self.save();
+        self.save()
     }
-    pub fn dev(mut self) {
+
+    fn function_index(&self, name: &str) -> Result<usize> {
+        self.functions
+            .iter()
+            .position(|f| f.config.name == name)
+            .ok_or_else(|| MuCliError::UnknownFunction(name.to_owned()))
+    }
+
+    pub fn list_functions(&mut self) -> Result<()> {
+        let rows = self
+            .functions
+            .iter_mut()
+            .map(|f| {
+                let deployments = f
+                    .deployments()?
+                    .iter()
+                    .map(|(network, id)| format!("{}: {}", network, id))
+                    .collect::<Vec<_>>();
+                Ok(vec![
+                    f.config.name.clone(),
+                    f.config.fn_type.to_string(),
+                    if deployments.is_empty() {
+                        "-".to_string()
+                    } else {
+                        deployments.join(", ")
+                    },
+                    f.state
+                        .last_build
+                        .map(format_time_ago)
+                        .unwrap_or_else(|| "never".to_string()),
+                ])
+            })
+            .collect::<Result<Vec<_>>>()?;
+        print_table(&["NAME", "TYPE", "DEPLOYED AS", "LAST BUILD"], &rows);
+        Ok(())
+    }
+
+    /// Removes a function from the project and deletes its sources. With
+    /// `uninstall`, it is first removed from every network it is deployed
+    /// to; otherwise its deployments are left running.
+    pub fn remove_function(&mut self, name: &str, uninstall: bool) -> Result<()> {
+        let idx = self.function_index(name)?;
+        print_full_line(&format!("Removing function: {}", name));
+
+        let networks = self.functions[idx]
+            .deployments()?
+            .into_keys()
+            .collect::<Vec<_>>();
+        if uninstall {
+            for network in networks {
+                let network = self.network(&network)?;
+                let result = self.functions[idx].remove(&network);
+                if result.is_err() {
+                    self.save()?;
+                    return result;
+                }
+            }
+        } else if !networks.is_empty() {
+            print_warning(&format!(
+                "`{}` stays deployed on {}. Pass --uninstall to remove it from the networks too.",
+                name,
+                networks.join(", ")
+            ));
+        }
+
+        let function = self.functions.remove(idx);
+        let root = function.get_root();
+        if fs::metadata(&root).is_ok() {
+            fs::remove_dir_all(&root)
+                .map_err(|e| MuCliError::io(format!("Failed to delete {}", root), e))?;
+        }
+
+        self.save()
+    }
+
+    /// Renames a function in the project's configuration, state and sources.
+    /// Its deployments are kept.
+    pub fn rename_function(&mut self, name: &str, new_name: &str) -> Result<()> {
+        let idx = self.function_index(name)?;
+        if self.functions.iter().any(|f| f.config.name == new_name) {
+            return Err(MuCliError::FunctionExists(new_name.to_owned()));
+        }
+        print_full_line(&format!("Renaming function {} to {}", name, new_name));
+
+        let function = &mut self.functions[idx];
+        let old_root = function.get_root();
+        function.config.name = new_name.to_owned();
+        function.state.name = new_name.to_owned();
+        let root = function.get_root();
+        fs::rename(&old_root, &root)
+            .map_err(|e| MuCliError::io(format!("Failed to rename {} to {}", old_root, root), e))?;
+
+        let result = function.renamed(name);
+        self.save()?;
+        result
+    }
+
+    pub fn dev(mut self) -> Result<()> {
         let rt = tokio::runtime::Builder::new_current_thread()
             .enable_all()
             .build()
-            .unwrap();
-        let _enter = rt.enter();
-
-        let fut = tokio::spawn(async move {
-            // Wait for the functions to be ready
-            let mut watchers = self
-                .functions
-                .iter_mut()
-                .map(|f| {
-                    f.build();
-                    f.deploy();
-                    MyWatcher::new(&f.get_root())
-                })
-                .collect::<Vec<_>>();
-
-            self.save();
-
-            // Start the frontends
-            join_all(self.frontends.iter().map(|f| async {
-                let mut rx = f.dev();
-                rx.recv().await.unwrap();
-            }))
-            .await;
-
-            // Start the watchers
-            for w in watchers.iter_mut() {
-                w.enable();
+            .map_err(|e| MuCliError::io("Failed to start the async runtime", e))?;
+
+        rt.block_on(self.watch())
+    }
+
+    async fn watch(&mut self) -> Result<()> {
+        self.deploy_services(&MuNetwork::local())?;
+        self.save()?;
+
+        // Wait for the functions to be ready. A function that fails to build
+        // is reported and picked up again by its watcher once it is fixed.
+        let mut watchers = Vec::with_capacity(self.functions.len());
+        for f in self.functions.iter_mut() {
+            if let Err(e) = f.dev() {
+                print_error(&e);
             }
-            print_full_line("Ready!!!");
-
-            loop {
-                let (_result, idx, _rest) =
-                    futures::future::select_all(watchers.iter_mut().map(|w| w.next())).await;
-                print_full_line("Change detected, rebuilding...");
-                let func = &mut self.functions[idx];
-                func.build();
-                func.deploy();
-                self.save();
-                watchers[idx].enable();
-                print_full_line("Ready again!!!");
+            watchers.push(MyWatcher::new(&f.get_root())?);
+        }
+
+        self.save()?;
+
+        // Start the frontends
+        let mut frontends = self
+            .frontends
+            .iter()
+            .map(|f| f.dev())
+            .collect::<Result<Vec<_>>>()?;
+        join_all(frontends.iter_mut().map(|rx| rx.recv())).await;
+
+        // Start the watchers
+        for w in watchers.iter_mut() {
+            w.enable();
+        }
+        print_full_line("Ready!!!");
+
+        loop {
+            let (_result, idx, _rest) =
+                futures::future::select_all(watchers.iter_mut().map(|w| w.next())).await;
+            print_full_line("Change detected, rebuilding...");
+            let result = self.functions[idx].dev().and_then(|_| self.save());
+            watchers[idx].enable();
+            match result {
+                Ok(()) => print_full_line("Ready again!!!"),
+                Err(e) => print_error(&e),
+            }
+        }
+    }
+
+    /// Deploys the project's services to `network` and points the functions'
+    /// builds at them.
+    fn deploy_services(&mut self, network: &MuNetwork) -> Result<()> {
+        if self.services.kv {
+            KvService::new(&mut self.services_state.kv).deploy(network)?;
+        }
+        self.set_build_env(network);
+        Ok(())
+    }
+
+    /// Passes the ids of the services deployed on `network` to every
+    /// function's build.
+    fn set_build_env(&mut self, network: &MuNetwork) {
+        let mut env = BTreeMap::new();
+        if self.services.kv {
+            if let Some(canister_id) = self.services_state.kv.canister_ids.get(&network.name) {
+                env.insert(KV_CANISTER_ID_ENV.to_string(), canister_id.clone());
             }
-        });
+        }
+
+        for function in self.functions.iter_mut() {
+            function.build_env = env.clone();
+        }
+    }
+
+    /// Builds the functions, then the frontends, which bundle the bindings
+    /// generated for the functions.
+    pub fn build(&mut self) -> Result<()> {
+        self.set_build_env(&MuNetwork::local());
+        let result = self.functions.iter_mut().try_for_each(|f| f.build());
+        self.save()?;
+        result?;
+
+        self.frontends.iter().try_for_each(|f| f.build())
+    }
+
+    /// Resolves a network by name. `local` is always available, other
+    /// networks must be declared in a `[networks.<name>]` section.
+    pub fn network(&self, name: &str) -> Result<MuNetwork> {
+        match self.networks.get(name) {
+            Some(config) => Ok(MuNetwork {
+                name: name.to_owned(),
+                config: config.clone(),
+            }),
+            None if name == config::LOCAL_NETWORK => Ok(MuNetwork::local()),
+            None => Err(MuCliError::UnknownNetwork(name.to_owned())),
+        }
+    }
+
+    pub fn deploy(&mut self, network: &str) -> Result<()> {
+        let network = self.network(network)?;
+        print_full_line(&format!("Deploying to network: {}", network.name));
+
+        if let Err(e) = self.deploy_services(&network) {
+            self.save()?;
+            return Err(e);
+        }
 
-        rt.block_on(fut).unwrap();
+        let result = self
+            .functions
+            .iter_mut()
+            .try_for_each(|f| f.deploy(&network));
+        self.save()?;
+        result
     }
 
-    pub fn build(&mut self) {
-        self.functions.iter_mut().for_each(|f| f.build());
-        self.save();
+    pub fn status(&mut self) -> Result<()> {
+        for function in self.functions.iter_mut() {
+            let status = function.status()?;
+            println!(
+                "{} ({}): {}",
+                function.config.name, function.config.fn_type, status
+            );
+        }
+        Ok(())
     }
 
-    pub fn deploy(&mut self) {
-        self.functions.iter_mut().for_each(|f| f.deploy());
-        self.save();
+    /// A client for the KV service deployed to `network`, working with the
+    /// collections of `owner`: the name of one of the project's ICP
+    /// functions, or a principal. Without an owner, it works with those of
+    /// the dfx identity.
+    pub fn kv(&self, network: &str, owner: Option<&str>) -> Result<KvClient> {
+        let network = self.network(network)?;
+        if !self.services.kv {
+            return Err(MuCliError::Backend(
+                "The KV service is not enabled. Set `kv = true` in the [services] section of mu.toml."
+                    .to_string(),
+            ));
+        }
+
+        let canister_id = self
+            .services_state
+            .kv
+            .canister_ids
+            .get(&network.name)
+            .cloned()
+            .ok_or_else(|| {
+                MuCliError::Backend(format!(
+                    "The KV service is not deployed to network `{}`. Run `mu deploy --network {}` first.",
+                    network.name, network.name
+                ))
+            })?;
+        let owner = owner
+            .map(|owner| self.kv_owner(owner, &network.name))
+            .transpose()?;
+        KvClient::new(canister_id, network, owner)
     }
 
-    pub fn add_frontend(&mut self, name: &str, template: MuFrontendTemplate) {
+    fn kv_owner(&self, owner: &str, network: &str) -> Result<Principal> {
+        let function = self.functions.iter().find(|f| f.config.name == owner);
+        let canister_id = match function {
+            Some(function) if function.config.fn_type.as_str() == icp::FUNCTION_TYPE => function
+                .state
+                .backend_state
+                .get::<MuIcpFunctionState>()?
+                .canister_ids
+                .get(network)
+                .cloned()
+                .ok_or_else(|| {
+                    MuCliError::Backend(format!(
+                        "Function `{}` is not deployed to network `{}`",
+                        owner, network
+                    ))
+                })?,
+            Some(_) => {
+                return Err(MuCliError::Backend(format!(
+                    "Function `{}` is not an ICP function and cannot own collections",
+                    owner
+                )))
+            }
+            None => owner.to_owned(),
+        };
+
+        Principal::from_text(&canister_id).map_err(|_| {
+            MuCliError::Backend(format!(
+                "`{}` is neither a function of the project nor a principal",
+                owner
+            ))
+        })
+    }
+
+    pub fn add_frontend(&mut self, name: &str, template: MuFrontendTemplate) -> Result<()> {
+        if self.frontends.iter().any(|f| f.config.name == name) {
+            return Err(MuCliError::FrontendExists(name.to_owned()));
+        }
         print_full_line(&format!("Adding frontend: {}", name));
 
         // create the diretory frontends/name
         let path = format!("frontends/{}", name);
-        std::fs::create_dir_all(path).unwrap();
+        std::fs::create_dir_all(&path)
+            .map_err(|e| MuCliError::io(format!("Failed to create {}", path), e))?;
 
-        let fe = MuFrontend::new(name, template);
+        let fe = MuFrontend::new(name, template)?;
 
         self.frontends.push(fe);
-        self.save();
+        self.save()
+    }
+
+    pub fn list_frontends(&self) {
+        let rows = self
+            .frontends
+            .iter()
+            .map(|f| {
+                let out_dir = f.out_dir();
+                vec![
+                    f.config.name.clone(),
+                    f.config.template.to_string(),
+                    if fs::metadata(&out_dir).is_ok() {
+                        out_dir
+                    } else {
+                        "not built".to_string()
+                    },
+                ]
+            })
+            .collect::<Vec<_>>();
+        print_table(&["NAME", "TEMPLATE", "BUILD OUTPUT"], &rows);
+    }
+
+    /// Removes a frontend from the project and deletes its sources.
+    pub fn remove_frontend(&mut self, name: &str) -> Result<()> {
+        let idx = self
+            .frontends
+            .iter()
+            .position(|f| f.config.name == name)
+            .ok_or_else(|| MuCliError::UnknownFrontend(name.to_owned()))?;
+        print_full_line(&format!("Removing frontend: {}", name));
+
+        let frontend = self.frontends.remove(idx);
+        let root = frontend.get_root();
+        if fs::metadata(&root).is_ok() {
+            fs::remove_dir_all(&root)
+                .map_err(|e| MuCliError::io(format!("Failed to delete {}", root), e))?;
+        }
+
+        self.save()
     }
 }
 
 pub struct MuFunction {
     pub state: MuFunctionState,
     pub config: MuFunctionConfig,
+    /// Environment variables passed to the function's build, such as the ids
+    /// of the project's services.
+    pub build_env: BTreeMap<String, String>,
 }
 
 impl MuFunction {
     pub fn new(name: &str, fn_type: MuFunctionType) -> MuFunction {
         MuFunction {
-            state: MuFunctionState::new(name, fn_type),
+            state: MuFunctionState::new(name, fn_type.clone()),
             config: MuFunctionConfig::new(name, fn_type),
+            build_env: BTreeMap::new(),
         }
     }
 
-    // fn get_backend(&self) -> Box<dyn MuFunctionBackend> {
-    //     match &self.state.backend_state {
-    //         MuBackendFunctionState::Icp(_) => {
-    //             IcpFunction::new(&format!("functions/{}", self.state.name), self)
-    //         }
-    //         MuBackendFunctionState::Solana(_) => unimplemented!(),
-    //     }
-    // }
-
-    pub fn init(&mut self) {
-        match &mut self.state.backend_state {
-            MuBackendFunctionState::Icp(_icp) => {
-                let icp_function =
-                    IcpFunction::new(&format!("functions/{}", self.state.name), self);
-                icp_function.init();
-            }
-            MuBackendFunctionState::Solana(_) => {
-                unimplemented!();
-            }
-        }
+    fn get_backend(&mut self) -> Result<Box<dyn MuFunctionBackend + '_>> {
+        let root = self.get_root();
+        get_function_backend(&root, self)
     }
 
-    pub fn build(&mut self) {
-        match &self.state.backend_state {
-            MuBackendFunctionState::Icp(_icp) => {
-                let mut icp_function =
-                    IcpFunction::new(&format!("functions/{}", self.state.name), self);
-                icp_function.build();
-            }
-            MuBackendFunctionState::Solana(_) => {
-                unimplemented!();
-            }
-        }
+    pub fn init(&mut self) -> Result<()> {
+        self.get_backend()?.init()
     }
 
-    pub fn deploy(&mut self) {
-        match &self.state.backend_state {
-            MuBackendFunctionState::Icp(_icp) => {
-                let mut icp_function =
-                    IcpFunction::new(&format!("functions/{}", self.state.name), self);
-                icp_function.deploy();
-            }
-            MuBackendFunctionState::Solana(_) => {
-                unimplemented!();
-            }
-        }
+    pub fn build(&mut self) -> Result<()> {
+        self.get_backend()?.build()?;
+        self.state.last_build = Some(now());
+        Ok(())
+    }
+
+    pub fn deploy(&mut self, network: &MuNetwork) -> Result<()> {
+        self.get_backend()?.deploy(network)
+    }
+
+    pub fn dev(&mut self) -> Result<()> {
+        self.get_backend()?.dev()?;
+        self.state.last_build = Some(now());
+        Ok(())
+    }
+
+    pub fn status(&mut self) -> Result<String> {
+        Ok(self.get_backend()?.status())
+    }
+
+    /// The ids the function is deployed as, keyed by network name.
+    pub fn deployments(&mut self) -> Result<BTreeMap<String, String>> {
+        Ok(self.get_backend()?.deployments())
+    }
+
+    pub fn remove(&mut self, network: &MuNetwork) -> Result<()> {
+        self.get_backend()?.remove(network)
+    }
+
+    pub fn renamed(&mut self, old_name: &str) -> Result<()> {
+        self.get_backend()?.renamed(old_name)
     }
 
     pub fn get_root(&self) -> String {
@@ -243,7 +613,7 @@ pub struct MuFrontend {
 }
 
 impl MuFrontend {
-    pub fn new(name: &str, template: MuFrontendTemplate) -> MuFrontend {
+    pub fn new(name: &str, template: MuFrontendTemplate) -> Result<MuFrontend> {
         let out = MuFrontend {
             config: MuFrontendConfig {
                 name: name.to_owned(),
@@ -251,19 +621,28 @@ impl MuFrontend {
             },
         };
 
-        out.get_backend().create_frontend();
-        out
+        out.get_backend().create_frontend()?;
+        Ok(out)
     }
 
     pub fn get_root(&self) -> String {
         format!("frontends/{}", self.config.name)
     }
 
-    fn get_backend(&self) -> JsBackend {
+    /// Where `mu build` puts the frontend's production build.
+    pub fn out_dir(&self) -> String {
+        format!("{}/{}", self.get_root(), FRONTEND_OUT_DIR)
+    }
+
+    fn get_backend(&self) -> JsBackend<'_> {
         JsBackend::new(&self.get_root(), &self.config)
     }
 
-    pub fn dev(&self) -> UnboundedReceiver<()> {
+    pub fn build(&self) -> Result<()> {
+        self.get_backend().build(FRONTEND_OUT_DIR)
+    }
+
+    pub fn dev(&self) -> Result<UnboundedReceiver<()>> {
         self.get_backend().dev()
     }
 }
@@ -275,17 +654,49 @@ pub enum MuFrontendTemplate {
     Vue,
 }
 
-#[derive(ValueEnum, Deserialize, Serialize, Clone, Copy, Debug)]
-pub enum MuFunctionType {
-    ICP,
-    Solana,
+/// The type of a function, which names the backend registered to scaffold,
+/// build and ship it.
+#[derive(Serialize, Clone, Debug, PartialEq, Eq)]
+#[serde(transparent)]
+pub struct MuFunctionType(String);
+
+impl MuFunctionType {
+    pub fn new(id: &str) -> MuFunctionType {
+        MuFunctionType(id.to_lowercase())
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl FromStr for MuFunctionType {
+    type Err = Infallible;
+
+    fn from_str(id: &str) -> std::result::Result<Self, Self::Err> {
+        Ok(MuFunctionType::new(id))
+    }
+}
+
+/// Older versions of `mu` wrote types as `ICP` and `Solana`.
+impl<'de> Deserialize<'de> for MuFunctionType {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        Ok(MuFunctionType::new(&String::deserialize(deserializer)?))
+    }
 }
 
-impl ToString for MuFunctionType {
-    fn to_string(&self) -> String {
+impl Display for MuFrontendTemplate {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            MuFunctionType::ICP => "icp".to_string(),
-            MuFunctionType::Solana => "solana".to_string(),
+            MuFrontendTemplate::Vanilla => write!(f, "vanilla"),
+            MuFrontendTemplate::React => write!(f, "react"),
+            MuFrontendTemplate::Vue => write!(f, "vue"),
         }
     }
 }
+
+impl Display for MuFunctionType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
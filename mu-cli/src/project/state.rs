@@ -1,77 +1,168 @@
-use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 
-use super::MuFunctionType;
+use serde::{de::DeserializeOwned, Deserialize, Deserializer, Serialize};
+use serde_json::{Map, Value};
 
-#[derive(Serialize, Deserialize, Debug)]
+use super::{config::read_if_exists, MuFunctionType};
+use crate::error::{MuCliError, Result};
+
+static STATE_FILENAME: &str = "mu.state.json";
+
+#[derive(Serialize, Deserialize, Debug, Default)]
 pub struct MuProjectState {
-    pub functions: Vec<MuFunctionState>,
+    /// The state of each function, keyed by function name
+    #[serde(deserialize_with = "deserialize_functions", default)]
+    pub functions: BTreeMap<String, MuFunctionState>,
+    #[serde(default)]
+    pub services: MuServicesState,
+}
+
+/// Reads the functions' state keyed by name, or as a list in the order of
+/// `mu.toml` as older versions of `mu` wrote it.
+fn deserialize_functions<'de, D>(
+    deserializer: D,
+) -> std::result::Result<BTreeMap<String, MuFunctionState>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Functions {
+        ByName(BTreeMap<String, MuFunctionState>),
+        List(Vec<MuFunctionState>),
+    }
+
+    Ok(match Functions::deserialize(deserializer)? {
+        Functions::ByName(functions) => functions
+            .into_iter()
+            .map(|(name, mut state)| {
+                state.name = name.clone();
+                (name, state)
+            })
+            .collect(),
+        Functions::List(functions) => functions
+            .into_iter()
+            .map(|state| (state.name.clone(), state))
+            .collect(),
+    })
 }
 
 impl MuProjectState {
-    pub fn save(&self) {
+    pub fn save(&self) -> Result<()> {
         let json = serde_json::to_string(&self).unwrap();
-        std::fs::write("mu.state.json", json).unwrap();
+        std::fs::write(STATE_FILENAME, json)
+            .map_err(|e| MuCliError::io(format!("Failed to write {}", STATE_FILENAME), e))
     }
 
-    pub fn load() -> Option<MuProjectState> {
-        if let Ok(json) = std::fs::read_to_string("mu.state.json") {
-            let state = serde_json::from_str(&json).unwrap();
-            Some(state)
-        } else {
-            None
-        }
+    pub fn load() -> Result<Option<MuProjectState>> {
+        let Some(json) = read_if_exists(STATE_FILENAME)? else {
+            return Ok(None);
+        };
+        let state =
+            serde_json::from_str(&json).map_err(|e| MuCliError::parse_json(STATE_FILENAME, e))?;
+        Ok(Some(state))
     }
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct MuFunctionState {
+    /// The function's name, which is also its key in `mu.state.json`
+    #[serde(skip_serializing, default)]
     pub name: String,
     pub backend_state: MuBackendFunctionState,
+    /// When the function last built successfully, in seconds since the epoch
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub last_build: Option<u64>,
 }
 
 impl MuFunctionState {
     pub fn new(name: &str, fn_type: MuFunctionType) -> MuFunctionState {
         MuFunctionState {
             name: name.to_owned(),
-            backend_state: match fn_type {
-                MuFunctionType::ICP => MuBackendFunctionState::Icp(Default::default()),
-                MuFunctionType::Solana => MuBackendFunctionState::Solana(Default::default()),
+            backend_state: MuBackendFunctionState {
+                fn_type,
+                state: Map::new(),
             },
+            last_build: None,
         }
     }
+}
 
-    pub fn unwrap_icp(&self) -> &MuIcpFunctionState {
-        match &self.backend_state {
-            MuBackendFunctionState::Icp(icp) => icp,
-            _ => panic!("Expected ICP backend state"),
-        }
+/// The function's type, and the state its backend keeps as it sees fit.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct MuBackendFunctionState {
+    #[serde(rename = "type")]
+    pub fn_type: MuFunctionType,
+    #[serde(flatten)]
+    state: Map<String, Value>,
+}
+
+impl MuBackendFunctionState {
+    /// Reads the state as the backend's type for it. A fresh function has
+    /// no state yet, so `T` should read an empty object as its default.
+    pub fn get<T: DeserializeOwned>(&self) -> Result<T> {
+        serde_json::from_value(Value::Object(self.state.clone()))
+            .map_err(|e| MuCliError::parse_json(STATE_FILENAME, e))
     }
 
-    pub fn unwrap_icp_mut(&mut self) -> &mut MuIcpFunctionState {
-        match &mut self.backend_state {
-            MuBackendFunctionState::Icp(icp) => icp,
-            _ => panic!("Expected ICP backend state"),
-        }
+    /// Replaces the state with `state`, which has to serialize to an object.
+    pub fn set<T: Serialize>(&mut self, state: &T) {
+        let Value::Object(state) = serde_json::to_value(state).unwrap() else {
+            panic!("Backend state must serialize to a JSON object");
+        };
+        self.state = state;
     }
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
-#[serde(tag = "type")]
-pub enum MuBackendFunctionState {
-    #[serde(rename = "icp")]
-    Icp(MuIcpFunctionState),
-    #[serde(rename = "solana")]
-    Solana(MuSolanaFunctionState),
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct MuServicesState {
+    #[serde(default)]
+    pub kv: MuKvServiceState,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, Default)]
-pub struct MuIcpFunctionState {
-    pub did: Option<String>,
-    pub canister_id: Option<String>,
-    pub js_bindings: Option<String>,
+pub struct MuKvServiceState {
+    /// Canister ids keyed by network name
+    #[serde(default)]
+    pub canister_ids: BTreeMap<String, String>,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone, Default)]
-pub struct MuSolanaFunctionState {
-    pub name: String,
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_the_backend_state_as_stored() {
+        let json = r#"{"functions":{"hello":{"backend_state":{"type":"icp","canister_ids":{"local":"bkyz2-fmaaa-aaaaa-qaaaq-cai"},"did":null,"js_bindings":null}}},"services":{"kv":{"canister_ids":{}}}}"#;
+        let state: MuProjectState = serde_json::from_str(json).unwrap();
+
+        let hello = &state.functions["hello"];
+        assert_eq!(hello.name, "hello");
+        assert_eq!(hello.backend_state.fn_type, MuFunctionType::new("icp"));
+        assert_eq!(serde_json::to_string(&state).unwrap(), json);
+    }
+
+    #[test]
+    fn reads_fresh_state_as_the_default() {
+        #[derive(Deserialize, Default, Debug, PartialEq)]
+        struct State {
+            id: Option<String>,
+            #[serde(default)]
+            ids: BTreeMap<String, String>,
+        }
+
+        let mut state = MuFunctionState::new("hello", MuFunctionType::new("icp"));
+        assert_eq!(
+            state.backend_state.get::<State>().unwrap(),
+            State::default()
+        );
+
+        state
+            .backend_state
+            .set(&serde_json::json!({"id": "aaaaa-aa"}));
+        assert_eq!(
+            state.backend_state.get::<State>().unwrap().id.as_deref(),
+            Some("aaaaa-aa")
+        );
+    }
 }
@@ -1,11 +1,19 @@
-use std::path::Path;
+use std::{
+    io::Write,
+    path::Path,
+    process::{Command, Stdio},
+    time::{SystemTime, UNIX_EPOCH},
+};
 
+use clap::ValueEnum;
 use colored::Colorize;
 use futures::Stream;
 use notify::{Event, RecursiveMode, Watcher};
 use terminal_size::{terminal_size, Width};
 use tokio::sync::mpsc::{self, UnboundedReceiver};
 
+use crate::error::{MuCliError, Result};
+
 pub fn print_full_line(message: &str) {
     let width = match terminal_size() {
         Some((Width(w), _)) => w as usize,
@@ -20,6 +28,125 @@ pub fn print_full_line(message: &str) {
     println!("{}", padded_message);
 }
 
+/// How commands that print data format it.
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum OutputFormat {
+    Table,
+    Json,
+}
+
+/// Prints `rows` as columns aligned under `headers`.
+pub fn print_table(headers: &[&str], rows: &[Vec<String>]) {
+    let mut widths = headers.iter().map(|h| h.len()).collect::<Vec<_>>();
+    for row in rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+
+    let format_row = |cells: &mut dyn Iterator<Item = &str>| {
+        cells
+            .zip(&widths)
+            .map(|(cell, width)| format!("{:<width$}", cell, width = width))
+            .collect::<Vec<_>>()
+            .join("  ")
+            .trim_end()
+            .to_string()
+    };
+    println!("{}", format_row(&mut headers.iter().copied()).bold());
+    for row in rows {
+        println!("{}", format_row(&mut row.iter().map(String::as_str)));
+    }
+}
+
+pub fn print_error(error: &MuCliError) {
+    eprintln!("{} {}", "[μ] error:".red().bold(), error);
+}
+
+pub fn print_warning(message: &str) {
+    eprintln!("{} {}", "[μ] warning:".yellow().bold(), message);
+}
+
+/// Seconds since the epoch.
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+/// Describes how long ago `time`, in seconds since the epoch, was, e.g.
+/// `5 minutes ago`.
+pub fn format_time_ago(time: u64) -> String {
+    let elapsed = now().saturating_sub(time);
+    let (amount, unit) = match elapsed {
+        0..60 => return "just now".to_string(),
+        60..3600 => (elapsed / 60, "minute"),
+        3600..86400 => (elapsed / 3600, "hour"),
+        _ => (elapsed / 86400, "day"),
+    };
+    let plural = if amount == 1 { "" } else { "s" };
+    format!("{} {}{} ago", amount, unit, plural)
+}
+
+/// Runs `command` to completion, failing with `Failed to {action}` if it
+/// does not exit successfully.
+pub fn run_command(command: &mut Command, action: &str) -> Result<()> {
+    let status = command
+        .status()
+        .map_err(|e| MuCliError::spawn(command, e))?;
+
+    if !status.success() {
+        return Err(MuCliError::CommandFailed {
+            action: action.to_owned(),
+            code: status.code(),
+        });
+    }
+    Ok(())
+}
+
+/// Like [`run_command`], but captures and returns the command's stdout.
+pub fn command_output(command: &mut Command, action: &str) -> Result<Vec<u8>> {
+    let output = command
+        .stderr(Stdio::inherit())
+        .output()
+        .map_err(|e| MuCliError::spawn(command, e))?;
+
+    if !output.status.success() {
+        return Err(MuCliError::CommandFailed {
+            action: action.to_owned(),
+            code: output.status.code(),
+        });
+    }
+    Ok(output.stdout)
+}
+
+/// Like [`command_output`], but writes `input` to the command's stdin.
+pub fn pipe_command(command: &mut Command, input: &[u8], action: &str) -> Result<Vec<u8>> {
+    let mut child = command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::inherit())
+        .spawn()
+        .map_err(|e| MuCliError::spawn(command, e))?;
+
+    // Dropping stdin once written closes it, so the command sees the end of
+    // its input
+    let written = child.stdin.take().unwrap().write_all(input);
+    let output = child
+        .wait_with_output()
+        .map_err(|e| MuCliError::io(format!("Failed to {}", action), e))?;
+    written.map_err(|e| MuCliError::io(format!("Failed to {}", action), e))?;
+
+    if !output.status.success() {
+        return Err(MuCliError::CommandFailed {
+            action: action.to_owned(),
+            code: output.status.code(),
+        });
+    }
+    Ok(output.stdout)
+}
+
 pub struct MyWatcher {
     _watcher: notify::RecommendedWatcher,
     event_rx: UnboundedReceiver<()>,
@@ -28,29 +155,30 @@ pub struct MyWatcher {
 }
 
 impl MyWatcher {
-    pub fn new(path: &str) -> Self {
+    pub fn new(path: &str) -> Result<Self> {
         let (event_tx, event_rx) = mpsc::unbounded_channel();
-        let mut watcher = notify::recommended_watcher(move |res: Result<Event, _>| match res {
-            Ok(event) => {
-                if event.kind.is_create() || event.kind.is_modify() || event.kind.is_remove() {
-                    event_tx.send(()).unwrap();
+        let mut watcher =
+            notify::recommended_watcher(move |res: notify::Result<Event>| match res {
+                Ok(event) => {
+                    if event.kind.is_create() || event.kind.is_modify() || event.kind.is_remove() {
+                        event_tx.send(()).unwrap();
+                    }
                 }
-            }
-            Err(e) => {
-                println!("watch error: {:?}", e);
-            }
-        })
-        .unwrap();
+                Err(e) => {
+                    println!("watch error: {:?}", e);
+                }
+            })
+            .map_err(MuCliError::Watch)?;
         watcher
             .watch(Path::new(path), RecursiveMode::Recursive)
-            .unwrap();
+            .map_err(MuCliError::Watch)?;
 
-        Self {
+        Ok(Self {
             _watcher: watcher,
             event_rx,
             enabled: false,
             waker: None,
-        }
+        })
     }
 
     pub fn enable(&mut self) {
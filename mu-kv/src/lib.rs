@@ -1,92 +1,651 @@
-use std::{
-    collections::HashMap,
-    sync::{LazyLock, Mutex},
+use std::{cell::RefCell, collections::VecDeque, ops::Bound, time::Duration};
+
+use candid::{CandidType, Principal};
+use serde::Deserialize;
+use store::{
+    Change, CollectionKey, ExportCursor, ExportedEntry, Index, Op, Quota, QuotaExceeded, Role,
+    Usage,
 };
 
-use ic_principal::Principal;
+mod store;
 
-static COLLECTIONS: LazyLock<Mutex<HashMap<(Principal, String), Collection>>> =
-    LazyLock::new(|| Mutex::new(HashMap::new()));
+/// The most entries a single `scan` or `range` call returns.
+const MAX_PAGE_SIZE: u32 = 1000;
 
-type Collection = HashMap<String, String>;
+#[derive(CandidType, Deserialize)]
+struct Page {
+    entries: Vec<(String, String)>,
+    /// The key the next page starts at, if there are more entries.
+    next: Option<String>,
+}
 
-#[ic_cdk::query]
-fn list_collections() -> Vec<String> {
-    let collections = COLLECTIONS.lock().unwrap();
-    let caller = ic_cdk::caller();
-    collections
-        .iter()
-        .filter_map(|((principal, name), _)| {
-            if principal == &caller {
-                Some(name.clone())
-            } else {
-                None
+#[derive(CandidType, Deserialize)]
+struct VersionedValue {
+    value: String,
+    version: u64,
+}
+
+impl From<(String, u64)> for VersionedValue {
+    fn from((value, version): (String, u64)) -> Self {
+        Self { value, version }
+    }
+}
+
+#[derive(CandidType, Deserialize)]
+enum SwapResult {
+    /// The entry was written and has this new version, or was removed.
+    Swapped {
+        version: Option<u64>,
+    },
+    /// The entry's version did not match, and this is its current value.
+    Conflict {
+        current: Option<VersionedValue>,
+    },
+    CollectionNotFound,
+    QuotaExceeded(QuotaExceeded),
+}
+
+#[derive(CandidType, Deserialize)]
+enum TransactResult {
+    /// All operations were applied. Holds the new version of each put, and
+    /// `None` for the other operations.
+    Committed {
+        versions: Vec<Option<u64>>,
+    },
+    /// The check at index `op` failed, and this is the entry's current value.
+    Conflict {
+        op: u32,
+        current: Option<VersionedValue>,
+    },
+    CollectionNotFound {
+        collection: String,
+    },
+    QuotaExceeded(QuotaExceeded),
+}
+
+#[derive(CandidType, Deserialize)]
+struct ChangesPage {
+    changes: Vec<Change>,
+    /// The sequence number of the collection's latest change.
+    last_seq: u64,
+    /// Some of the requested changes are no longer in the log, so the
+    /// collection has to be read again.
+    truncated: bool,
+}
+
+#[derive(CandidType, Deserialize)]
+struct ExportPage {
+    entries: Vec<ExportedEntry>,
+    /// The indexes of the collection, to create it with on import.
+    indexes: Vec<Index>,
+    /// Where the next page starts, if there are more entries.
+    next: Option<ExportCursor>,
+}
+
+#[derive(CandidType, Deserialize)]
+struct UsageReport {
+    /// What the collection takes up.
+    collection: Usage,
+    /// What all collections of the owner take up together.
+    owner: Usage,
+    /// The owner's limit on the total.
+    quota: Quota,
+}
+
+#[derive(CandidType, Deserialize, Clone)]
+struct CallStats {
+    method: String,
+    /// The instructions the call took.
+    instructions: u64,
+    /// When the call was made, in nanoseconds since the epoch.
+    at: u64,
+}
+
+#[derive(CandidType, Deserialize)]
+struct PrincipalStats {
+    principal: Principal,
+    collections: u64,
+    usage: Usage,
+}
+
+#[derive(CandidType, Deserialize)]
+struct Stats {
+    /// A page of the principals, in order.
+    principals: Vec<PrincipalStats>,
+    /// The principal the next page starts after, if there are more.
+    next: Option<Principal>,
+    /// The totals across all principals.
+    principal_count: u64,
+    collections: u64,
+    usage: Usage,
+    heap_memory_bytes: u64,
+    stable_memory_bytes: u64,
+    cycles: u128,
+    /// The most recent update calls, oldest first.
+    recent_calls: Vec<CallStats>,
+}
+
+/// How many recent update calls `stats` reports.
+const RECENT_CALLS: usize = 100;
+
+thread_local! {
+    /// The most recent update calls. Queries are not recorded, since their
+    /// changes to the state are discarded. The calls are kept on the heap,
+    /// so they are lost on upgrades.
+    static RECENT_CALL_STATS: RefCell<VecDeque<CallStats>> = const { RefCell::new(VecDeque::new()) };
+}
+
+/// Records how many instructions the update call it was started in took,
+/// once it is dropped at the end of the call.
+struct CallMeter {
+    method: &'static str,
+}
+
+impl CallMeter {
+    fn start(method: &'static str) -> Self {
+        Self { method }
+    }
+}
+
+impl Drop for CallMeter {
+    fn drop(&mut self) {
+        let call = CallStats {
+            method: self.method.to_owned(),
+            instructions: ic_cdk::api::performance_counter(0),
+            at: ic_cdk::api::time(),
+        };
+        RECENT_CALL_STATS.with(|recent| {
+            let mut recent = recent.borrow_mut();
+            if recent.len() == RECENT_CALLS {
+                recent.pop_front();
             }
-        })
+            recent.push_back(call);
+        });
+    }
+}
+
+/// How often expired entries are removed.
+const GC_INTERVAL: Duration = Duration::from_secs(60);
+
+/// The most expired entries removed in one message, so a large backlog is
+/// spread over several messages instead of exceeding the instruction limit.
+const GC_BATCH_SIZE: usize = 500;
+
+/// The most collections, entries or grants counted in one message when
+/// backfilling the usage and stats after an upgrade.
+const BACKFILL_BATCH_SIZE: usize = 2000;
+
+fn page_size(limit: u32) -> usize {
+    limit.clamp(1, MAX_PAGE_SIZE) as usize
+}
+
+fn caller_collection(name: String) -> CollectionKey {
+    CollectionKey::new(ic_cdk::caller(), name)
+}
+
+/// The collection `name` of `owner`, or of the caller if no owner is given.
+/// Traps unless the caller has at least `role` on it. Controllers of mu-kv
+/// can read every collection, so project tooling can inspect the data of its
+/// functions, but need to be granted a role like anyone else to change it.
+fn authorized_collection(name: String, owner: Option<Principal>, role: Role) -> CollectionKey {
+    let caller = ic_cdk::caller();
+    let collection = CollectionKey::new(owner.unwrap_or(caller), name);
+    if role == Role::Read && ic_cdk::api::is_controller(&caller) {
+        return collection;
+    }
+    if store::role(&collection, caller).is_none_or(|granted| granted < role) {
+        ic_cdk::trap(&format!(
+            "{} does not have the {:?} role on collection `{}` of {}",
+            caller, role, collection.name, collection.owner
+        ));
+    }
+    collection
+}
+
+/// Traps unless the caller is a controller of mu-kv.
+fn only_controllers(action: &str) {
+    if !ic_cdk::api::is_controller(&ic_cdk::caller()) {
+        ic_cdk::trap(&format!("only controllers can {}", action));
+    }
+}
+
+/// `owner`, or the caller if no owner is given. Traps unless the caller is
+/// that principal or a controller of mu-kv, which lets project tooling read
+/// the collections of its functions.
+fn owner_or_caller(owner: Option<Principal>) -> Principal {
+    let caller = ic_cdk::caller();
+    let owner = owner.unwrap_or(caller);
+    if caller != owner && !ic_cdk::api::is_controller(&caller) {
+        ic_cdk::trap(&format!(
+            "only {} and controllers of mu-kv can do this",
+            owner
+        ));
+    }
+    owner
+}
+
+/// The collection `name` of `owner`, or of the caller if no owner is given.
+/// Traps unless the caller is its owner or a controller of mu-kv.
+fn owned_collection(name: String, owner: Option<Principal>) -> CollectionKey {
+    CollectionKey::new(owner_or_caller(owner), name)
+}
+
+#[ic_cdk::init]
+fn init() {
+    ic_cdk_timers::set_timer_interval(GC_INTERVAL, collect_expired);
+}
+
+#[ic_cdk::post_upgrade]
+fn post_upgrade() {
+    if store::start_backfill() {
+        ic_cdk_timers::set_timer(Duration::ZERO, backfill);
+    }
+    init();
+}
+
+fn backfill() {
+    if store::backfill(BACKFILL_BATCH_SIZE) {
+        ic_cdk_timers::set_timer(Duration::ZERO, backfill);
+    }
+}
+
+/// Traps while mu-kv is still counting its data after an upgrade.
+fn backfilled() {
+    if !store::backfilled() {
+        ic_cdk::trap("mu-kv is still counting its data after an upgrade, try again later");
+    }
+}
+
+fn collect_expired() {
+    if store::remove_expired(GC_BATCH_SIZE) {
+        ic_cdk_timers::set_timer(Duration::ZERO, collect_expired);
+    }
+}
+
+/// The collections of `owner`, or of the caller if no owner is given. Only
+/// the owner and controllers of mu-kv can list them.
+#[ic_cdk::query]
+fn list_collections(owner: Option<Principal>) -> Vec<String> {
+    store::list_collections(owner_or_caller(owner))
+}
+
+#[ic_cdk::query]
+fn has_collection(name: String, owner: Option<Principal>) -> bool {
+    store::has_collection(&authorized_collection(name, owner, Role::Read))
+}
+
+/// Creates the collection, replacing any existing one of the same name.
+/// `indexes` are kept up to date with every write and can be searched with
+/// `index_lookup`.
+#[ic_cdk::update]
+fn create_collection(name: String, indexes: Option<Vec<Index>>) {
+    let _call = CallMeter::start("create_collection");
+    store::create_collection(caller_collection(name), indexes.unwrap_or_default());
+}
+
+#[ic_cdk::query]
+fn query_collection(name: String, key: String, owner: Option<Principal>) -> Option<String> {
+    store::get(&authorized_collection(name, owner, Role::Read), key)
+}
+
+#[ic_cdk::query]
+fn query_all_keys(name: String, owner: Option<Principal>) -> Vec<String> {
+    store::entries(&authorized_collection(name, owner, Role::Read))
+        .into_iter()
+        .map(|(key, _)| key)
         .collect()
 }
 
 #[ic_cdk::query]
-fn has_collection(name: String) -> bool {
-    let collections = COLLECTIONS.lock().unwrap();
-    let caller = ic_cdk::caller();
-    collections.contains_key(&(caller, name))
+fn query_all_values(name: String, owner: Option<Principal>) -> Vec<String> {
+    store::entries(&authorized_collection(name, owner, Role::Read))
+        .into_iter()
+        .map(|(_, value)| value)
+        .collect()
 }
 
+/// The value of `key` along with its version, which changes every time the
+/// entry is written.
+#[ic_cdk::query]
+fn query_versioned(name: String, key: String, owner: Option<Principal>) -> Option<VersionedValue> {
+    store::get_versioned(&authorized_collection(name, owner, Role::Read), key)
+        .map(VersionedValue::from)
+}
+
+/// Writes `value` under `key`, or removes the key if `value` is `None`, only
+/// if the entry is at `expected_version`. Pass `None` as the expected version
+/// to only write keys that do not exist yet.
 #[ic_cdk::update]
-fn create_collection(name: String) {
-    let caller = ic_cdk::caller();
-    let mut collections = COLLECTIONS.lock().unwrap();
-    collections.insert((caller, name), HashMap::new());
+fn compare_and_swap(
+    name: String,
+    key: String,
+    expected_version: Option<u64>,
+    value: Option<String>,
+    owner: Option<Principal>,
+) -> SwapResult {
+    let _call = CallMeter::start("compare_and_swap");
+    match store::compare_and_swap(
+        &authorized_collection(name, owner, Role::Write),
+        key,
+        expected_version,
+        value,
+    ) {
+        store::Swap::Swapped(version) => SwapResult::Swapped { version },
+        store::Swap::Conflict(current) => SwapResult::Conflict {
+            current: current.map(VersionedValue::from),
+        },
+        store::Swap::CollectionNotFound => SwapResult::CollectionNotFound,
+        store::Swap::QuotaExceeded(exceeded) => SwapResult::QuotaExceeded(exceeded),
+    }
 }
 
+/// The values of `keys`, in the same order.
 #[ic_cdk::query]
-fn query_collection(name: String, key: String) -> Option<String> {
-    let collections = COLLECTIONS.lock().unwrap();
-    let caller = ic_cdk::caller();
-    collections
-        .get(&(caller, name))
-        .and_then(|collection| collection.get(&key).cloned())
+fn batch_get(name: String, keys: Vec<String>, owner: Option<Principal>) -> Vec<Option<String>> {
+    store::get_many(&authorized_collection(name, owner, Role::Read), keys)
 }
 
+/// Entries whose keys start with `prefix`, in key order. Pass the last key
+/// of the previous page as `start_after` to continue a scan.
 #[ic_cdk::query]
-fn query_all_keys(name: String) -> Vec<String> {
-    let collections = COLLECTIONS.lock().unwrap();
-    let caller = ic_cdk::caller();
-    collections
-        .get(&(caller, name))
-        .map(|collection| collection.keys().cloned().collect())
-        .unwrap_or_default()
+fn scan(
+    name: String,
+    prefix: String,
+    start_after: Option<String>,
+    limit: u32,
+    owner: Option<Principal>,
+) -> Page {
+    let start = match start_after {
+        Some(key) if key >= prefix => Bound::Excluded(key),
+        _ => Bound::Included(prefix.clone()),
+    };
+    let (entries, next) = store::page(
+        &authorized_collection(name, owner, Role::Read),
+        start,
+        Bound::Unbounded,
+        &prefix,
+        page_size(limit),
+    );
+    Page { entries, next }
 }
 
+/// Entries with keys in `[from, to)`, in key order. Pass the returned
+/// `next` key as `from` to continue.
 #[ic_cdk::query]
-fn query_all_values(name: String) -> Vec<String> {
-    let collections = COLLECTIONS.lock().unwrap();
-    let caller = ic_cdk::caller();
-    collections
-        .get(&(caller, name))
-        .map(|collection| collection.values().cloned().collect())
-        .unwrap_or_default()
+fn range(name: String, from: String, to: String, limit: u32, owner: Option<Principal>) -> Page {
+    let (entries, next) = store::page(
+        &authorized_collection(name, owner, Role::Read),
+        Bound::Included(from),
+        Bound::Excluded(to),
+        "",
+        page_size(limit),
+    );
+    Page { entries, next }
 }
 
+/// Entries whose value the index `index` maps to `value`, in key order and
+/// after `start_after` if given, or `None` if the collection has no such
+/// index. Pass the last returned key as `start_after` to continue.
+#[ic_cdk::query]
+fn index_lookup(
+    name: String,
+    index: String,
+    value: String,
+    limit: u32,
+    owner: Option<Principal>,
+    start_after: Option<String>,
+) -> Option<Page> {
+    store::index_lookup(
+        &authorized_collection(name, owner, Role::Read),
+        index,
+        value,
+        start_after,
+        page_size(limit),
+    )
+    .map(|(entries, next)| Page { entries, next })
+}
+
+/// Sets `key` to `value`, or removes it if `value` is `None`. A value set
+/// with a TTL is no longer returned once `ttl_seconds` have passed; one set
+/// without a TTL never expires, whether or not the key had one before. The
+/// other writes, `compare_and_swap`, `batch_update` and `transact` puts,
+/// never set a TTL either.
 #[ic_cdk::update]
-fn update_collection(name: String, key: String, value: Option<String>) {
-    let mut collections = COLLECTIONS.lock().unwrap();
-    let caller = ic_cdk::caller();
-    if let Some(collection) = collections.get_mut(&(caller, name)) {
-        if let Some(value) = value {
-            collection.insert(key, value);
-        } else {
-            collection.remove(&key);
+fn update_collection(
+    name: String,
+    key: String,
+    value: Option<String>,
+    owner: Option<Principal>,
+    ttl_seconds: Option<u64>,
+) -> Result<(), QuotaExceeded> {
+    let _call = CallMeter::start("update_collection");
+    store::set(
+        &authorized_collection(name, owner, Role::Write),
+        key,
+        value,
+        ttl_seconds,
+    )
+}
+
+/// Applies all changes at once, removing the keys whose value is `None`.
+#[ic_cdk::update]
+fn batch_update(
+    name: String,
+    changes: Vec<(String, Option<String>)>,
+    owner: Option<Principal>,
+) -> Result<(), QuotaExceeded> {
+    let _call = CallMeter::start("batch_update");
+    store::set_many(&authorized_collection(name, owner, Role::Write), changes)
+}
+
+/// Applies puts and deletes across several of the caller's collections at
+/// once, but only if all checks pass, so either all operations take effect
+/// or none do.
+#[ic_cdk::update]
+fn transact(ops: Vec<Op>) -> TransactResult {
+    let _call = CallMeter::start("transact");
+    match store::transact(ic_cdk::caller(), ops) {
+        store::Transaction::Committed(versions) => TransactResult::Committed { versions },
+        store::Transaction::Conflict(op, current) => TransactResult::Conflict {
+            op: op as u32,
+            current: current.map(VersionedValue::from),
+        },
+        store::Transaction::CollectionNotFound(collection) => {
+            TransactResult::CollectionNotFound { collection }
         }
+        store::Transaction::QuotaExceeded(exceeded) => TransactResult::QuotaExceeded(exceeded),
     }
 }
 
+/// The binary value of `key`. Blobs are stored apart from text values, so
+/// the same key can hold both.
+#[ic_cdk::query]
+fn query_blob(name: String, key: String, owner: Option<Principal>) -> Option<Vec<u8>> {
+    store::get_blob(&authorized_collection(name, owner, Role::Read), key)
+}
+
 #[ic_cdk::update]
-fn delete_collection(name: String) {
-    let mut collections = COLLECTIONS.lock().unwrap();
-    let caller = ic_cdk::caller();
-    collections.remove(&(caller, name));
+fn update_blob(
+    name: String,
+    key: String,
+    value: Option<Vec<u8>>,
+    owner: Option<Principal>,
+) -> Result<(), QuotaExceeded> {
+    let _call = CallMeter::start("update_blob");
+    store::set_blob(&authorized_collection(name, owner, Role::Write), key, value)
+}
+
+/// Up to `limit` changes made to the collection after change `seq`, oldest
+/// first. Pass the last returned `seq` to continue. Reading from 0 returns
+/// the changes still in the log, which keeps the last 1000; once older ones
+/// were dropped, or the collection was deleted or replaced, `truncated` is
+/// set and the collection has to be read in full instead.
+#[ic_cdk::query]
+fn changes_since(name: String, seq: u64, limit: u32, owner: Option<Principal>) -> ChangesPage {
+    let (changes, last_seq, truncated) = store::changes_since(
+        &authorized_collection(name, owner, Role::Read),
+        seq,
+        page_size(limit),
+    );
+    ChangesPage {
+        changes,
+        last_seq,
+        truncated,
+    }
+}
+
+/// A page of the collection's entries for backing it up, text values first
+/// and then blobs, or `None` if the collection does not exist. Pass the
+/// returned `next` cursor to continue.
+#[ic_cdk::query]
+fn export_collection(
+    name: String,
+    after: Option<ExportCursor>,
+    limit: u32,
+    owner: Option<Principal>,
+) -> Option<ExportPage> {
+    let collection = owned_collection(name, owner);
+    if !store::has_collection(&collection) {
+        return None;
+    }
+
+    let (entries, next) = store::export(&collection, after, page_size(limit));
+    Some(ExportPage {
+        entries,
+        indexes: store::indexes(&collection),
+        next,
+    })
+}
+
+/// Writes exported entries into the collection, creating it with `indexes`
+/// if it does not exist yet. Large exports are imported over several calls.
+/// Only the owner and controllers of mu-kv can import, so controllers can
+/// seed a fresh replica with a function's collections.
+#[ic_cdk::update]
+fn import_collection(
+    name: String,
+    indexes: Vec<Index>,
+    entries: Vec<ExportedEntry>,
+    owner: Option<Principal>,
+) -> Result<(), QuotaExceeded> {
+    let _call = CallMeter::start("import_collection");
+    store::import(&owned_collection(name, owner), indexes, entries)
+}
+
+/// How much the collection and its owner store, counting keys and both text
+/// and binary values. Traps while usage is still being counted after an
+/// upgrade of a store that did not track it yet.
+#[ic_cdk::query]
+fn usage(name: String, owner: Option<Principal>) -> UsageReport {
+    let collection = authorized_collection(name, owner, Role::Read);
+    backfilled();
+    UsageReport {
+        collection: store::usage(&collection),
+        owner: store::owner_usage(collection.owner),
+        quota: store::quota(collection.owner),
+    }
+}
+
+/// Sets the quota of `principal`, or the default quota of all principals
+/// without their own if `principal` is `None`. A `None` quota goes back to
+/// the default. Only controllers of mu-kv can change quotas.
+#[ic_cdk::update]
+fn set_quota(principal: Option<Principal>, quota: Option<Quota>) {
+    let _call = CallMeter::start("set_quota");
+    only_controllers("change quotas");
+    store::set_quota(principal, quota);
+}
+
+/// What mu-kv stores in total and for up to `limit` principals after
+/// `after`, and the resources it uses. Pass the returned `next` principal
+/// as `after` to read the next page. Only controllers of mu-kv can read the
+/// stats, and like `usage` they are unavailable while usage is still being
+/// counted after an upgrade.
+#[ic_cdk::query]
+fn stats(after: Option<Principal>, limit: Option<u32>) -> Stats {
+    only_controllers("read the stats");
+    backfilled();
+
+    let (principal_count, collections, usage) = store::total_stats();
+    let (page, next) = store::principal_stats(after, page_size(limit.unwrap_or(MAX_PAGE_SIZE)));
+    let principals = page
+        .into_iter()
+        .map(|(principal, collections, usage)| PrincipalStats {
+            principal,
+            collections,
+            usage,
+        })
+        .collect();
+
+    #[cfg(target_arch = "wasm32")]
+    let heap_memory_bytes = core::arch::wasm32::memory_size(0) as u64 * 65536;
+    #[cfg(not(target_arch = "wasm32"))]
+    let heap_memory_bytes = 0;
+
+    Stats {
+        principals,
+        next,
+        principal_count,
+        collections,
+        usage,
+        heap_memory_bytes,
+        stable_memory_bytes: ic_cdk::api::stable::stable_size() * 65536,
+        cycles: ic_cdk::api::canister_balance128(),
+        recent_calls: RECENT_CALL_STATS.with(|recent| recent.borrow().iter().cloned().collect()),
+    }
+}
+
+/// Deletes all collections of `principal` and its quota, and revokes the
+/// roles it was granted on other collections. Returns how many collections
+/// were deleted. Only controllers of mu-kv can purge principals, once it
+/// has finished counting its data after an upgrade.
+#[ic_cdk::update]
+fn purge_principal(principal: Principal) -> u64 {
+    let _call = CallMeter::start("purge_principal");
+    only_controllers("purge principals");
+    backfilled();
+    store::purge(principal)
+}
+
+#[ic_cdk::update]
+fn delete_collection(name: String, owner: Option<Principal>) {
+    let _call = CallMeter::start("delete_collection");
+    store::delete_collection(&authorized_collection(name, owner, Role::Admin));
+}
+
+/// Gives `principal` `role` on the collection, or revokes its access if
+/// `role` is `None`. Only the owner and admins can manage access.
+#[ic_cdk::update]
+fn grant_access(name: String, principal: Principal, role: Option<Role>, owner: Option<Principal>) {
+    let _call = CallMeter::start("grant_access");
+    let collection = authorized_collection(name, owner, Role::Admin);
+    if principal == collection.owner {
+        ic_cdk::trap("the owner of a collection always has the Admin role");
+    }
+    store::grant(&collection, principal, role);
+}
+
+/// The principals the collection is shared with, and their roles.
+#[ic_cdk::query]
+fn list_access(name: String, owner: Option<Principal>) -> Vec<(Principal, Role)> {
+    store::grants(&authorized_collection(name, owner, Role::Admin))
 }
 
 ic_cdk::export_candid!();
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use candid_parser::utils::{service_equal, CandidSource};
+
+    #[test]
+    fn did_file_matches_the_interface() {
+        let did = Path::new(concat!(env!("CARGO_MANIFEST_DIR"), "/mu-kv.did"));
+        service_equal(
+            CandidSource::Text(&super::__export_service()),
+            CandidSource::File(did),
+        )
+        .expect("mu-kv.did is out of date");
+    }
+}
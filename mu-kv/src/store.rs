@@ -0,0 +1,2696 @@
+use std::{borrow::Cow, cell::RefCell, collections::BTreeMap, ops::Bound, thread::LocalKey};
+
+use candid::CandidType;
+use ic_principal::Principal;
+use ic_stable_structures::{
+    memory_manager::{MemoryId, MemoryManager, VirtualMemory},
+    storable::Bound as StorableBound,
+    DefaultMemoryImpl, StableBTreeMap, StableCell, Storable,
+};
+use serde::Deserialize;
+
+type Memory = VirtualMemory<DefaultMemoryImpl>;
+
+/// Entries in key order, and the key the next page starts at if there are
+/// more.
+type EntryPage = (Vec<(String, String)>, Option<String>);
+
+/// Pages stop growing once their entries take up this many bytes, keeping
+/// replies well below the 2MiB message limit.
+const MAX_PAGE_BYTES: usize = 1024 * 1024;
+
+const COLLECTIONS_MEMORY_ID: MemoryId = MemoryId::new(0);
+const ENTRIES_MEMORY_ID: MemoryId = MemoryId::new(1);
+const VERSIONS_MEMORY_ID: MemoryId = MemoryId::new(2);
+const NEXT_VERSION_MEMORY_ID: MemoryId = MemoryId::new(3);
+const GRANTS_MEMORY_ID: MemoryId = MemoryId::new(4);
+const EXPIRIES_MEMORY_ID: MemoryId = MemoryId::new(5);
+const EXPIRY_QUEUE_MEMORY_ID: MemoryId = MemoryId::new(6);
+const BLOBS_MEMORY_ID: MemoryId = MemoryId::new(7);
+const USAGE_MEMORY_ID: MemoryId = MemoryId::new(8);
+const QUOTAS_MEMORY_ID: MemoryId = MemoryId::new(9);
+const DEFAULT_QUOTA_MEMORY_ID: MemoryId = MemoryId::new(10);
+const CHANGES_MEMORY_ID: MemoryId = MemoryId::new(11);
+const SEQUENCES_MEMORY_ID: MemoryId = MemoryId::new(12);
+const INDEXES_MEMORY_ID: MemoryId = MemoryId::new(13);
+const INDEX_ENTRIES_MEMORY_ID: MemoryId = MemoryId::new(14);
+const RESETS_MEMORY_ID: MemoryId = MemoryId::new(15);
+const BACKFILL_MEMORY_ID: MemoryId = MemoryId::new(16);
+const PRINCIPAL_STATS_MEMORY_ID: MemoryId = MemoryId::new(17);
+const TOTALS_MEMORY_ID: MemoryId = MemoryId::new(18);
+const GRANTED_MEMORY_ID: MemoryId = MemoryId::new(19);
+
+/// How many changes the log of each collection keeps.
+const MAX_CHANGES: u64 = 1000;
+
+/// The quota of principals no other quota was set for.
+const DEFAULT_QUOTA: Quota = Quota {
+    max_bytes: 100 * 1024 * 1024,
+    max_entries: 100_000,
+};
+
+thread_local! {
+    /// The canister's stable memory.
+    static MEMORY: DefaultMemoryImpl = DefaultMemoryImpl::default();
+
+    static MEMORY_MANAGER: RefCell<MemoryManager<DefaultMemoryImpl>> =
+        RefCell::new(MemoryManager::init(MEMORY.with(|memory| memory.clone())));
+
+    static COLLECTIONS: RefCell<StableBTreeMap<CollectionKey, (), Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(COLLECTIONS_MEMORY_ID)),
+        ));
+
+    static ENTRIES: RefCell<StableBTreeMap<EntryKey, String, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(ENTRIES_MEMORY_ID)),
+        ));
+
+    /// The version of each entry. Entries written before versions were
+    /// introduced have none and are reported as version 0.
+    static VERSIONS: RefCell<StableBTreeMap<EntryKey, u64, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(VERSIONS_MEMORY_ID)),
+        ));
+
+    /// Versions are drawn from a single counter, so an entry that is deleted
+    /// and written again never reuses an earlier version.
+    static NEXT_VERSION: RefCell<StableCell<u64, Memory>> =
+        RefCell::new(StableCell::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(NEXT_VERSION_MEMORY_ID)),
+            1,
+        ).expect("failed to initialize the version counter"));
+
+    /// The roles collection owners granted to other principals.
+    static GRANTS: RefCell<StableBTreeMap<GrantKey, u8, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(GRANTS_MEMORY_ID)),
+        ));
+
+    /// When entries written with a TTL expire, in nanoseconds since the epoch.
+    static EXPIRIES: RefCell<StableBTreeMap<EntryKey, u64, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(EXPIRIES_MEMORY_ID)),
+        ));
+
+    /// The same expiries ordered by time, so expired entries can be found
+    /// without scanning the whole store.
+    static EXPIRY_QUEUE: RefCell<StableBTreeMap<ExpiryKey, (), Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(EXPIRY_QUEUE_MEMORY_ID)),
+        ));
+
+    /// Binary values. Blobs have their own keys, separate from text entries.
+    static BLOBS: RefCell<StableBTreeMap<EntryKey, Vec<u8>, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(BLOBS_MEMORY_ID)),
+        ));
+
+    /// The bytes and number of entries stored in each collection.
+    static USAGE: RefCell<StableBTreeMap<CollectionKey, (u64, u64), Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(USAGE_MEMORY_ID)),
+        ));
+
+    /// Quotas set for individual principals, as maximum bytes and entries.
+    static QUOTAS: RefCell<StableBTreeMap<Principal, (u64, u64), Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(QUOTAS_MEMORY_ID)),
+        ));
+
+    static DEFAULT_QUOTA_CELL: RefCell<StableCell<(u64, u64), Memory>> =
+        RefCell::new(StableCell::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(DEFAULT_QUOTA_MEMORY_ID)),
+            DEFAULT_QUOTA.into(),
+        ).expect("failed to initialize the default quota"));
+
+    /// The most recent changes of each collection, by sequence number.
+    static CHANGES: RefCell<StableBTreeMap<ChangeKey, LoggedChange, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(CHANGES_MEMORY_ID)),
+        ));
+
+    /// The sequence number of the last change of each collection. Kept when
+    /// a collection is deleted, so its sequence numbers are never reused.
+    static SEQUENCES: RefCell<StableBTreeMap<CollectionKey, u64, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(SEQUENCES_MEMORY_ID)),
+        ));
+
+    /// The sequence number at which each collection was last deleted or
+    /// replaced. Readers that have not seen it missed the reset.
+    static RESETS: RefCell<StableBTreeMap<CollectionKey, u64, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(RESETS_MEMORY_ID)),
+        ));
+
+    /// How far counting the usage and stats of stores written before they
+    /// were tracked has got.
+    static BACKFILL: RefCell<StableCell<Backfill, Memory>> =
+        RefCell::new(StableCell::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(BACKFILL_MEMORY_ID)),
+            Backfill::Done,
+        ).expect("failed to initialize the backfill"));
+
+    /// The collections, bytes and entries of every principal that owns
+    /// collections.
+    static PRINCIPAL_STATS: RefCell<StableBTreeMap<Principal, (u64, u64, u64), Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(PRINCIPAL_STATS_MEMORY_ID)),
+        ));
+
+    /// The collections, bytes and entries of all principals together.
+    static TOTALS: RefCell<StableCell<(u64, u64, u64), Memory>> =
+        RefCell::new(StableCell::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(TOTALS_MEMORY_ID)),
+            (0, 0, 0),
+        ).expect("failed to initialize the totals"));
+
+    /// The same grants as `GRANTS` ordered by principal, so the grants of
+    /// one principal can be found without scanning all of them.
+    static GRANTED: RefCell<StableBTreeMap<PrincipalGrantKey, (), Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(GRANTED_MEMORY_ID)),
+        ));
+
+    /// The field path of each index, keyed by collection and index name.
+    static INDEXES: RefCell<StableBTreeMap<EntryKey, String, Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(INDEXES_MEMORY_ID)),
+        ));
+
+    /// The keys of the entries each index maps a value to.
+    static INDEX_ENTRIES: RefCell<StableBTreeMap<IndexEntryKey, (), Memory>> =
+        RefCell::new(StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(INDEX_ENTRIES_MEMORY_ID)),
+        ));
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub enum ChangeKind {
+    /// The text value was written and now has this version.
+    Set {
+        version: u64,
+    },
+    /// The text value was removed, or expired.
+    Removed,
+    BlobSet,
+    BlobRemoved,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct Change {
+    pub seq: u64,
+    pub key: String,
+    pub kind: ChangeKind,
+}
+
+/// Where counting the usage and stats of existing data stands. Collections
+/// are counted first, then text entries, then blobs, and last grants are
+/// indexed by principal, each up to and including the last key done.
+#[derive(Clone, Debug, PartialEq)]
+enum Backfill {
+    Done,
+    Collections(Option<CollectionKey>),
+    Entries(Option<EntryKey>),
+    Blobs(Option<EntryKey>),
+    Grants(Option<GrantKey>),
+}
+
+impl Backfill {
+    /// Whether `collection` was counted already, so creating or deleting it
+    /// has to update the stats.
+    fn counted_collection(&self, collection: &CollectionKey) -> bool {
+        match self {
+            Self::Collections(last) => last.as_ref().is_some_and(|last| collection <= last),
+            _ => true,
+        }
+    }
+
+    /// Whether the usage of the text entry or blob at `key` was counted
+    /// already, so writing it has to update the usage.
+    fn counted(&self, key: &EntryKey, blob: bool) -> bool {
+        let reached = |last: &Option<EntryKey>| last.as_ref().is_some_and(|last| key <= last);
+        match self {
+            Self::Done | Self::Grants(_) => true,
+            Self::Collections(_) => false,
+            Self::Entries(last) => !blob && reached(last),
+            Self::Blobs(last) => !blob || reached(last),
+        }
+    }
+}
+
+/// A change as it is stored in the log, which keeps its sequence number in
+/// the map key.
+struct LoggedChange {
+    key: String,
+    kind: ChangeKind,
+}
+
+/// An index on the field at `path` of JSON values, given as object keys
+/// separated by dots; an empty path indexes the whole value. String fields
+/// are indexed by their text, numbers and booleans by their JSON form.
+/// Values that are not JSON or lack the field are left out.
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct Index {
+    pub name: String,
+    pub path: String,
+}
+
+/// How much a principal may store across all of its collections. Keys count
+/// towards the stored bytes along with values.
+#[derive(CandidType, Deserialize, Clone, Copy, Debug)]
+pub struct Quota {
+    pub max_bytes: u64,
+    pub max_entries: u64,
+}
+
+impl From<(u64, u64)> for Quota {
+    fn from((max_bytes, max_entries): (u64, u64)) -> Self {
+        Self {
+            max_bytes,
+            max_entries,
+        }
+    }
+}
+
+impl From<Quota> for (u64, u64) {
+    fn from(quota: Quota) -> Self {
+        (quota.max_bytes, quota.max_entries)
+    }
+}
+
+#[derive(CandidType, Deserialize, Clone, Copy, Debug, Default)]
+pub struct Usage {
+    pub bytes: u64,
+    pub entries: u64,
+}
+
+/// A write was rejected because it would take its owner's usage to `usage`,
+/// over `quota`.
+#[derive(CandidType, Deserialize, Debug)]
+pub struct QuotaExceeded {
+    pub quota: Quota,
+    pub usage: Usage,
+}
+
+/// What a principal may do with a collection. Each role includes the ones
+/// before it, and owners always have the `Admin` role.
+#[derive(CandidType, Deserialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Role {
+    Read,
+    Write,
+    /// Can also delete the collection and manage its grants.
+    Admin,
+}
+
+impl Role {
+    fn from_u8(role: u8) -> Self {
+        match role {
+            0 => Role::Read,
+            1 => Role::Write,
+            _ => Role::Admin,
+        }
+    }
+}
+
+/// The outcome of [`compare_and_swap`].
+pub enum Swap {
+    /// The entry was written, and now has this version, or was removed.
+    Swapped(Option<u64>),
+    /// The entry's version did not match; this is its current value and
+    /// version, if it exists.
+    Conflict(Option<(String, u64)>),
+    QuotaExceeded(QuotaExceeded),
+    CollectionNotFound,
+}
+
+/// An operation of a transaction on one of the caller's collections.
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub enum Op {
+    Put {
+        collection: String,
+        key: String,
+        value: String,
+    },
+    Delete {
+        collection: String,
+        key: String,
+    },
+    /// Fails the transaction unless the entry is at `version`, or does not
+    /// exist if `version` is `None`.
+    Check {
+        collection: String,
+        key: String,
+        version: Option<u64>,
+    },
+}
+
+impl Op {
+    fn collection(&self) -> &String {
+        match self {
+            Op::Put { collection, .. }
+            | Op::Delete { collection, .. }
+            | Op::Check { collection, .. } => collection,
+        }
+    }
+}
+
+/// The outcome of [`transact`].
+pub enum Transaction {
+    /// All operations were applied. Holds the new version of each put, and
+    /// `None` for the other operations.
+    Committed(Vec<Option<u64>>),
+    /// The check at this index failed, and this is the entry's current value
+    /// and version, if it exists.
+    Conflict(usize, Option<(String, u64)>),
+    CollectionNotFound(String),
+    QuotaExceeded(QuotaExceeded),
+}
+
+/// Identifies a collection: collections are namespaced by the principal that
+/// created them.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct CollectionKey {
+    pub owner: Principal,
+    pub name: String,
+}
+
+impl CollectionKey {
+    pub fn new(owner: Principal, name: String) -> Self {
+        Self { owner, name }
+    }
+
+    fn entry(&self, key: String) -> EntryKey {
+        EntryKey {
+            collection: self.clone(),
+            key,
+        }
+    }
+}
+
+/// Entries are ordered by owner, then collection, then key, so the entries
+/// of one collection form a contiguous range of the map.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct EntryKey {
+    pub collection: CollectionKey,
+    pub key: String,
+}
+
+/// A value of an exported entry.
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub enum ExportedValue {
+    Text(String),
+    Blob(Vec<u8>),
+}
+
+/// An entry as it is exported and imported. Text values written with a TTL
+/// keep their expiry time, in nanoseconds since the epoch.
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct ExportedEntry {
+    pub key: String,
+    pub value: ExportedValue,
+    pub expires_at: Option<u64>,
+}
+
+/// Where an export goes on: the first key not exported yet, among the text
+/// entries or, once those are done, the blobs.
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct ExportCursor {
+    pub blobs: bool,
+    pub key: String,
+}
+
+/// A principal's grant on a collection. Grants are ordered by collection, so
+/// the grants of one collection form a contiguous range of the map.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+struct GrantKey {
+    collection: CollectionKey,
+    principal: Principal,
+}
+
+/// A grant ordered by principal, so the grants of one principal form a
+/// contiguous range of the map.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+struct PrincipalGrantKey {
+    principal: Principal,
+    collection: CollectionKey,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+struct ExpiryKey {
+    expires_at: u64,
+    entry: EntryKey,
+}
+
+/// An entry of an index. Index entries are ordered by index, then value,
+/// so the keys an index maps a value to form a contiguous range of the map.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+struct IndexEntryKey {
+    /// The collection and name of the index.
+    index: EntryKey,
+    value: String,
+    key: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+struct ChangeKey {
+    collection: CollectionKey,
+    seq: u64,
+}
+
+fn write_principal(bytes: &mut Vec<u8>, principal: &Principal) {
+    let principal = principal.as_slice();
+    bytes.push(principal.len() as u8);
+    bytes.extend_from_slice(principal);
+}
+
+fn read_principal(bytes: &[u8]) -> (Principal, &[u8]) {
+    let len = bytes[0] as usize;
+    (Principal::from_slice(&bytes[1..1 + len]), &bytes[1 + len..])
+}
+
+impl Storable for CollectionKey {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        let mut bytes = Vec::with_capacity(1 + Principal::MAX_LENGTH_IN_BYTES + self.name.len());
+        write_principal(&mut bytes, &self.owner);
+        bytes.extend_from_slice(self.name.as_bytes());
+        Cow::Owned(bytes)
+    }
+
+    fn from_bytes(bytes: Cow<'_, [u8]>) -> Self {
+        let (owner, name) = read_principal(&bytes);
+        Self {
+            owner,
+            name: String::from_utf8(name.to_vec()).unwrap(),
+        }
+    }
+
+    const BOUND: StorableBound = StorableBound::Unbounded;
+}
+
+impl Storable for EntryKey {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        let name = self.collection.name.as_bytes();
+        let mut bytes = Vec::with_capacity(
+            1 + Principal::MAX_LENGTH_IN_BYTES + 4 + name.len() + self.key.len(),
+        );
+        write_principal(&mut bytes, &self.collection.owner);
+        bytes.extend_from_slice(&(name.len() as u32).to_be_bytes());
+        bytes.extend_from_slice(name);
+        bytes.extend_from_slice(self.key.as_bytes());
+        Cow::Owned(bytes)
+    }
+
+    fn from_bytes(bytes: Cow<'_, [u8]>) -> Self {
+        let (owner, rest) = read_principal(&bytes);
+        let name_len = u32::from_be_bytes(rest[..4].try_into().unwrap()) as usize;
+        let (name, key) = rest[4..].split_at(name_len);
+        Self {
+            collection: CollectionKey {
+                owner,
+                name: String::from_utf8(name.to_vec()).unwrap(),
+            },
+            key: String::from_utf8(key.to_vec()).unwrap(),
+        }
+    }
+
+    const BOUND: StorableBound = StorableBound::Unbounded;
+}
+
+impl Storable for GrantKey {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        let name = self.collection.name.as_bytes();
+        let mut bytes =
+            Vec::with_capacity(2 * (1 + Principal::MAX_LENGTH_IN_BYTES) + 4 + name.len());
+        write_principal(&mut bytes, &self.collection.owner);
+        bytes.extend_from_slice(&(name.len() as u32).to_be_bytes());
+        bytes.extend_from_slice(name);
+        write_principal(&mut bytes, &self.principal);
+        Cow::Owned(bytes)
+    }
+
+    fn from_bytes(bytes: Cow<'_, [u8]>) -> Self {
+        let (owner, rest) = read_principal(&bytes);
+        let name_len = u32::from_be_bytes(rest[..4].try_into().unwrap()) as usize;
+        let (name, rest) = rest[4..].split_at(name_len);
+        let (principal, _) = read_principal(rest);
+        Self {
+            collection: CollectionKey {
+                owner,
+                name: String::from_utf8(name.to_vec()).unwrap(),
+            },
+            principal,
+        }
+    }
+
+    const BOUND: StorableBound = StorableBound::Unbounded;
+}
+
+impl Storable for PrincipalGrantKey {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        let mut bytes = Vec::new();
+        write_principal(&mut bytes, &self.principal);
+        bytes.extend_from_slice(&self.collection.to_bytes());
+        Cow::Owned(bytes)
+    }
+
+    fn from_bytes(bytes: Cow<'_, [u8]>) -> Self {
+        let (principal, collection) = read_principal(&bytes);
+        Self {
+            principal,
+            collection: CollectionKey::from_bytes(Cow::Borrowed(collection)),
+        }
+    }
+
+    const BOUND: StorableBound = StorableBound::Unbounded;
+}
+
+impl Storable for ExpiryKey {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        let mut bytes = self.expires_at.to_be_bytes().to_vec();
+        bytes.extend_from_slice(&self.entry.to_bytes());
+        Cow::Owned(bytes)
+    }
+
+    fn from_bytes(bytes: Cow<'_, [u8]>) -> Self {
+        let (expires_at, entry) = bytes.split_at(8);
+        Self {
+            expires_at: u64::from_be_bytes(expires_at.try_into().unwrap()),
+            entry: EntryKey::from_bytes(Cow::Borrowed(entry)),
+        }
+    }
+
+    const BOUND: StorableBound = StorableBound::Unbounded;
+}
+
+impl Storable for ChangeKey {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        let mut bytes = self.collection.entry(String::new()).to_bytes().into_owned();
+        bytes.extend_from_slice(&self.seq.to_be_bytes());
+        Cow::Owned(bytes)
+    }
+
+    fn from_bytes(bytes: Cow<'_, [u8]>) -> Self {
+        let (collection, seq) = bytes.split_at(bytes.len() - 8);
+        Self {
+            collection: EntryKey::from_bytes(Cow::Borrowed(collection)).collection,
+            seq: u64::from_be_bytes(seq.try_into().unwrap()),
+        }
+    }
+
+    const BOUND: StorableBound = StorableBound::Unbounded;
+}
+
+impl Storable for IndexEntryKey {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        let mut bytes = Vec::new();
+        write_principal(&mut bytes, &self.index.collection.owner);
+        for part in [&self.index.collection.name, &self.index.key, &self.value] {
+            bytes.extend_from_slice(&(part.len() as u32).to_be_bytes());
+            bytes.extend_from_slice(part.as_bytes());
+        }
+        bytes.extend_from_slice(self.key.as_bytes());
+        Cow::Owned(bytes)
+    }
+
+    fn from_bytes(bytes: Cow<'_, [u8]>) -> Self {
+        let (owner, mut rest) = read_principal(&bytes);
+        let mut parts = Vec::with_capacity(3);
+        for _ in 0..3 {
+            let len = u32::from_be_bytes(rest[..4].try_into().unwrap()) as usize;
+            let (part, tail) = rest[4..].split_at(len);
+            parts.push(String::from_utf8(part.to_vec()).unwrap());
+            rest = tail;
+        }
+        let [name, index, value] = parts.try_into().unwrap();
+        Self {
+            index: EntryKey {
+                collection: CollectionKey { owner, name },
+                key: index,
+            },
+            value,
+            key: String::from_utf8(rest.to_vec()).unwrap(),
+        }
+    }
+
+    const BOUND: StorableBound = StorableBound::Unbounded;
+}
+
+impl Storable for Backfill {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        let (tag, last) = match self {
+            Self::Done => (0, None),
+            Self::Entries(last) => (1, last.as_ref().map(Storable::to_bytes)),
+            Self::Blobs(last) => (2, last.as_ref().map(Storable::to_bytes)),
+            Self::Collections(last) => (3, last.as_ref().map(Storable::to_bytes)),
+            Self::Grants(last) => (4, last.as_ref().map(Storable::to_bytes)),
+        };
+        let mut bytes = vec![tag];
+        if let Some(last) = last {
+            bytes.extend_from_slice(&last);
+        }
+        Cow::Owned(bytes)
+    }
+
+    fn from_bytes(bytes: Cow<'_, [u8]>) -> Self {
+        fn last<K: Storable>(bytes: &[u8]) -> Option<K> {
+            (bytes.len() > 1).then(|| K::from_bytes(Cow::Borrowed(&bytes[1..])))
+        }
+        match bytes[0] {
+            0 => Self::Done,
+            1 => Self::Entries(last(&bytes)),
+            2 => Self::Blobs(last(&bytes)),
+            3 => Self::Collections(last(&bytes)),
+            _ => Self::Grants(last(&bytes)),
+        }
+    }
+
+    const BOUND: StorableBound = StorableBound::Unbounded;
+}
+
+impl Storable for LoggedChange {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        let (kind, version) = match self.kind {
+            ChangeKind::Set { version } => (0, version),
+            ChangeKind::Removed => (1, 0),
+            ChangeKind::BlobSet => (2, 0),
+            ChangeKind::BlobRemoved => (3, 0),
+        };
+        let mut bytes = Vec::with_capacity(9 + self.key.len());
+        bytes.push(kind);
+        bytes.extend_from_slice(&version.to_be_bytes());
+        bytes.extend_from_slice(self.key.as_bytes());
+        Cow::Owned(bytes)
+    }
+
+    fn from_bytes(bytes: Cow<'_, [u8]>) -> Self {
+        let version = u64::from_be_bytes(bytes[1..9].try_into().unwrap());
+        let kind = match bytes[0] {
+            0 => ChangeKind::Set { version },
+            1 => ChangeKind::Removed,
+            2 => ChangeKind::BlobSet,
+            _ => ChangeKind::BlobRemoved,
+        };
+        Self {
+            key: String::from_utf8(bytes[9..].to_vec()).unwrap(),
+            kind,
+        }
+    }
+
+    const BOUND: StorableBound = StorableBound::Unbounded;
+}
+
+#[cfg(target_arch = "wasm32")]
+fn now() -> u64 {
+    ic_cdk::api::time()
+}
+
+/// Outside of a canister, as in unit tests, the system clock stands in for
+/// the IC's time.
+#[cfg(not(target_arch = "wasm32"))]
+fn now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_nanos() as u64)
+        .unwrap_or_default()
+}
+
+/// Whether the entry was written with a TTL that has run out. Expired
+/// entries are hidden until [`remove_expired`] gets to them.
+fn is_expired(key: &EntryKey, now: u64) -> bool {
+    EXPIRIES.with(|expiries| {
+        expiries
+            .borrow()
+            .get(key)
+            .is_some_and(|expires_at| expires_at <= now)
+    })
+}
+
+pub fn list_collections(owner: Principal) -> Vec<String> {
+    COLLECTIONS.with(|collections| {
+        collections
+            .borrow()
+            .range(CollectionKey::new(owner, String::new())..)
+            .take_while(|(collection, _)| collection.owner == owner)
+            .map(|(collection, _)| collection.name)
+            .collect()
+    })
+}
+
+pub fn has_collection(collection: &CollectionKey) -> bool {
+    COLLECTIONS.with(|collections| collections.borrow().contains_key(collection))
+}
+
+/// Creates an empty collection with `indexes`, replacing any existing one of
+/// the same name.
+pub fn create_collection(collection: CollectionKey, indexes: Vec<Index>) {
+    let replaced = has_collection(&collection);
+    clear_entries(&collection);
+    if replaced {
+        clear_changes(&collection);
+    }
+    clear_indexes(&collection);
+    clear_grants(&collection);
+    if !replaced && counted_collection(&collection) {
+        add_stats(collection.owner, (1, 0, 0));
+    }
+    INDEXES.with(|defined| {
+        let mut defined = defined.borrow_mut();
+        for index in indexes {
+            defined.insert(collection.entry(index.name), index.path);
+        }
+    });
+    COLLECTIONS.with(|collections| collections.borrow_mut().insert(collection, ()));
+}
+
+pub fn delete_collection(collection: &CollectionKey) {
+    clear_entries(collection);
+    clear_indexes(collection);
+    clear_changes(collection);
+    clear_grants(collection);
+    let (bytes, entries) = USAGE
+        .with(|usage| usage.borrow_mut().remove(collection))
+        .unwrap_or((0, 0));
+    let removed = COLLECTIONS
+        .with(|collections| collections.borrow_mut().remove(collection))
+        .is_some();
+    let collections = (removed && counted_collection(collection)) as i64;
+    add_stats(
+        collection.owner,
+        (-collections, -(bytes as i64), -(entries as i64)),
+    );
+}
+
+fn counted_collection(collection: &CollectionKey) -> bool {
+    BACKFILL.with(|backfill| backfill.borrow().get().counted_collection(collection))
+}
+
+/// The role `principal` has on a collection, if any.
+pub fn role(collection: &CollectionKey, principal: Principal) -> Option<Role> {
+    if principal == collection.owner {
+        return Some(Role::Admin);
+    }
+
+    GRANTS.with(|grants| {
+        grants
+            .borrow()
+            .get(&GrantKey {
+                collection: collection.clone(),
+                principal,
+            })
+            .map(Role::from_u8)
+    })
+}
+
+/// Gives `principal` `role` on a collection, or revokes its access if `role`
+/// is `None`.
+pub fn grant(collection: &CollectionKey, principal: Principal, role: Option<Role>) {
+    let key = GrantKey {
+        collection: collection.clone(),
+        principal,
+    };
+    let granted = PrincipalGrantKey {
+        principal,
+        collection: collection.clone(),
+    };
+    GRANTED.with(|granted_map| {
+        let mut granted_map = granted_map.borrow_mut();
+        match role {
+            Some(_) => granted_map.insert(granted, ()),
+            None => granted_map.remove(&granted),
+        }
+    });
+    GRANTS.with(|grants| {
+        let mut grants = grants.borrow_mut();
+        match role {
+            Some(role) => grants.insert(key, role as u8),
+            None => grants.remove(&key),
+        }
+    });
+}
+
+/// The principals other than the owner that have access to a collection.
+pub fn grants(collection: &CollectionKey) -> Vec<(Principal, Role)> {
+    GRANTS.with(|grants| {
+        grants
+            .borrow()
+            .range(
+                GrantKey {
+                    collection: collection.clone(),
+                    principal: Principal::from_slice(&[]),
+                }..,
+            )
+            .take_while(|(grant, _)| &grant.collection == collection)
+            .map(|(grant, role)| (grant.principal, Role::from_u8(role)))
+            .collect()
+    })
+}
+
+fn clear_grants(collection: &CollectionKey) {
+    for (principal, _) in grants(collection) {
+        grant(collection, principal, None);
+    }
+}
+
+pub fn get(collection: &CollectionKey, key: String) -> Option<String> {
+    get_versioned(collection, key).map(|(value, _)| value)
+}
+
+/// Inserts `value` under `key`, or removes the key if `value` is `None`.
+/// A value written with a TTL expires `ttl_seconds` from now, and one
+/// written without a TTL clears any the entry had, as do all other writes.
+/// Does nothing if the collection does not exist.
+pub fn set(
+    collection: &CollectionKey,
+    key: String,
+    value: Option<String>,
+    ttl_seconds: Option<u64>,
+) -> Result<(), QuotaExceeded> {
+    if !has_collection(collection) {
+        return Ok(());
+    }
+
+    let key = collection.entry(key);
+    check_quota(
+        collection.owner,
+        size_change(&key.key, text_len(&key), value.as_ref().map(String::len)),
+    )?;
+    let expires_at = ttl_seconds.map(|ttl| now().saturating_add(ttl.saturating_mul(1_000_000_000)));
+    write(key, value, expires_at);
+    Ok(())
+}
+
+/// The value of `key` and its version.
+pub fn get_versioned(collection: &CollectionKey, key: String) -> Option<(String, u64)> {
+    let key = collection.entry(key);
+    if is_expired(&key, now()) {
+        return None;
+    }
+    let value = ENTRIES.with(|entries| entries.borrow().get(&key))?;
+    Some((value, version(&key)))
+}
+
+fn version(key: &EntryKey) -> u64 {
+    VERSIONS.with(|versions| versions.borrow().get(key).unwrap_or(0))
+}
+
+/// Writes `value` under `key`, or removes the key if `value` is `None`, but
+/// only if the entry's current version is `expected_version`. A `None`
+/// expected version means the key must not exist.
+pub fn compare_and_swap(
+    collection: &CollectionKey,
+    key: String,
+    expected_version: Option<u64>,
+    value: Option<String>,
+) -> Swap {
+    if !has_collection(collection) {
+        return Swap::CollectionNotFound;
+    }
+
+    let current = get_versioned(collection, key.clone());
+    if current.as_ref().map(|(_, version)| *version) != expected_version {
+        return Swap::Conflict(current);
+    }
+
+    let key = collection.entry(key);
+    let change = size_change(&key.key, text_len(&key), value.as_ref().map(String::len));
+    if let Err(exceeded) = check_quota(collection.owner, change) {
+        return Swap::QuotaExceeded(exceeded);
+    }
+    Swap::Swapped(write(key, value, None))
+}
+
+/// Writes or removes a single entry, returning its new version. Entries
+/// written without an expiry time never expire.
+fn write(key: EntryKey, value: Option<String>, expires_at: Option<u64>) -> Option<u64> {
+    let Some(value) = value else {
+        remove(&key);
+        return None;
+    };
+
+    let old = ENTRIES.with(|entries| entries.borrow().get(&key));
+    set_expiry(&key, expires_at);
+    update_usage(
+        &key,
+        false,
+        old.as_ref().map(String::len),
+        Some(value.len()),
+    );
+    update_indexes(&key, old.as_deref(), Some(&value));
+    let version = NEXT_VERSION.with(|next| {
+        let mut next = next.borrow_mut();
+        let version = *next.get();
+        next.set(version + 1)
+            .expect("failed to update the version counter");
+        version
+    });
+    VERSIONS.with(|versions| versions.borrow_mut().insert(key.clone(), version));
+    log_change(&key, ChangeKind::Set { version });
+    ENTRIES.with(|entries| entries.borrow_mut().insert(key, value));
+    Some(version)
+}
+
+fn remove(key: &EntryKey) {
+    let Some(old) = ENTRIES.with(|entries| entries.borrow().get(key)) else {
+        return;
+    };
+    log_change(key, ChangeKind::Removed);
+    update_usage(key, false, Some(old.len()), None);
+    update_indexes(key, Some(&old), None);
+    set_expiry(key, None);
+    VERSIONS.with(|versions| versions.borrow_mut().remove(key));
+    ENTRIES.with(|entries| entries.borrow_mut().remove(key));
+}
+
+fn set_expiry(key: &EntryKey, expires_at: Option<u64>) {
+    let previous = EXPIRIES.with(|expiries| {
+        let mut expiries = expiries.borrow_mut();
+        match expires_at {
+            Some(expires_at) => expiries.insert(key.clone(), expires_at),
+            None => expiries.remove(key),
+        }
+    });
+
+    EXPIRY_QUEUE.with(|queue| {
+        let mut queue = queue.borrow_mut();
+        if let Some(expires_at) = previous {
+            queue.remove(&ExpiryKey {
+                expires_at,
+                entry: key.clone(),
+            });
+        }
+        if let Some(expires_at) = expires_at {
+            queue.insert(
+                ExpiryKey {
+                    expires_at,
+                    entry: key.clone(),
+                },
+                (),
+            );
+        }
+    });
+}
+
+/// Removes up to `limit` expired entries, oldest first. Returns whether
+/// there are more left to remove.
+pub fn remove_expired(limit: usize) -> bool {
+    let now = now();
+    let expired = EXPIRY_QUEUE.with(|queue| {
+        queue
+            .borrow()
+            .iter()
+            .take_while(|(expiry, _)| expiry.expires_at <= now)
+            .take(limit + 1)
+            .map(|(expiry, _)| expiry.entry)
+            .collect::<Vec<_>>()
+    });
+
+    let more = expired.len() > limit;
+    for key in expired.iter().take(limit) {
+        remove(key);
+    }
+    more
+}
+
+pub fn get_many(collection: &CollectionKey, keys: Vec<String>) -> Vec<Option<String>> {
+    keys.into_iter().map(|key| get(collection, key)).collect()
+}
+
+/// Applies each change like [`set`]. Since the canister never awaits while
+/// applying them, other calls see either none or all of the changes.
+pub fn set_many(
+    collection: &CollectionKey,
+    changes: Vec<(String, Option<String>)>,
+) -> Result<(), QuotaExceeded> {
+    if !has_collection(collection) {
+        return Ok(());
+    }
+
+    // Later changes to the same key replace the earlier ones
+    let mut lengths = BTreeMap::new();
+    let mut change = (0, 0);
+    for (key, value) in &changes {
+        let old = match lengths.get(key) {
+            Some(len) => *len,
+            None => text_len(&collection.entry(key.clone())),
+        };
+        let new = value.as_ref().map(String::len);
+        let (bytes, entries) = size_change(key, old, new);
+        change = (change.0 + bytes, change.1 + entries);
+        lengths.insert(key, new);
+    }
+    check_quota(collection.owner, change)?;
+
+    for (key, value) in changes {
+        write(collection.entry(key), value, None);
+    }
+    Ok(())
+}
+
+/// Applies `ops` to collections of `owner` if all of their checks pass.
+/// Checks see the entries as they were before the transaction. Since the
+/// canister never awaits while applying them, other calls see either none
+/// or all of the operations.
+pub fn transact(owner: Principal, ops: Vec<Op>) -> Transaction {
+    if let Some(op) = ops
+        .iter()
+        .find(|op| !has_collection(&CollectionKey::new(owner, op.collection().clone())))
+    {
+        return Transaction::CollectionNotFound(op.collection().clone());
+    }
+
+    for (i, op) in ops.iter().enumerate() {
+        if let Op::Check {
+            collection,
+            key,
+            version,
+        } = op
+        {
+            let current =
+                get_versioned(&CollectionKey::new(owner, collection.clone()), key.clone());
+            if current.as_ref().map(|(_, version)| *version) != *version {
+                return Transaction::Conflict(i, current);
+            }
+        }
+    }
+
+    // Later operations on the same key replace the earlier ones
+    let mut lengths = BTreeMap::new();
+    let mut change = (0, 0);
+    for op in &ops {
+        let (collection, key, new) = match op {
+            Op::Put {
+                collection,
+                key,
+                value,
+            } => (collection, key, Some(value.len())),
+            Op::Delete { collection, key } => (collection, key, None),
+            Op::Check { .. } => continue,
+        };
+        let old = match lengths.get(&(collection, key)) {
+            Some(len) => *len,
+            None => text_len(&CollectionKey::new(owner, collection.clone()).entry(key.clone())),
+        };
+        let (bytes, entries) = size_change(key, old, new);
+        change = (change.0 + bytes, change.1 + entries);
+        lengths.insert((collection, key), new);
+    }
+    if let Err(exceeded) = check_quota(owner, change) {
+        return Transaction::QuotaExceeded(exceeded);
+    }
+
+    Transaction::Committed(
+        ops.into_iter()
+            .map(|op| match op {
+                Op::Put {
+                    collection,
+                    key,
+                    value,
+                } => write(
+                    CollectionKey::new(owner, collection).entry(key),
+                    Some(value),
+                    None,
+                ),
+                Op::Delete { collection, key } => {
+                    write(CollectionKey::new(owner, collection).entry(key), None, None)
+                }
+                Op::Check { .. } => None,
+            })
+            .collect(),
+    )
+}
+
+pub fn get_blob(collection: &CollectionKey, key: String) -> Option<Vec<u8>> {
+    BLOBS.with(|blobs| blobs.borrow().get(&collection.entry(key)))
+}
+
+/// Stores `value` under `key`, or removes the blob if `value` is `None`.
+/// Does nothing if the collection does not exist.
+pub fn set_blob(
+    collection: &CollectionKey,
+    key: String,
+    value: Option<Vec<u8>>,
+) -> Result<(), QuotaExceeded> {
+    if !has_collection(collection) {
+        return Ok(());
+    }
+
+    let key = collection.entry(key);
+    let old = blob_len(&key);
+    let new = value.as_ref().map(Vec::len);
+    check_quota(collection.owner, size_change(&key.key, old, new))?;
+    write_blob(key, value);
+    Ok(())
+}
+
+fn write_blob(key: EntryKey, value: Option<Vec<u8>>) {
+    let old = blob_len(&key);
+    match value {
+        Some(_) => log_change(&key, ChangeKind::BlobSet),
+        None if old.is_some() => log_change(&key, ChangeKind::BlobRemoved),
+        None => return,
+    }
+    update_usage(&key, true, old, value.as_ref().map(Vec::len));
+    BLOBS.with(|blobs| {
+        let mut blobs = blobs.borrow_mut();
+        match value {
+            Some(value) => blobs.insert(key, value),
+            None => blobs.remove(&key),
+        }
+    });
+}
+
+/// Appends a change to the log of the entry's collection, dropping the
+/// oldest change once the log is full.
+fn log_change(key: &EntryKey, kind: ChangeKind) {
+    let collection = &key.collection;
+    let seq = SEQUENCES.with(|sequences| {
+        let mut sequences = sequences.borrow_mut();
+        let seq = sequences.get(collection).unwrap_or(0) + 1;
+        sequences.insert(collection.clone(), seq);
+        seq
+    });
+
+    CHANGES.with(|changes| {
+        let mut changes = changes.borrow_mut();
+        changes.insert(
+            ChangeKey {
+                collection: collection.clone(),
+                seq,
+            },
+            LoggedChange {
+                key: key.key.clone(),
+                kind,
+            },
+        );
+        if seq > MAX_CHANGES {
+            changes.remove(&ChangeKey {
+                collection: collection.clone(),
+                seq: seq - MAX_CHANGES,
+            });
+        }
+    });
+}
+
+/// Up to `limit` changes of a collection made after change `seq`, along
+/// with the sequence number of its latest change. The flag is set if some
+/// changes after `seq` are no longer in the log, or `seq` is from before the
+/// collection was deleted or replaced, so readers have to reload the
+/// collection. Reading from 0 is only complete if neither happened yet.
+pub fn changes_since(
+    collection: &CollectionKey,
+    seq: u64,
+    limit: usize,
+) -> (Vec<Change>, u64, bool) {
+    let last_seq = SEQUENCES.with(|sequences| sequences.borrow().get(collection).unwrap_or(0));
+    let reset_seq = RESETS.with(|resets| resets.borrow().get(collection).unwrap_or(0));
+    let oldest_seq = last_seq.saturating_sub(MAX_CHANGES).max(reset_seq) + 1;
+    let truncated = seq > last_seq || seq + 1 < oldest_seq;
+
+    let changes = CHANGES.with(|changes| {
+        changes
+            .borrow()
+            .range(
+                ChangeKey {
+                    collection: collection.clone(),
+                    seq: seq.saturating_add(1),
+                }..,
+            )
+            .take_while(|(change, _)| &change.collection == collection)
+            .take(limit)
+            .map(|(change, logged)| Change {
+                seq: change.seq,
+                key: logged.key,
+                kind: logged.kind,
+            })
+            .collect()
+    });
+    (changes, last_seq, truncated)
+}
+
+/// Empties the log of a collection. Its sequence goes on from where it was,
+/// skipping one number to mark the reset.
+fn clear_changes(collection: &CollectionKey) {
+    let (changes, _, _) = changes_since(collection, 0, usize::MAX);
+    CHANGES.with(|log| {
+        let mut log = log.borrow_mut();
+        for change in changes {
+            log.remove(&ChangeKey {
+                collection: collection.clone(),
+                seq: change.seq,
+            });
+        }
+    });
+    let seq = SEQUENCES.with(|sequences| {
+        let mut sequences = sequences.borrow_mut();
+        let seq = sequences.get(collection).unwrap_or(0) + 1;
+        sequences.insert(collection.clone(), seq);
+        seq
+    });
+    RESETS.with(|resets| resets.borrow_mut().insert(collection.clone(), seq));
+}
+
+pub fn indexes(collection: &CollectionKey) -> Vec<Index> {
+    INDEXES.with(|indexes| {
+        indexes
+            .borrow()
+            .range(collection.entry(String::new())..)
+            .take_while(|(index, _)| &index.collection == collection)
+            .map(|(index, path)| Index {
+                name: index.key,
+                path,
+            })
+            .collect()
+    })
+}
+
+/// What an index on `path` maps a JSON value to, if anything.
+fn indexed_value(value: &serde_json::Value, path: &str) -> Option<String> {
+    let field = path
+        .split('.')
+        .filter(|part| !part.is_empty())
+        .try_fold(value, |value, part| value.get(part))?;
+    match field {
+        serde_json::Value::String(text) => Some(text.clone()),
+        serde_json::Value::Number(number) => Some(index_number(number)),
+        serde_json::Value::Bool(_) => Some(field.to_string()),
+        _ => None,
+    }
+}
+
+/// How indexes store a number. Whole numbers written as floats, such as
+/// `1.0`, are stored like integers, so `1` finds them. Clients compute the
+/// same with `MuKvIndex::lookup_value` in mu-sdk.
+fn index_number(number: &serde_json::Number) -> String {
+    match number.as_f64() {
+        Some(float) if number.is_f64() && float.fract() == 0.0 && float.abs() < 2f64.powi(53) => {
+            (float as i64).to_string()
+        }
+        _ => number.to_string(),
+    }
+}
+
+/// Moves the entry to the right place in every index of its collection
+/// after its value changed from `old` to `new`.
+fn update_indexes(key: &EntryKey, old: Option<&str>, new: Option<&str>) {
+    let indexes = indexes(&key.collection);
+    if indexes.is_empty() {
+        return;
+    }
+
+    let parse = |value: Option<&str>| value.and_then(|value| serde_json::from_str(value).ok());
+    let (old, new): (Option<serde_json::Value>, Option<serde_json::Value>) =
+        (parse(old), parse(new));
+    INDEX_ENTRIES.with(|entries| {
+        let mut entries = entries.borrow_mut();
+        for Index { name, path } in indexes {
+            let old = old.as_ref().and_then(|value| indexed_value(value, &path));
+            let new = new.as_ref().and_then(|value| indexed_value(value, &path));
+            if old == new {
+                continue;
+            }
+
+            let entry = |value| IndexEntryKey {
+                index: key.collection.entry(name.clone()),
+                value,
+                key: key.key.clone(),
+            };
+            if let Some(old) = old {
+                entries.remove(&entry(old));
+            }
+            if let Some(new) = new {
+                entries.insert(entry(new), ());
+            }
+        }
+    });
+}
+
+/// Up to `limit` entries that `index` maps `value` to, in key order and
+/// after `start_after` if given, along with the key the next page starts at
+/// if there are more. `None` if the collection has no such index.
+pub fn index_lookup(
+    collection: &CollectionKey,
+    index: String,
+    value: String,
+    start_after: Option<String>,
+    limit: usize,
+) -> Option<EntryPage> {
+    let index = collection.entry(index);
+    if !INDEXES.with(|indexes| indexes.borrow().contains_key(&index)) {
+        return None;
+    }
+
+    let now = now();
+    INDEX_ENTRIES.with(|index_entries| {
+        let index_entries = index_entries.borrow();
+        let mut keys = index_entries
+            .range(
+                IndexEntryKey {
+                    index: index.clone(),
+                    value: value.clone(),
+                    key: start_after.clone().unwrap_or_default(),
+                }..,
+            )
+            .take_while(|(entry, _)| entry.index == index && entry.value == value)
+            .filter(|(entry, _)| Some(&entry.key) != start_after.as_ref())
+            .map(|(entry, _)| collection.entry(entry.key))
+            .filter(|key| !is_expired(key, now))
+            .filter_map(|key| {
+                let value = ENTRIES.with(|entries| entries.borrow().get(&key))?;
+                Some((key.key, value))
+            });
+
+        let mut found = Vec::new();
+        let mut bytes = 0;
+        while found.len() < limit && (found.is_empty() || bytes < MAX_PAGE_BYTES) {
+            let Some((key, value)) = keys.next() else {
+                break;
+            };
+            bytes += key.len() + value.len();
+            found.push((key, value));
+        }
+
+        let next = keys.next().map(|(key, _)| key);
+        Some((found, next))
+    })
+}
+
+/// Removes the indexes of a collection. Their entries go along with the
+/// collection's entries, so those have to be cleared first.
+fn clear_indexes(collection: &CollectionKey) {
+    let indexes = indexes(collection);
+    INDEXES.with(|defined| {
+        let mut defined = defined.borrow_mut();
+        for index in indexes {
+            defined.remove(&collection.entry(index.name));
+        }
+    });
+}
+
+/// Up to `limit` entries of a collection from `from` on, the text entries
+/// first and then the blobs, along with the cursor to continue from if
+/// there are more. Expired entries are left out.
+pub fn export(
+    collection: &CollectionKey,
+    from: Option<ExportCursor>,
+    limit: usize,
+) -> (Vec<ExportedEntry>, Option<ExportCursor>) {
+    let mut page = Vec::new();
+    let now = now();
+
+    let blobs_from = match from {
+        Some(ExportCursor { blobs: true, key }) => key,
+        from => {
+            let next = ENTRIES.with(|entries| {
+                export_map(
+                    &entries.borrow(),
+                    collection,
+                    from.map(|cursor| cursor.key).unwrap_or_default(),
+                    limit,
+                    &mut page,
+                    |key, value| {
+                        let expires_at = EXPIRIES.with(|expiries| expiries.borrow().get(key));
+                        (expires_at.is_none_or(|expires_at| expires_at > now))
+                            .then_some((ExportedValue::Text(value), expires_at))
+                    },
+                )
+            });
+            if let Some(key) = next {
+                return (page, Some(ExportCursor { blobs: false, key }));
+            }
+            String::new()
+        }
+    };
+
+    let next = BLOBS.with(|blobs| {
+        export_map(
+            &blobs.borrow(),
+            collection,
+            blobs_from,
+            limit,
+            &mut page,
+            |_, value| Some((ExportedValue::Blob(value), None)),
+        )
+    });
+    (page, next.map(|key| ExportCursor { blobs: true, key }))
+}
+
+/// Adds the entries of the collection in `map` from `from` on to `page`
+/// until it is full, returning the key of the first entry left over, if
+/// any. `export` turns values into exported ones, or skips them by
+/// returning `None`.
+fn export_map<V: Storable>(
+    map: &StableBTreeMap<EntryKey, V, Memory>,
+    collection: &CollectionKey,
+    from: String,
+    limit: usize,
+    page: &mut Vec<ExportedEntry>,
+    export: impl Fn(&EntryKey, V) -> Option<(ExportedValue, Option<u64>)>,
+) -> Option<String> {
+    let mut matching = map
+        .range(collection.entry(from)..)
+        .take_while(|(entry, _)| &entry.collection == collection)
+        .filter_map(|(entry, value)| {
+            let (value, expires_at) = export(&entry, value)?;
+            Some(ExportedEntry {
+                key: entry.key,
+                value,
+                expires_at,
+            })
+        });
+
+    let size = |entry: &ExportedEntry| {
+        entry.key.len()
+            + match &entry.value {
+                ExportedValue::Text(value) => value.len(),
+                ExportedValue::Blob(value) => value.len(),
+            }
+    };
+    let mut bytes = page.iter().map(size).sum::<usize>();
+    while page.len() < limit && (page.is_empty() || bytes < MAX_PAGE_BYTES) {
+        let entry = matching.next()?;
+        bytes += size(&entry);
+        page.push(entry);
+    }
+
+    matching.next().map(|entry| entry.key)
+}
+
+/// Writes exported entries into a collection, creating it with `indexes`
+/// if it does not exist. Entries replace existing ones with the same key,
+/// and get new versions. Entries that expired since they were exported
+/// are skipped.
+pub fn import(
+    collection: &CollectionKey,
+    indexes: Vec<Index>,
+    entries: Vec<ExportedEntry>,
+) -> Result<(), QuotaExceeded> {
+    let now = now();
+    let entries = entries
+        .into_iter()
+        .filter(|entry| entry.expires_at.is_none_or(|expires_at| expires_at > now))
+        .collect::<Vec<_>>();
+
+    // Later entries with the same key replace the earlier ones
+    let mut lengths = BTreeMap::new();
+    let mut change = (0, 0);
+    for entry in &entries {
+        let key = collection.entry(entry.key.clone());
+        let (blob, new) = match &entry.value {
+            ExportedValue::Text(value) => (false, value.len()),
+            ExportedValue::Blob(value) => (true, value.len()),
+        };
+        let old = match lengths.get(&(blob, &entry.key)) {
+            Some(len) => Some(*len),
+            None if blob => blob_len(&key),
+            None => text_len(&key),
+        };
+        let (bytes, entries) = size_change(&entry.key, old, Some(new));
+        change = (change.0 + bytes, change.1 + entries);
+        lengths.insert((blob, &entry.key), new);
+    }
+    check_quota(collection.owner, change)?;
+
+    if !has_collection(collection) {
+        create_collection(collection.clone(), indexes);
+    }
+    for entry in entries {
+        let key = collection.entry(entry.key);
+        match entry.value {
+            ExportedValue::Text(value) => {
+                write(key, Some(value), entry.expires_at);
+            }
+            ExportedValue::Blob(value) => write_blob(key, Some(value)),
+        }
+    }
+    Ok(())
+}
+
+fn text_len(key: &EntryKey) -> Option<usize> {
+    ENTRIES.with(|entries| entries.borrow().get(key).map(|value| value.len()))
+}
+
+fn blob_len(key: &EntryKey) -> Option<usize> {
+    BLOBS.with(|blobs| blobs.borrow().get(key).map(|value| value.len()))
+}
+
+/// How replacing a value of length `old` with one of length `new` changes
+/// the stored bytes and entries. `None` lengths stand for missing values.
+fn size_change(key: &str, old: Option<usize>, new: Option<usize>) -> (i64, i64) {
+    let size = |len: Option<usize>| len.map_or(0, |len| (key.len() + len) as i64);
+    (
+        size(new) - size(old),
+        new.is_some() as i64 - old.is_some() as i64,
+    )
+}
+
+fn apply_change(value: u64, change: i64) -> u64 {
+    value.saturating_add_signed(change)
+}
+
+/// Updates the usage of the collection of the text entry or blob at `key`,
+/// unless a running backfill has yet to count it.
+fn update_usage(key: &EntryKey, blob: bool, old: Option<usize>, new: Option<usize>) {
+    if BACKFILL.with(|backfill| backfill.borrow().get().counted(key, blob)) {
+        add_usage(&key.collection, size_change(&key.key, old, new));
+    }
+}
+
+fn add_usage(collection: &CollectionKey, (bytes, entries): (i64, i64)) {
+    add_stats(collection.owner, (0, bytes, entries));
+    USAGE.with(|usage| {
+        let mut usage = usage.borrow_mut();
+        let (total_bytes, total_entries) = usage.get(collection).unwrap_or((0, 0));
+        usage.insert(
+            collection.clone(),
+            (
+                apply_change(total_bytes, bytes),
+                apply_change(total_entries, entries),
+            ),
+        );
+    });
+}
+
+/// Adds `change` to the collections, bytes and entries of `owner` and to the
+/// totals.
+fn add_stats(owner: Principal, change: (i64, i64, i64)) {
+    let apply = |(collections, bytes, entries): (u64, u64, u64)| {
+        (
+            apply_change(collections, change.0),
+            apply_change(bytes, change.1),
+            apply_change(entries, change.2),
+        )
+    };
+    if change == (0, 0, 0) {
+        return;
+    }
+
+    PRINCIPAL_STATS.with(|stats| {
+        let mut stats = stats.borrow_mut();
+        match apply(stats.get(&owner).unwrap_or((0, 0, 0))) {
+            (0, 0, 0) => stats.remove(&owner),
+            updated => stats.insert(owner, updated),
+        }
+    });
+    TOTALS.with(|totals| {
+        let mut totals = totals.borrow_mut();
+        let updated = apply(*totals.get());
+        totals.set(updated).expect("failed to update the totals");
+    });
+}
+
+/// Starts counting the usage and stats of every collection if none are
+/// recorded, as is the case for stores written before they were tracked.
+/// Returns whether a backfill is running, including one an earlier upgrade
+/// started, so [`backfill`] has to be called.
+pub fn start_backfill() -> bool {
+    if backfilled()
+        && PRINCIPAL_STATS.with(|stats| stats.borrow().is_empty())
+        && !COLLECTIONS.with(|collections| collections.borrow().is_empty())
+    {
+        USAGE.with(|usage| usage.borrow_mut().clear_new());
+        GRANTED.with(|granted| granted.borrow_mut().clear_new());
+        TOTALS.with(|totals| {
+            totals
+                .borrow_mut()
+                .set((0, 0, 0))
+                .expect("failed to update the totals")
+        });
+        set_backfill(Backfill::Collections(None));
+    }
+    !backfilled()
+}
+
+/// Counts up to `limit` more collections, entries or blobs, or indexes as
+/// many grants. Returns whether there is more to do.
+pub fn backfill(limit: usize) -> bool {
+    let backfill = BACKFILL.with(|backfill| backfill.borrow().get().clone());
+    set_backfill(match backfill {
+        Backfill::Done => return false,
+        Backfill::Collections(last) => {
+            let collections = next_keys(&COLLECTIONS, last, limit);
+            for (collection, _) in &collections {
+                add_stats(collection.owner, (1, 0, 0));
+            }
+            advance(
+                collections,
+                limit,
+                Backfill::Collections,
+                Backfill::Entries(None),
+            )
+        }
+        Backfill::Entries(last) => {
+            let entries = next_keys(&ENTRIES, last, limit);
+            for (key, value) in &entries {
+                add_usage(
+                    &key.collection,
+                    size_change(&key.key, None, Some(value.len())),
+                );
+            }
+            advance(entries, limit, Backfill::Entries, Backfill::Blobs(None))
+        }
+        Backfill::Blobs(last) => {
+            let blobs = next_keys(&BLOBS, last, limit);
+            for (key, blob) in &blobs {
+                add_usage(
+                    &key.collection,
+                    size_change(&key.key, None, Some(blob.len())),
+                );
+            }
+            advance(blobs, limit, Backfill::Blobs, Backfill::Grants(None))
+        }
+        Backfill::Grants(last) => {
+            let grants = next_keys(&GRANTS, last, limit);
+            GRANTED.with(|granted| {
+                let mut granted = granted.borrow_mut();
+                for (grant, _) in &grants {
+                    granted.insert(
+                        PrincipalGrantKey {
+                            principal: grant.principal,
+                            collection: grant.collection.clone(),
+                        },
+                        (),
+                    );
+                }
+            });
+            advance(grants, limit, Backfill::Grants, Backfill::Done)
+        }
+    });
+    !backfilled()
+}
+
+/// Up to `limit` entries of `map` after `last`.
+fn next_keys<K: Storable + Ord + Clone, V: Storable>(
+    map: &'static LocalKey<RefCell<StableBTreeMap<K, V, Memory>>>,
+    last: Option<K>,
+    limit: usize,
+) -> Vec<(K, V)> {
+    let start = last.map_or(Bound::Unbounded, Bound::Excluded);
+    map.with(|map| {
+        map.borrow()
+            .range((start, Bound::Unbounded))
+            .take(limit)
+            .collect()
+    })
+}
+
+/// Where the backfill continues after `done`: at the last of them if the
+/// page was full, or at the next step otherwise.
+fn advance<K: Clone, V>(
+    done: Vec<(K, V)>,
+    limit: usize,
+    more: fn(Option<K>) -> Backfill,
+    next: Backfill,
+) -> Backfill {
+    if done.len() < limit {
+        next
+    } else {
+        more(done.last().map(|(key, _)| key.clone()))
+    }
+}
+
+fn set_backfill(backfill: Backfill) {
+    BACKFILL.with(|cell| {
+        cell.borrow_mut()
+            .set(backfill)
+            .expect("failed to update the backfill");
+    });
+}
+
+/// Whether the recorded usage, stats and grant index are complete. They are
+/// not while a backfill is still counting existing data.
+pub fn backfilled() -> bool {
+    BACKFILL.with(|backfill| *backfill.borrow().get() == Backfill::Done)
+}
+
+/// The bytes and entries stored in a collection.
+pub fn usage(collection: &CollectionKey) -> Usage {
+    let (bytes, entries) = USAGE.with(|usage| usage.borrow().get(collection).unwrap_or((0, 0)));
+    Usage { bytes, entries }
+}
+
+/// The bytes and entries stored across all collections of `owner`.
+pub fn owner_usage(owner: Principal) -> Usage {
+    USAGE.with(|usage| {
+        usage
+            .borrow()
+            .range(CollectionKey::new(owner, String::new())..)
+            .take_while(|(collection, _)| collection.owner == owner)
+            .fold(Usage::default(), |total, (_, (bytes, entries))| Usage {
+                bytes: total.bytes + bytes,
+                entries: total.entries + entries,
+            })
+    })
+}
+
+pub fn quota(owner: Principal) -> Quota {
+    QUOTAS
+        .with(|quotas| quotas.borrow().get(&owner))
+        .unwrap_or_else(|| DEFAULT_QUOTA_CELL.with(|quota| *quota.borrow().get()))
+        .into()
+}
+
+/// Sets the quota of `owner`, or the default quota if `owner` is `None`.
+/// A `None` quota resets it to the default.
+pub fn set_quota(owner: Option<Principal>, quota: Option<Quota>) {
+    match owner {
+        Some(owner) => QUOTAS.with(|quotas| {
+            let mut quotas = quotas.borrow_mut();
+            match quota {
+                Some(quota) => quotas.insert(owner, quota.into()),
+                None => quotas.remove(&owner),
+            };
+        }),
+        None => DEFAULT_QUOTA_CELL.with(|cell| {
+            cell.borrow_mut()
+                .set(quota.unwrap_or(DEFAULT_QUOTA).into())
+                .expect("failed to update the default quota");
+        }),
+    }
+}
+
+/// The number of collections and the usage of up to `limit` principals
+/// that own collections after `after`, and the last of them if there are
+/// more.
+pub fn principal_stats(
+    after: Option<Principal>,
+    limit: usize,
+) -> (Vec<(Principal, u64, Usage)>, Option<Principal>) {
+    let start = after.map_or(Bound::Unbounded, Bound::Excluded);
+    let mut stats = PRINCIPAL_STATS.with(|stats| {
+        stats
+            .borrow()
+            .range((start, Bound::Unbounded))
+            .take(limit + 1)
+            .map(|(principal, (collections, bytes, entries))| {
+                (principal, collections, Usage { bytes, entries })
+            })
+            .collect::<Vec<_>>()
+    });
+    let next = (stats.len() > limit).then(|| {
+        stats.truncate(limit);
+        stats[limit - 1].0
+    });
+    (stats, next)
+}
+
+/// The number of principals that own collections, and their collections
+/// and usage together.
+pub fn total_stats() -> (u64, u64, Usage) {
+    let principals = PRINCIPAL_STATS.with(|stats| stats.borrow().len());
+    let (collections, bytes, entries) = TOTALS.with(|totals| *totals.borrow().get());
+    (principals, collections, Usage { bytes, entries })
+}
+
+/// Deletes all collections of `principal` and its quota, and revokes the
+/// roles it was granted on other collections. Returns how many collections
+/// were deleted.
+pub fn purge(principal: Principal) -> u64 {
+    let names = list_collections(principal);
+    for name in &names {
+        delete_collection(&CollectionKey::new(principal, name.clone()));
+    }
+    set_quota(Some(principal), None);
+
+    let granted = GRANTED.with(|granted| {
+        granted
+            .borrow()
+            .range(
+                PrincipalGrantKey {
+                    principal,
+                    collection: CollectionKey::new(Principal::from_slice(&[]), String::new()),
+                }..,
+            )
+            .take_while(|(grant, _)| grant.principal == principal)
+            .map(|(grant, _)| grant.collection)
+            .collect::<Vec<_>>()
+    });
+    for collection in granted {
+        grant(&collection, principal, None);
+    }
+    names.len() as u64
+}
+
+/// Fails if growing the usage of `owner` by `change` would exceed its quota.
+/// Changes that do not add bytes or entries are always allowed, even if the
+/// owner is over a quota that was lowered. So is everything while a backfill
+/// is counting the usage, as it is not known yet.
+fn check_quota(owner: Principal, (bytes, entries): (i64, i64)) -> Result<(), QuotaExceeded> {
+    if (bytes <= 0 && entries <= 0) || !backfilled() {
+        return Ok(());
+    }
+
+    let quota = quota(owner);
+    let current = owner_usage(owner);
+    let usage = Usage {
+        bytes: apply_change(current.bytes, bytes),
+        entries: apply_change(current.entries, entries),
+    };
+    if (bytes > 0 && usage.bytes > quota.max_bytes)
+        || (entries > 0 && usage.entries > quota.max_entries)
+    {
+        return Err(QuotaExceeded { quota, usage });
+    }
+    Ok(())
+}
+
+/// All entries of a collection, in key order.
+pub fn entries(collection: &CollectionKey) -> Vec<(String, String)> {
+    let now = now();
+    ENTRIES.with(|entries| {
+        entries
+            .borrow()
+            .range(collection.entry(String::new())..)
+            .take_while(|(entry, _)| &entry.collection == collection)
+            .filter(|(entry, _)| !is_expired(entry, now))
+            .map(|(entry, value)| (entry.key, value))
+            .collect()
+    })
+}
+
+/// Up to `limit` entries of a collection starting at `start`, in key order,
+/// that are below `end` and start with `prefix`. Also returns the key the
+/// following page starts at, if there are more matching entries.
+pub fn page(
+    collection: &CollectionKey,
+    start: Bound<String>,
+    end: Bound<String>,
+    prefix: &str,
+    limit: usize,
+) -> EntryPage {
+    let start = match start {
+        Bound::Included(key) => Bound::Included(collection.entry(key)),
+        Bound::Excluded(key) => Bound::Excluded(collection.entry(key)),
+        Bound::Unbounded => Bound::Included(collection.entry(String::new())),
+    };
+
+    let now = now();
+    ENTRIES.with(|entries| {
+        let entries = entries.borrow();
+        let mut matching = entries
+            .range((start, Bound::Unbounded))
+            .take_while(|(entry, _)| {
+                &entry.collection == collection
+                    && entry.key.starts_with(prefix)
+                    && match &end {
+                        Bound::Included(end) => &entry.key <= end,
+                        Bound::Excluded(end) => &entry.key < end,
+                        Bound::Unbounded => true,
+                    }
+            })
+            .filter(|(entry, _)| !is_expired(entry, now))
+            .map(|(entry, value)| (entry.key, value));
+
+        let mut page = Vec::new();
+        let mut bytes = 0;
+        while page.len() < limit && (page.is_empty() || bytes < MAX_PAGE_BYTES) {
+            let Some((key, value)) = matching.next() else {
+                break;
+            };
+            bytes += key.len() + value.len();
+            page.push((key, value));
+        }
+
+        let next = matching.next().map(|(key, _)| key);
+        (page, next)
+    })
+}
+
+fn clear_entries(collection: &CollectionKey) {
+    let keys = ENTRIES.with(|entries| {
+        entries
+            .borrow()
+            .range(collection.entry(String::new())..)
+            .take_while(|(entry, _)| &entry.collection == collection)
+            .map(|(entry, _)| entry)
+            .collect::<Vec<_>>()
+    });
+    for key in keys {
+        remove(&key);
+    }
+
+    let blobs = BLOBS.with(|blobs| {
+        blobs
+            .borrow()
+            .range(collection.entry(String::new())..)
+            .take_while(|(entry, _)| &entry.collection == collection)
+            .map(|(entry, _)| entry)
+            .collect::<Vec<_>>()
+    });
+    for key in blobs {
+        write_blob(key, None);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn principal(id: u8) -> Principal {
+        Principal::from_slice(&[id])
+    }
+
+    fn collection(name: &str) -> CollectionKey {
+        let collection = CollectionKey::new(principal(1), name.to_owned());
+        if !has_collection(&collection) {
+            create_collection(collection.clone(), vec![]);
+        }
+        collection
+    }
+
+    /// Loads every structure from stable memory again, like a canister
+    /// upgrade does once the heap is wiped.
+    fn upgrade() {
+        let memory = MEMORY.with(|memory| memory.clone());
+        MEMORY_MANAGER.with(|m| *m.borrow_mut() = MemoryManager::init(memory));
+        let get = |id| MEMORY_MANAGER.with(|m| m.borrow().get(id));
+
+        COLLECTIONS.with(|m| *m.borrow_mut() = StableBTreeMap::init(get(COLLECTIONS_MEMORY_ID)));
+        ENTRIES.with(|m| *m.borrow_mut() = StableBTreeMap::init(get(ENTRIES_MEMORY_ID)));
+        VERSIONS.with(|m| *m.borrow_mut() = StableBTreeMap::init(get(VERSIONS_MEMORY_ID)));
+        NEXT_VERSION
+            .with(|m| *m.borrow_mut() = StableCell::init(get(NEXT_VERSION_MEMORY_ID), 1).unwrap());
+        GRANTS.with(|m| *m.borrow_mut() = StableBTreeMap::init(get(GRANTS_MEMORY_ID)));
+        EXPIRIES.with(|m| *m.borrow_mut() = StableBTreeMap::init(get(EXPIRIES_MEMORY_ID)));
+        EXPIRY_QUEUE.with(|m| *m.borrow_mut() = StableBTreeMap::init(get(EXPIRY_QUEUE_MEMORY_ID)));
+        BLOBS.with(|m| *m.borrow_mut() = StableBTreeMap::init(get(BLOBS_MEMORY_ID)));
+        USAGE.with(|m| *m.borrow_mut() = StableBTreeMap::init(get(USAGE_MEMORY_ID)));
+        QUOTAS.with(|m| *m.borrow_mut() = StableBTreeMap::init(get(QUOTAS_MEMORY_ID)));
+        DEFAULT_QUOTA_CELL.with(|m| {
+            *m.borrow_mut() =
+                StableCell::init(get(DEFAULT_QUOTA_MEMORY_ID), DEFAULT_QUOTA.into()).unwrap()
+        });
+        CHANGES.with(|m| *m.borrow_mut() = StableBTreeMap::init(get(CHANGES_MEMORY_ID)));
+        SEQUENCES.with(|m| *m.borrow_mut() = StableBTreeMap::init(get(SEQUENCES_MEMORY_ID)));
+        RESETS.with(|m| *m.borrow_mut() = StableBTreeMap::init(get(RESETS_MEMORY_ID)));
+        BACKFILL.with(|m| {
+            *m.borrow_mut() = StableCell::init(get(BACKFILL_MEMORY_ID), Backfill::Done).unwrap()
+        });
+        PRINCIPAL_STATS
+            .with(|m| *m.borrow_mut() = StableBTreeMap::init(get(PRINCIPAL_STATS_MEMORY_ID)));
+        TOTALS.with(|m| {
+            *m.borrow_mut() = StableCell::init(get(TOTALS_MEMORY_ID), (0, 0, 0)).unwrap()
+        });
+        GRANTED.with(|m| *m.borrow_mut() = StableBTreeMap::init(get(GRANTED_MEMORY_ID)));
+        INDEXES.with(|m| *m.borrow_mut() = StableBTreeMap::init(get(INDEXES_MEMORY_ID)));
+        INDEX_ENTRIES
+            .with(|m| *m.borrow_mut() = StableBTreeMap::init(get(INDEX_ENTRIES_MEMORY_ID)));
+    }
+
+    #[test]
+    fn data_survives_upgrades() {
+        let users = collection("users");
+        set(&users, "alice".into(), Some("admin".into()), None).unwrap();
+        set(&users, "bob".into(), Some("guest".into()), Some(3600)).unwrap();
+        set_blob(&users, "avatar".into(), Some(vec![1, 2, 3])).unwrap();
+        let version = get_versioned(&users, "alice".into()).unwrap().1;
+        grant(&users, principal(2), Some(Role::Read));
+        set_quota(Some(principal(1)), Some(Quota::from((1 << 20, 10))));
+
+        upgrade();
+
+        assert!(has_collection(&users));
+        assert_eq!(
+            entries(&users),
+            vec![
+                ("alice".to_owned(), "admin".to_owned()),
+                ("bob".to_owned(), "guest".to_owned())
+            ]
+        );
+        assert_eq!(
+            get_versioned(&users, "alice".into()),
+            Some(("admin".to_owned(), version))
+        );
+        assert_eq!(get_blob(&users, "avatar".into()), Some(vec![1, 2, 3]));
+        assert_eq!(role(&users, principal(2)), Some(Role::Read));
+        assert_eq!(quota(principal(1)).max_entries, 10);
+        assert_eq!(usage(&users).entries, 3);
+
+        // Versions keep increasing after the upgrade
+        set(&users, "carol".into(), Some("guest".into()), None).unwrap();
+        assert!(get_versioned(&users, "carol".into()).unwrap().1 > version);
+    }
+
+    fn pairs(entries: &[(&str, &str)]) -> Vec<(String, String)> {
+        entries
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn get_and_set() {
+        let users = collection("users");
+        assert_eq!(get(&users, "alice".into()), None);
+
+        set(&users, "alice".into(), Some("admin".into()), None).unwrap();
+        assert_eq!(get(&users, "alice".into()), Some("admin".to_owned()));
+
+        set(&users, "alice".into(), None, None).unwrap();
+        assert_eq!(get(&users, "alice".into()), None);
+
+        // Writes to missing collections are ignored
+        let missing = CollectionKey::new(principal(1), "missing".into());
+        set(&missing, "alice".into(), Some("admin".into()), None).unwrap();
+        assert_eq!(get(&missing, "alice".into()), None);
+    }
+
+    #[test]
+    fn entries_lists_keys_and_values_in_order() {
+        let users = collection("users");
+        let other = collection("other");
+        set(&users, "bob".into(), Some("2".into()), None).unwrap();
+        set(&users, "alice".into(), Some("1".into()), None).unwrap();
+        set(&other, "carol".into(), Some("3".into()), None).unwrap();
+
+        assert_eq!(entries(&users), pairs(&[("alice", "1"), ("bob", "2")]));
+    }
+
+    #[test]
+    fn expired_entries_are_hidden_and_collected() {
+        let sessions = collection("sessions");
+        set(&sessions, "live".into(), Some("1".into()), Some(3600)).unwrap();
+        set(&sessions, "dead".into(), Some("2".into()), Some(0)).unwrap();
+
+        assert_eq!(get(&sessions, "dead".into()), None);
+        assert_eq!(entries(&sessions), pairs(&[("live", "1")]));
+
+        assert!(!remove_expired(10));
+        assert_eq!(usage(&sessions).entries, 1);
+    }
+
+    #[test]
+    fn writes_without_a_ttl_clear_it() {
+        let sessions = collection("sessions");
+        set(&sessions, "a".into(), Some("1".into()), Some(0)).unwrap();
+        set(&sessions, "b".into(), Some("1".into()), Some(0)).unwrap();
+        set(&sessions, "a".into(), Some("2".into()), None).unwrap();
+        set_many(&sessions, vec![("b".into(), Some("2".into()))]).unwrap();
+
+        remove_expired(10);
+        assert_eq!(entries(&sessions), pairs(&[("a", "2"), ("b", "2")]));
+    }
+
+    #[test]
+    fn delete_collection_removes_everything() {
+        let users = collection("users");
+        set(&users, "alice".into(), Some("admin".into()), None).unwrap();
+        set_blob(&users, "avatar".into(), Some(vec![1])).unwrap();
+
+        delete_collection(&users);
+        assert!(!has_collection(&users));
+        assert_eq!(get(&users, "alice".into()), None);
+        assert_eq!(get_blob(&users, "avatar".into()), None);
+        assert_eq!(owner_usage(users.owner).entries, 0);
+    }
+
+    #[test]
+    fn blobs_are_separate_from_text() {
+        let files = collection("files");
+        set(&files, "a".into(), Some("text".into()), None).unwrap();
+        set_blob(&files, "a".into(), Some(vec![0, 255])).unwrap();
+
+        assert_eq!(get(&files, "a".into()), Some("text".to_owned()));
+        assert_eq!(get_blob(&files, "a".into()), Some(vec![0, 255]));
+
+        set_blob(&files, "a".into(), None).unwrap();
+        assert_eq!(get_blob(&files, "a".into()), None);
+        assert_eq!(get(&files, "a".into()), Some("text".to_owned()));
+    }
+
+    #[test]
+    fn usage_counts_keys_and_values() {
+        let files = collection("files");
+        set(&files, "ab".into(), Some("cde".into()), None).unwrap();
+        set_blob(&files, "f".into(), Some(vec![1, 2])).unwrap();
+
+        let usage = usage(&files);
+        assert_eq!((usage.bytes, usage.entries), (8, 2));
+
+        set(&files, "ab".into(), Some("c".into()), None).unwrap();
+        assert_eq!(super::usage(&files).bytes, 6);
+    }
+
+    #[test]
+    fn usage_and_stats_are_backfilled_if_missing() {
+        let users = collection("users");
+        let files = collection("files");
+        set(&users, "alice".into(), Some("admin".into()), None).unwrap();
+        set(&users, "bob".into(), Some("guest".into()), None).unwrap();
+        set_blob(&files, "a".into(), Some(vec![1, 2])).unwrap();
+        grant(&users, principal(2), Some(Role::Read));
+
+        USAGE.with(|usage| usage.borrow_mut().clear_new());
+        PRINCIPAL_STATS.with(|stats| stats.borrow_mut().clear_new());
+        GRANTED.with(|granted| granted.borrow_mut().clear_new());
+        TOTALS.with(|totals| totals.borrow_mut().set((0, 0, 0)).unwrap());
+        upgrade();
+        assert!(start_backfill());
+        assert!(!backfilled());
+
+        // Collections created in between count only if the backfill is past
+        // them, and so do writes
+        assert!(backfill(1));
+        let logs = collection("logs");
+        while BACKFILL.with(|backfill| matches!(backfill.borrow().get(), Backfill::Collections(_)))
+        {
+            backfill(1);
+        }
+        assert!(backfill(1));
+        set(&users, "alice".into(), Some("root".into()), None).unwrap();
+        set(&users, "bob".into(), Some("guests".into()), None).unwrap();
+        upgrade();
+        assert!(start_backfill());
+        assert!(backfill(1));
+        set_blob(&files, "b".into(), Some(vec![3])).unwrap();
+        set(&logs, "x".into(), Some("y".into()), None).unwrap();
+        while backfill(1) {}
+
+        assert!(backfilled());
+        let Usage { bytes, entries } = usage(&users);
+        assert_eq!((bytes, entries), (18, 2));
+        let Usage { bytes, entries } = usage(&files);
+        assert_eq!((bytes, entries), (5, 2));
+        let (principals, collections, Usage { bytes, entries }) = total_stats();
+        assert_eq!((principals, collections, bytes, entries), (1, 3, 25, 5));
+        purge(principal(2));
+        assert!(grants(&users).is_empty());
+
+        // Recorded usage is left alone
+        set(&users, "carol".into(), Some("x".into()), None).unwrap();
+        assert!(!start_backfill());
+        assert_eq!(usage(&users).entries, 3);
+    }
+
+    #[test]
+    fn stats_are_kept_per_principal() {
+        for id in 1..=3 {
+            let users = CollectionKey::new(principal(id), "users".into());
+            create_collection(users.clone(), vec![]);
+            set(&users, "a".into(), Some("xy".into()), None).unwrap();
+        }
+
+        let (page, next) = principal_stats(None, 2);
+        let principals = page
+            .iter()
+            .map(|(principal, ..)| *principal)
+            .collect::<Vec<_>>();
+        assert_eq!(principals, [principal(1), principal(2)]);
+        assert_eq!(next, Some(principal(2)));
+        let (page, next) = principal_stats(next, 2);
+        let (owner, collections, Usage { bytes, entries }) = page[0];
+        assert_eq!(
+            (owner, collections, bytes, entries),
+            (principal(3), 1, 3, 1)
+        );
+        assert_eq!(next, None);
+
+        delete_collection(&CollectionKey::new(principal(2), "users".into()));
+        let (principals, collections, Usage { bytes, entries }) = total_stats();
+        assert_eq!((principals, collections, bytes, entries), (2, 2, 6, 2));
+    }
+
+    #[test]
+    fn purging_removes_collections_and_grants() {
+        let users = collection("users");
+        let notes = CollectionKey::new(principal(2), "notes".into());
+        create_collection(notes.clone(), vec![]);
+        set(&notes, "a".into(), Some("b".into()), None).unwrap();
+        grant(&users, principal(2), Some(Role::Write));
+        grant(&users, principal(3), Some(Role::Read));
+
+        assert_eq!(purge(principal(2)), 1);
+        assert!(list_collections(principal(2)).is_empty());
+        assert_eq!(grants(&users), [(principal(3), Role::Read)]);
+        assert_eq!(total_stats().0, 1);
+    }
+
+    #[test]
+    fn quotas_reject_growth() {
+        let files = collection("files");
+        set_quota(Some(principal(1)), Some(Quota::from((10, 1))));
+
+        set(&files, "a".into(), Some("1".into()), None).unwrap();
+        let exceeded = set(&files, "b".into(), Some("2".into()), None).unwrap_err();
+        assert_eq!(exceeded.usage.entries, 2);
+        assert!(set_blob(&files, "big".into(), Some(vec![0; 20])).is_err());
+
+        // Shrinking is always allowed
+        set(&files, "a".into(), None, None).unwrap();
+    }
+
+    #[test]
+    fn versions_increase_with_every_write() {
+        let counters = collection("counters");
+        set(&counters, "hits".into(), Some("1".into()), None).unwrap();
+        let (_, first) = get_versioned(&counters, "hits".into()).unwrap();
+        set(&counters, "hits".into(), Some("2".into()), None).unwrap();
+        let (value, second) = get_versioned(&counters, "hits".into()).unwrap();
+
+        assert_eq!(value, "2");
+        assert!(second > first);
+    }
+
+    #[test]
+    fn compare_and_swap_checks_versions() {
+        let counters = collection("counters");
+        let Swap::Swapped(Some(version)) =
+            compare_and_swap(&counters, "hits".into(), None, Some("1".into()))
+        else {
+            panic!("creating a missing key failed");
+        };
+
+        assert!(matches!(
+            compare_and_swap(&counters, "hits".into(), None, Some("1".into())),
+            Swap::Conflict(Some((ref value, v))) if value == "1" && v == version
+        ));
+        assert!(matches!(
+            compare_and_swap(&counters, "hits".into(), Some(version), Some("2".into())),
+            Swap::Swapped(Some(v)) if v > version
+        ));
+        assert!(matches!(
+            compare_and_swap(&counters, "hits".into(), Some(version), None),
+            Swap::Conflict(_)
+        ));
+
+        let missing = CollectionKey::new(principal(1), "missing".into());
+        assert!(matches!(
+            compare_and_swap(&missing, "hits".into(), None, Some("1".into())),
+            Swap::CollectionNotFound
+        ));
+    }
+
+    #[test]
+    fn get_many_and_set_many() {
+        let users = collection("users");
+        set(&users, "bob".into(), Some("old".into()), None).unwrap();
+        set_many(
+            &users,
+            vec![
+                ("alice".into(), Some("1".into())),
+                ("bob".into(), None),
+                ("carol".into(), Some("3".into())),
+            ],
+        )
+        .unwrap();
+
+        assert_eq!(
+            get_many(&users, vec!["alice".into(), "bob".into(), "carol".into()]),
+            vec![Some("1".to_owned()), None, Some("3".to_owned())]
+        );
+    }
+
+    #[test]
+    fn set_many_is_all_or_nothing() {
+        let users = collection("users");
+        set_quota(Some(principal(1)), Some(Quota::from((1024, 2))));
+        let result = set_many(
+            &users,
+            vec![
+                ("a".into(), Some("1".into())),
+                ("b".into(), Some("2".into())),
+                ("c".into(), Some("3".into())),
+            ],
+        );
+
+        assert!(result.is_err());
+        assert!(entries(&users).is_empty());
+    }
+
+    #[test]
+    fn pages_scan_prefixes_and_ranges() {
+        let users = collection("users");
+        for key in ["a/1", "a/2", "a/3", "b/1"] {
+            set(&users, key.into(), Some(key.into()), None).unwrap();
+        }
+
+        let (entries, next) = page(
+            &users,
+            Bound::Included("a/".into()),
+            Bound::Unbounded,
+            "a/",
+            2,
+        );
+        assert_eq!(entries, pairs(&[("a/1", "a/1"), ("a/2", "a/2")]));
+        assert_eq!(next.as_deref(), Some("a/3"));
+
+        let (entries, next) = page(
+            &users,
+            Bound::Excluded("a/2".into()),
+            Bound::Unbounded,
+            "a/",
+            2,
+        );
+        assert_eq!(entries, pairs(&[("a/3", "a/3")]));
+        assert_eq!(next, None);
+
+        let (entries, next) = page(
+            &users,
+            Bound::Included("a/2".into()),
+            Bound::Excluded("b/1".into()),
+            "",
+            10,
+        );
+        assert_eq!(entries, pairs(&[("a/2", "a/2"), ("a/3", "a/3")]));
+        assert_eq!(next, None);
+    }
+
+    #[test]
+    fn changes_are_logged_in_order() {
+        let users = collection("users");
+        set(&users, "alice".into(), Some("1".into()), None).unwrap();
+        set_blob(&users, "avatar".into(), Some(vec![1])).unwrap();
+        set(&users, "alice".into(), None, None).unwrap();
+
+        let (changes, last_seq, truncated) = changes_since(&users, 0, 10);
+        assert_eq!(last_seq, 3);
+        assert!(!truncated);
+        assert!(matches!(changes[0].kind, ChangeKind::Set { .. }));
+        assert!(matches!(changes[1].kind, ChangeKind::BlobSet));
+        assert!(matches!(changes[2].kind, ChangeKind::Removed));
+
+        let (changes, _, _) = changes_since(&users, 2, 10);
+        assert_eq!(changes.len(), 1);
+        assert_eq!(changes[0].seq, 3);
+    }
+
+    #[test]
+    fn change_sequences_survive_deleting_and_replacing() {
+        let users = collection("users");
+        set(&users, "alice".into(), Some("1".into()), None).unwrap();
+        let (_, seen, _) = changes_since(&users, 0, 10);
+
+        delete_collection(&users);
+        let users = collection("users");
+        set(&users, "alice".into(), Some("2".into()), None).unwrap();
+        let (changes, last_seq, truncated) = changes_since(&users, seen, 10);
+        assert!(last_seq > seen + 1);
+        assert!(truncated);
+        assert_eq!(changes.len(), 1);
+        assert!(changes_since(&users, 0, 10).2);
+        assert!(!changes_since(&users, last_seq - 1, 10).2);
+
+        create_collection(users.clone(), vec![]);
+        let (changes, reset_seq, truncated) = changes_since(&users, last_seq, 10);
+        assert!(changes.is_empty());
+        assert!(reset_seq > last_seq);
+        assert!(truncated);
+        assert!(!changes_since(&users, reset_seq, 10).2);
+    }
+
+    #[test]
+    fn reading_from_zero_is_truncated_once_the_log_is_full() {
+        let users = collection("users");
+        for _ in 0..MAX_CHANGES {
+            set(&users, "alice".into(), Some("1".into()), None).unwrap();
+        }
+        assert!(!changes_since(&users, 0, 10).2);
+
+        set(&users, "alice".into(), Some("1".into()), None).unwrap();
+        let (changes, last_seq, truncated) = changes_since(&users, 0, 10);
+        assert!(truncated);
+        assert_eq!(changes[0].seq, 2);
+        assert!(!changes_since(&users, 1, 10).2);
+        assert_eq!(last_seq, MAX_CHANGES + 1);
+    }
+
+    #[test]
+    fn indexes_find_entries_by_field() {
+        let users = CollectionKey::new(principal(1), "users".into());
+        create_collection(
+            users.clone(),
+            vec![Index {
+                name: "by_role".into(),
+                path: "role".into(),
+            }],
+        );
+        set(
+            &users,
+            "alice".into(),
+            Some(r#"{"role":"admin"}"#.into()),
+            None,
+        )
+        .unwrap();
+        set(
+            &users,
+            "bob".into(),
+            Some(r#"{"role":"guest"}"#.into()),
+            None,
+        )
+        .unwrap();
+        set(
+            &users,
+            "carol".into(),
+            Some(r#"{"role":"admin"}"#.into()),
+            None,
+        )
+        .unwrap();
+
+        let (admins, next) =
+            index_lookup(&users, "by_role".into(), "admin".into(), None, 10).unwrap();
+        assert_eq!(
+            admins
+                .iter()
+                .map(|(key, _)| key.as_str())
+                .collect::<Vec<_>>(),
+            ["alice", "carol"]
+        );
+        assert_eq!(next, None);
+
+        // Pages go on after the last key returned
+        let (admins, next) =
+            index_lookup(&users, "by_role".into(), "admin".into(), None, 1).unwrap();
+        assert_eq!(admins.len(), 1);
+        assert_eq!(next.as_deref(), Some("carol"));
+        let (admins, next) = index_lookup(
+            &users,
+            "by_role".into(),
+            "admin".into(),
+            Some("alice".into()),
+            1,
+        )
+        .unwrap();
+        assert_eq!(admins, pairs(&[("carol", r#"{"role":"admin"}"#)]));
+        assert_eq!(next, None);
+
+        // Changing the field moves the entry in the index
+        set(
+            &users,
+            "carol".into(),
+            Some(r#"{"role":"guest"}"#.into()),
+            None,
+        )
+        .unwrap();
+        let (admins, _) = index_lookup(&users, "by_role".into(), "admin".into(), None, 10).unwrap();
+        assert_eq!(admins, pairs(&[("alice", r#"{"role":"admin"}"#)]));
+
+        assert!(index_lookup(&users, "missing".into(), "admin".into(), None, 10).is_none());
+    }
+
+    #[test]
+    fn indexes_store_whole_numbers_like_integers() {
+        let scores = CollectionKey::new(principal(1), "scores".into());
+        create_collection(
+            scores.clone(),
+            vec![Index {
+                name: "by_score".into(),
+                path: "score".into(),
+            }],
+        );
+        for (key, value) in [("a", "1"), ("b", "1.0"), ("c", "1.5"), ("d", "-2.0")] {
+            set(
+                &scores,
+                key.into(),
+                Some(format!(r#"{{"score":{}}}"#, value)),
+                None,
+            )
+            .unwrap();
+        }
+
+        let keys = |value: &str| {
+            let (found, _) =
+                index_lookup(&scores, "by_score".into(), value.into(), None, 10).unwrap();
+            found.into_iter().map(|(key, _)| key).collect::<Vec<_>>()
+        };
+        assert_eq!(keys("1"), ["a", "b"]);
+        assert_eq!(keys("1.5"), ["c"]);
+        assert_eq!(keys("-2"), ["d"]);
+    }
+
+    #[test]
+    fn indexed_values_match_the_sdk() {
+        for value in [
+            "0",
+            "1",
+            "-1",
+            "1.0",
+            "-2.0",
+            "1.5",
+            "-0.25",
+            "1e3",
+            "1e20",
+            "-0.0",
+            "18446744073709551615",
+            "-9223372036854775808",
+            "true",
+            r#""text""#,
+        ] {
+            let value = serde_json::from_str(value).unwrap();
+            assert_eq!(
+                indexed_value(&value, ""),
+                mu_sdk::kv::MuKvIndex::lookup_value(&value),
+                "{}",
+                value
+            );
+        }
+    }
+
+    #[test]
+    fn transactions_apply_all_or_nothing() {
+        let orders = collection("orders");
+        let stock = collection("stock");
+        set(&stock, "apple".into(), Some("3".into()), None).unwrap();
+        let (_, version) = get_versioned(&stock, "apple".into()).unwrap();
+
+        let conflict = transact(
+            principal(1),
+            vec![
+                Op::Put {
+                    collection: "orders".into(),
+                    key: "1".into(),
+                    value: "apple".into(),
+                },
+                Op::Check {
+                    collection: "stock".into(),
+                    key: "apple".into(),
+                    version: Some(version + 1),
+                },
+            ],
+        );
+        assert!(matches!(conflict, Transaction::Conflict(1, Some(_))));
+        assert_eq!(get(&orders, "1".into()), None);
+
+        let committed = transact(
+            principal(1),
+            vec![
+                Op::Check {
+                    collection: "stock".into(),
+                    key: "apple".into(),
+                    version: Some(version),
+                },
+                Op::Put {
+                    collection: "orders".into(),
+                    key: "1".into(),
+                    value: "apple".into(),
+                },
+                Op::Put {
+                    collection: "stock".into(),
+                    key: "apple".into(),
+                    value: "2".into(),
+                },
+            ],
+        );
+        assert!(matches!(
+            committed,
+            Transaction::Committed(ref versions) if versions[0].is_none() && versions[2] > Some(version)
+        ));
+        assert_eq!(get(&orders, "1".into()), Some("apple".to_owned()));
+        assert_eq!(get(&stock, "apple".into()), Some("2".to_owned()));
+
+        assert!(matches!(
+            transact(
+                principal(1),
+                vec![Op::Delete {
+                    collection: "missing".into(),
+                    key: "1".into(),
+                }],
+            ),
+            Transaction::CollectionNotFound(ref name) if name == "missing"
+        ));
+    }
+
+    #[test]
+    fn roles_include_the_ones_before_them() {
+        assert!(Role::Read < Role::Write);
+        assert!(Role::Write < Role::Admin);
+        for role in [Role::Read, Role::Write, Role::Admin] {
+            assert_eq!(Role::from_u8(role as u8), role);
+        }
+    }
+
+    #[test]
+    fn owners_are_implicitly_admins() {
+        let users = collection("users");
+        assert_eq!(role(&users, principal(1)), Some(Role::Admin));
+        assert_eq!(role(&users, principal(2)), None);
+        assert!(grants(&users).is_empty());
+    }
+
+    #[test]
+    fn grants_can_be_changed_and_revoked() {
+        let users = collection("users");
+        let other = collection("other");
+        grant(&users, principal(2), Some(Role::Read));
+        grant(&users, principal(3), Some(Role::Write));
+        assert_eq!(role(&users, principal(2)), Some(Role::Read));
+        assert_eq!(role(&other, principal(2)), None);
+        assert_eq!(
+            grants(&users),
+            [(principal(2), Role::Read), (principal(3), Role::Write)]
+        );
+
+        grant(&users, principal(2), Some(Role::Admin));
+        assert_eq!(role(&users, principal(2)), Some(Role::Admin));
+        grant(&users, principal(2), None);
+        assert_eq!(role(&users, principal(2)), None);
+        assert_eq!(grants(&users), [(principal(3), Role::Write)]);
+
+        delete_collection(&users);
+        let users = collection("users");
+        assert_eq!(role(&users, principal(3)), None);
+    }
+
+    /// Exports a collection page by page, returning the keys of each page.
+    fn export_pages(collection: &CollectionKey, limit: usize) -> Vec<Vec<String>> {
+        let mut pages = Vec::new();
+        let mut cursor = None;
+        loop {
+            let (page, next) = export(collection, cursor, limit);
+            pages.push(page.into_iter().map(|entry| entry.key).collect());
+            match next {
+                Some(next) => cursor = Some(next),
+                None => return pages,
+            }
+        }
+    }
+
+    #[test]
+    fn exports_continue_with_blobs_after_a_full_page_of_text() {
+        let files = collection("files");
+        set(&files, "z".into(), Some("text".into()), None).unwrap();
+        set_blob(&files, "a".into(), Some(vec![1])).unwrap();
+
+        assert_eq!(export_pages(&files, 1), [["z"], ["a"]]);
+    }
+
+    #[test]
+    fn exports_page_through_text_then_blobs() {
+        let files = collection("files");
+        for key in ["a", "b", "c"] {
+            set(&files, key.into(), Some("text".into()), None).unwrap();
+            set_blob(&files, key.into(), Some(vec![1])).unwrap();
+        }
+        set_blob(&files, String::new(), Some(vec![2])).unwrap();
+
+        assert_eq!(
+            export_pages(&files, 2),
+            [vec!["a", "b"], vec!["c", ""], vec!["a", "b"], vec!["c"]]
+        );
+
+        let (entries, _) = export(&files, None, 10);
+        let copy = CollectionKey::new(principal(1), "copy".into());
+        import(&copy, vec![], entries).unwrap();
+        assert_eq!(export_pages(&copy, 10), export_pages(&files, 10));
+    }
+}
@@ -1,102 +1,755 @@
+use candid::{
+    utils::{ArgumentDecoder, ArgumentEncoder},
+    CandidType,
+};
 use ic_principal::Principal;
-use std::{cell::LazyCell, future::Future};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::{future::Future, marker::PhantomData, time::Duration};
+
+#[cfg(test)]
+use candid::types::{internal::TypeContainer, Type};
 
 use crate::MuError;
 
+#[cfg(test)]
+mod canister;
+mod changes;
+mod index;
+#[cfg(any(test, feature = "testing"))]
+mod memory;
+mod scan;
+mod transaction;
+mod typed;
+
+pub use changes::{MuKvChange, MuKvChangeKind, MuKvChanges, MuKvWatcher};
+pub use index::MuKvIndex;
+#[cfg(any(test, feature = "testing"))]
+pub use memory::MuMemoryKv;
+pub use scan::{MuKvEntries, MuKvPage};
+pub use transaction::{MuKvCommit, MuKvOp, MuKvTransaction};
+pub use typed::{Candid, Json, KvEncoding, TypedCollection};
+
+/// A value along with its version. Every write gives the entry a new,
+/// higher version.
+#[derive(CandidType, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct MuKvVersioned {
+    pub value: String,
+    pub version: u64,
+}
+
+/// What another principal may do with a shared collection. Each role includes
+/// the ones before it.
+#[derive(CandidType, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum MuKvRole {
+    Read,
+    Write,
+    /// Can also delete the collection and share it further.
+    Admin,
+}
+
+/// How much a principal may store across all of its collections. Keys count
+/// towards the stored bytes along with values.
+#[derive(CandidType, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MuKvQuota {
+    pub max_bytes: u64,
+    pub max_entries: u64,
+}
+
+#[derive(CandidType, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MuKvUsage {
+    pub bytes: u64,
+    pub entries: u64,
+}
+
+/// The result of [`MuKvCollection::usage`].
+#[derive(CandidType, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MuKvUsageReport {
+    /// What the collection takes up.
+    pub collection: MuKvUsage,
+    /// What all collections of its owner take up together.
+    pub owner: MuKvUsage,
+    /// The owner's limit on the total.
+    pub quota: MuKvQuota,
+}
+
+/// The outcome of [`MuKvCollection::compare_and_swap`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MuKvSwap {
+    /// The entry was written and has this new version, or was removed.
+    Swapped { version: Option<u64> },
+    /// The entry was changed by someone else; this is its current value.
+    Conflict { current: Option<MuKvVersioned> },
+}
+
 pub trait MuKv {
-    fn collection(&self, name: &str) -> impl Future<Output = Result<impl MuKvCollection, MuError>>;
+    /// Opens the collection `name`, creating it if it does not exist.
+    fn collection(&self, name: &str) -> impl Future<Output = Result<impl MuKvCollection, MuError>> {
+        self.collection_with_indexes(name, &[])
+    }
+
+    /// Opens the collection `name`, creating it with `indexes` if it does not
+    /// exist. Indexes are declared when a collection is created, so those of
+    /// an existing collection stay as they are.
+    fn collection_with_indexes(
+        &self,
+        name: &str,
+        indexes: &[MuKvIndex],
+    ) -> impl Future<Output = Result<impl MuKvCollection, MuError>>;
+
+    /// Applies `ops` to the caller's collections together, or not at all.
+    /// [`MuKv::transaction`] builds the operations.
+    fn transact(&self, ops: Vec<MuKvOp>) -> impl Future<Output = Result<MuKvCommit, MuError>>;
+
+    /// Starts a transaction across the caller's collections.
+    fn transaction(&self) -> MuKvTransaction<'_, Self> {
+        MuKvTransaction::new(self)
+    }
 }
 
 pub trait MuKvCollection {
     fn query(&self, key: &str) -> impl Future<Output = Result<Option<String>, MuError>>;
+    /// Sets `key` to `value`, or removes the key if `value` is `None`. The
+    /// entry does not expire, even if it was written with a TTL before; the
+    /// same goes for every other write without a TTL.
     fn update(&self, key: &str, value: Option<&str>) -> impl Future<Output = Result<(), MuError>>;
+
+    /// Sets `key` to `value` until `ttl` has passed. Expired entries are no
+    /// longer returned by any query. mu-kv stores TTLs in whole seconds, so
+    /// `ttl` is rounded up to the next second.
+    fn update_with_ttl(
+        &self,
+        key: &str,
+        value: &str,
+        ttl: Duration,
+    ) -> impl Future<Output = Result<(), MuError>>;
     fn keys(&self) -> impl Future<Output = Result<Vec<String>, MuError>>;
     fn values(&self) -> impl Future<Output = Result<Vec<String>, MuError>>;
     fn delete(&self) -> impl Future<Output = Result<(), MuError>>;
-}
 
-pub struct MuIcpKv;
+    /// The binary value stored under `key`. Blobs have their own keys,
+    /// separate from text values.
+    fn query_blob(&self, key: &str) -> impl Future<Output = Result<Option<Vec<u8>>, MuError>>;
+
+    /// Stores `value` under `key`, or removes the blob if `value` is `None`.
+    fn update_blob(
+        &self,
+        key: &str,
+        value: Option<&[u8]>,
+    ) -> impl Future<Output = Result<(), MuError>>;
+
+    /// How much this collection and all collections of its owner store,
+    /// along with the owner's quota. Writes that would exceed the quota fail
+    /// with [`MuError::QuotaExceeded`].
+    fn usage(&self) -> impl Future<Output = Result<MuKvUsageReport, MuError>>;
+
+    /// The value of `key` along with its version.
+    fn query_versioned(
+        &self,
+        key: &str,
+    ) -> impl Future<Output = Result<Option<MuKvVersioned>, MuError>>;
+
+    /// Writes `value` under `key`, or removes the key if `value` is `None`,
+    /// only if the entry is still at `expected_version`. Pass `None` as the
+    /// expected version to only create new keys. Fails with
+    /// [`MuError::NotFound`] if the collection was deleted. Like
+    /// [`MuKvCollection::update`], this clears any TTL of the entry.
+    fn compare_and_swap(
+        &self,
+        key: &str,
+        expected_version: Option<u64>,
+        value: Option<&str>,
+    ) -> impl Future<Output = Result<MuKvSwap, MuError>>;
+
+    /// The values of `keys`, in the same order, read in a single call.
+    fn get_many(&self, keys: &[&str])
+        -> impl Future<Output = Result<Vec<Option<String>>, MuError>>;
+
+    /// Up to `limit` entries that `index` maps `value` to, in key order and
+    /// after `start_after` if given. Pass the last key of a page as
+    /// `start_after` to read the next one. Fails with [`MuError::NotFound`]
+    /// if the collection has no such index.
+    fn index_lookup(
+        &self,
+        index: &str,
+        value: &str,
+        start_after: Option<&str>,
+        limit: u32,
+    ) -> impl Future<Output = Result<MuKvPage, MuError>>;
+
+    /// Sets or, for `None` values, removes all given keys in a single call.
+    /// Either all changes are applied or none are. The entries set do not
+    /// expire.
+    fn update_many(
+        &self,
+        changes: &[(&str, Option<&str>)],
+    ) -> impl Future<Output = Result<(), MuError>>;
+
+    /// Up to `limit` entries whose keys start with `prefix`, after
+    /// `start_after` if given. Pass the last key of a page as `start_after`
+    /// to read the next one.
+    fn scan(
+        &self,
+        prefix: &str,
+        start_after: Option<&str>,
+        limit: u32,
+    ) -> impl Future<Output = Result<MuKvPage, MuError>>;
+
+    /// Up to `limit` entries with keys in `[from, to)`. Pass the page's
+    /// `next` key as `from` to read the next one.
+    fn range(
+        &self,
+        from: &str,
+        to: &str,
+        limit: u32,
+    ) -> impl Future<Output = Result<MuKvPage, MuError>>;
 
-const KV_PRINCIPAL: LazyCell<Principal> =
-    LazyCell::new(|| "by6od-j4aaa-aaaaa-qaadq-cai".parse().unwrap());
+    /// Up to `limit` changes made to the collection after change `seq`,
+    /// oldest first. mu-kv keeps the last 1000 changes of each collection;
+    /// the result is `truncated` if older changes after `seq` were dropped
+    /// or the collection was deleted or replaced since.
+    fn changes_since(
+        &self,
+        seq: u64,
+        limit: u32,
+    ) -> impl Future<Output = Result<MuKvChanges, MuError>>;
+
+    /// Follows the changes made after change `seq`. Pass 0 to start with all
+    /// changes of the collection, which is `truncated` if some were dropped
+    /// already, or the `last_seq` of the collection's latest [`MuKvChanges`]
+    /// to only see new ones.
+    fn watch(&self, seq: u64) -> MuKvWatcher<'_, Self>
+    where
+        Self: Sized,
+    {
+        MuKvWatcher::new(self, seq)
+    }
+
+    /// Iterates over all entries whose keys start with `prefix`.
+    fn scan_all(&self, prefix: &str) -> MuKvEntries<'_, Self>
+    where
+        Self: Sized,
+    {
+        MuKvEntries::prefix(self, prefix)
+    }
+
+    /// Iterates over all entries with keys in `[from, to)`.
+    fn range_all(&self, from: &str, to: &str) -> MuKvEntries<'_, Self>
+    where
+        Self: Sized,
+    {
+        MuKvEntries::range(self, from, to)
+    }
+
+    /// Iterates over all entries that `index` maps `value` to.
+    fn index_lookup_all(&self, index: &str, value: &str) -> MuKvEntries<'_, Self>
+    where
+        Self: Sized,
+    {
+        MuKvEntries::index(self, index, value, None)
+    }
+
+    /// Wraps the collection to read and write JSON encoded `V`s under `K`s.
+    fn typed<K: ToString, V: Serialize + DeserializeOwned>(self) -> TypedCollection<Self, K, V>
+    where
+        Self: Sized,
+    {
+        TypedCollection::new(self)
+    }
+}
+
+pub struct MuIcpKv {
+    principal: Principal,
+}
 
 impl MuIcpKv {
+    /// Connects to the mu-kv canister whose id was given in the
+    /// `MU_KV_CANISTER_ID` environment variable when the function was built.
+    /// Traps if it was not set, which happens when the function is built
+    /// without `mu dev` or `mu deploy`; use [`MuIcpKv::try_new`] to handle
+    /// that case.
     pub fn new() -> Self {
-        Self
+        Self::try_new().unwrap_or_else(|error| match error {
+            MuError::KvNotConfigured { message } => panic!("{}", message),
+            error => panic!("{:?}", error),
+        })
+    }
+
+    /// Like [`MuIcpKv::new`], but fails with [`MuError::KvNotConfigured`] if
+    /// `MU_KV_CANISTER_ID` was not set or is not a valid principal.
+    pub fn try_new() -> Result<Self, MuError> {
+        let Some(id) = option_env!("MU_KV_CANISTER_ID") else {
+            return Err(MuError::KvNotConfigured {
+                message: "MU_KV_CANISTER_ID was not set when the function was built; enable \
+                          the kv service in mu.toml and build with mu dev or mu deploy"
+                    .to_owned(),
+            });
+        };
+        let principal = id.parse().map_err(|_| MuError::KvNotConfigured {
+            message: format!("MU_KV_CANISTER_ID is not a valid principal: {}", id),
+        })?;
+        Ok(Self::with_principal(principal))
+    }
+
+    /// Connects to the mu-kv canister with the given id.
+    pub fn with_principal(principal: Principal) -> Self {
+        Self { principal }
     }
 }
 
+impl MuIcpKv {
+    /// Opens the collection `name` created by `owner`, which must have given
+    /// this canister access with [`MuIcpKv::grant_access`]. Calls that need a
+    /// role this canister was not granted fail with [`MuError::CallFailed`].
+    pub async fn shared_collection(
+        &self,
+        owner: Principal,
+        name: &str,
+    ) -> Result<impl MuKvCollection, MuError> {
+        let (exists,) = HAS_COLLECTION
+            .call(self.principal, (name.to_owned(), Some(owner)))
+            .await?;
+        if !exists {
+            return Err(MuError::NotFound);
+        }
+
+        Ok(MuIcpKvCollection {
+            kv: self.principal,
+            name: name.to_owned(),
+            owner: Some(owner),
+        })
+    }
+
+    /// Gives `principal` `role` on this canister's collection `name`.
+    pub async fn grant_access(
+        &self,
+        name: &str,
+        principal: Principal,
+        role: MuKvRole,
+    ) -> Result<(), MuError> {
+        self.set_access(name, principal, Some(role)).await
+    }
+
+    /// Takes away any role `principal` has on this canister's collection
+    /// `name`.
+    pub async fn revoke_access(&self, name: &str, principal: Principal) -> Result<(), MuError> {
+        self.set_access(name, principal, None).await
+    }
+
+    /// The principals this canister's collection `name` is shared with.
+    pub async fn list_access(&self, name: &str) -> Result<Vec<(Principal, MuKvRole)>, MuError> {
+        let (grants,) = LIST_ACCESS
+            .call(self.principal, (name.to_owned(), None::<Principal>))
+            .await?;
+        Ok(grants)
+    }
+
+    async fn set_access(
+        &self,
+        name: &str,
+        principal: Principal,
+        role: Option<MuKvRole>,
+    ) -> Result<(), MuError> {
+        GRANT_ACCESS
+            .call(
+                self.principal,
+                (name.to_owned(), principal, role, None::<Principal>),
+            )
+            .await
+    }
+}
+
+/// A method of the mu-kv canister, called with arguments `A` and returning
+/// `R`. The methods the SDK calls are declared with [`methods!`], and a test
+/// checks their types against `mu-kv/mu-kv.did`.
+struct Method<A, R> {
+    name: &'static str,
+    types: PhantomData<fn(A) -> R>,
+}
+
+impl<A: ArgumentEncoder, R: for<'a> ArgumentDecoder<'a>> Method<A, R> {
+    /// Calls the method on the mu-kv canister `kv`.
+    async fn call(&self, kv: Principal, args: A) -> Result<R, MuError> {
+        #[cfg(not(test))]
+        let result = ic_cdk::call(kv, self.name, args).await;
+        #[cfg(test)]
+        let result = canister::call(kv, self.name, args).await;
+        result.map_err(|(code, message)| MuError::CallFailed {
+            method: self.name.to_owned(),
+            message: format!("{:?}: {}", code, message),
+        })
+    }
+}
+
+/// The error mu-kv returns for writes over the owner's quota.
+#[derive(CandidType, Deserialize)]
+struct QuotaExceeded {
+    quota: MuKvQuota,
+    usage: MuKvUsage,
+}
+
+impl From<QuotaExceeded> for MuError {
+    fn from(QuotaExceeded { quota, usage }: QuotaExceeded) -> Self {
+        MuError::QuotaExceeded { quota, usage }
+    }
+}
+
+/// The result of mu-kv's writes to a collection.
+type WriteResult = Result<(), QuotaExceeded>;
+
+impl<A: ArgumentEncoder> Method<A, (WriteResult,)> {
+    /// Calls a method that writes to a collection and can fail with
+    /// [`MuError::QuotaExceeded`].
+    async fn call_write(&self, kv: Principal, args: A) -> Result<(), MuError> {
+        let (result,) = self.call(kv, args).await?;
+        Ok(result?)
+    }
+}
+
+/// The result of mu-kv's `compare_and_swap`.
+#[derive(CandidType, Deserialize)]
+enum SwapResult {
+    Swapped { version: Option<u64> },
+    Conflict { current: Option<MuKvVersioned> },
+    CollectionNotFound,
+    QuotaExceeded(QuotaExceeded),
+}
+
+/// The result of mu-kv's `transact`.
+#[derive(CandidType, Deserialize)]
+enum TransactResult {
+    Committed {
+        versions: Vec<Option<u64>>,
+    },
+    Conflict {
+        op: u32,
+        current: Option<MuKvVersioned>,
+    },
+    CollectionNotFound {
+        collection: String,
+    },
+    QuotaExceeded(QuotaExceeded),
+}
+
+/// Declares a constant [`Method`] for each mu-kv method, along with
+/// `method_types` listing their names and Candid types for the tests.
+macro_rules! methods {
+    ($($name:ident = $method:literal ($($arg:ty),*) -> ($($ret:ty),*);)*) => {
+        $(
+            const $name: Method<($($arg,)*), ($($ret,)*)> = Method {
+                name: $method,
+                types: PhantomData,
+            };
+        )*
+
+        #[cfg(test)]
+        fn method_types(
+            types: &mut TypeContainer,
+        ) -> Vec<(&'static str, Vec<Type>, Vec<Type>)> {
+            vec![$((
+                $method,
+                vec![$(types.add::<$arg>()),*],
+                vec![$(types.add::<$ret>()),*],
+            )),*]
+        }
+    };
+}
+
+methods! {
+    HAS_COLLECTION = "has_collection"(String, Option<Principal>) -> (bool);
+    CREATE_COLLECTION = "create_collection"(String, Option<Vec<MuKvIndex>>) -> ();
+    DELETE_COLLECTION = "delete_collection"(String, Option<Principal>) -> ();
+    LIST_ACCESS = "list_access"(String, Option<Principal>) -> (Vec<(Principal, MuKvRole)>);
+    GRANT_ACCESS = "grant_access"(String, Principal, Option<MuKvRole>, Option<Principal>) -> ();
+    TRANSACT = "transact"(Vec<MuKvOp>) -> (TransactResult);
+    QUERY_COLLECTION = "query_collection"(String, String, Option<Principal>) -> (Option<String>);
+    QUERY_ALL_KEYS = "query_all_keys"(String, Option<Principal>) -> (Vec<String>);
+    QUERY_ALL_VALUES = "query_all_values"(String, Option<Principal>) -> (Vec<String>);
+    UPDATE_COLLECTION = "update_collection"(
+        String,
+        String,
+        Option<String>,
+        Option<Principal>,
+        Option<u64>
+    ) -> (WriteResult);
+    QUERY_BLOB = "query_blob"(String, String, Option<Principal>) -> (Option<Vec<u8>>);
+    UPDATE_BLOB = "update_blob"(String, String, Option<Vec<u8>>, Option<Principal>) -> (WriteResult);
+    CHANGES_SINCE = "changes_since"(String, u64, u32, Option<Principal>) -> (MuKvChanges);
+    USAGE = "usage"(String, Option<Principal>) -> (MuKvUsageReport);
+    QUERY_VERSIONED = "query_versioned"(String, String, Option<Principal>) -> (Option<MuKvVersioned>);
+    COMPARE_AND_SWAP = "compare_and_swap"(
+        String,
+        String,
+        Option<u64>,
+        Option<String>,
+        Option<Principal>
+    ) -> (SwapResult);
+    BATCH_GET = "batch_get"(String, Vec<String>, Option<Principal>) -> (Vec<Option<String>>);
+    BATCH_UPDATE = "batch_update"(
+        String,
+        Vec<(String, Option<String>)>,
+        Option<Principal>
+    ) -> (WriteResult);
+    INDEX_LOOKUP = "index_lookup"(
+        String,
+        String,
+        String,
+        u32,
+        Option<Principal>,
+        Option<String>
+    ) -> (Option<MuKvPage>);
+    SCAN = "scan"(String, String, Option<String>, u32, Option<Principal>) -> (MuKvPage);
+    RANGE = "range"(String, String, String, u32, Option<Principal>) -> (MuKvPage);
+}
+
 struct MuIcpKvCollection {
+    kv: Principal,
     name: String,
+    /// The principal that owns the collection, if it is not the caller.
+    owner: Option<Principal>,
+}
+impl Default for MuIcpKv {
+    fn default() -> Self {
+        Self::new()
+    }
 }
+
 impl MuKv for MuIcpKv {
-    async fn collection(&self, name: &str) -> Result<impl MuKvCollection, MuError> {
+    async fn collection_with_indexes(
+        &self,
+        name: &str,
+        indexes: &[MuKvIndex],
+    ) -> Result<impl MuKvCollection, MuError> {
         //use has_collection to check if it exists and if not create it
-        let exists: Result<(bool,), _> =
-            ic_cdk::call(*KV_PRINCIPAL, "has_collection", (name.to_owned(),))
-                .await
-                .map_err(|_| MuError::InternalError);
-
-        if !exists?.0 {
-            let res: Result<(), _> =
-                ic_cdk::call(*KV_PRINCIPAL, "create_collection", (name.to_owned(),)).await;
-            if res.is_err() {
-                return Err(MuError::InternalError);
-            }
+        let (exists,) = HAS_COLLECTION
+            .call(self.principal, (name.to_owned(), None::<Principal>))
+            .await?;
+
+        if !exists {
+            CREATE_COLLECTION
+                .call(self.principal, (name.to_owned(), Some(indexes.to_vec())))
+                .await?;
         }
 
         Ok(MuIcpKvCollection {
+            kv: self.principal,
             name: name.to_owned(),
+            owner: None,
         })
     }
+
+    async fn transact(&self, ops: Vec<MuKvOp>) -> Result<MuKvCommit, MuError> {
+        let (result,) = TRANSACT.call(self.principal, (ops,)).await?;
+        match result {
+            TransactResult::Committed { versions } => Ok(MuKvCommit::Committed { versions }),
+            TransactResult::Conflict { op, current } => Ok(MuKvCommit::Conflict {
+                op: op as usize,
+                current,
+            }),
+            TransactResult::CollectionNotFound { .. } => Err(MuError::NotFound),
+            TransactResult::QuotaExceeded(exceeded) => Err(exceeded.into()),
+        }
+    }
 }
 
 impl MuKvCollection for MuIcpKvCollection {
     async fn query(&self, key: &str) -> Result<Option<String>, MuError> {
-        let result: Result<(String,), _> = ic_cdk::call(
-            *KV_PRINCIPAL,
-            "query_collection",
-            (self.name.to_owned(), key.to_owned()),
-        )
-        .await;
-        result
-            .map_err(|_| MuError::InternalError)
-            .map(|(value,)| Some(value))
+        let (value,) = QUERY_COLLECTION
+            .call(self.kv, (self.name.clone(), key.to_owned(), self.owner))
+            .await?;
+        Ok(value)
     }
 
     async fn keys(&self) -> Result<Vec<String>, MuError> {
-        let result: Result<(Vec<String>,), _> =
-            ic_cdk::call(*KV_PRINCIPAL, "query_all_keys", (self.name.clone(),)).await;
-        result
-            .map_err(|_| MuError::InternalError)
-            .map(|(keys,)| keys)
+        let (keys,) = QUERY_ALL_KEYS
+            .call(self.kv, (self.name.clone(), self.owner))
+            .await?;
+        Ok(keys)
     }
 
     async fn values(&self) -> Result<Vec<String>, MuError> {
-        let result: Result<(Vec<String>,), _> =
-            ic_cdk::call(*KV_PRINCIPAL, "query_all_values", (self.name.clone(),)).await;
-        result
-            .map_err(|_| MuError::InternalError)
-            .map(|(values,)| values)
+        let (values,) = QUERY_ALL_VALUES
+            .call(self.kv, (self.name.clone(), self.owner))
+            .await?;
+        Ok(values)
     }
 
     async fn update(&self, key: &str, value: Option<&str>) -> Result<(), MuError> {
-        let result: Result<(), _> = ic_cdk::call(
-            *KV_PRINCIPAL,
-            "update_collection",
-            (
-                self.name.clone(),
-                key.to_owned(),
-                value.map(|s| s.to_owned()),
-            ),
-        )
-        .await;
-        result.map_err(|_| MuError::InternalError)
+        UPDATE_COLLECTION
+            .call_write(
+                self.kv,
+                (
+                    self.name.clone(),
+                    key.to_owned(),
+                    value.map(|s| s.to_owned()),
+                    self.owner,
+                    None,
+                ),
+            )
+            .await
+    }
+
+    async fn update_with_ttl(&self, key: &str, value: &str, ttl: Duration) -> Result<(), MuError> {
+        UPDATE_COLLECTION
+            .call_write(
+                self.kv,
+                (
+                    self.name.clone(),
+                    key.to_owned(),
+                    Some(value.to_owned()),
+                    self.owner,
+                    Some(ttl.as_secs() + u64::from(ttl.subsec_nanos() > 0)),
+                ),
+            )
+            .await
     }
 
     async fn delete(&self) -> Result<(), MuError> {
-        let result: Result<(), _> =
-            ic_cdk::call(*KV_PRINCIPAL, "delete_collection", (self.name.clone(),)).await;
-        result.map_err(|_| MuError::InternalError)
+        DELETE_COLLECTION
+            .call(self.kv, (self.name.clone(), self.owner))
+            .await
+    }
+
+    async fn query_blob(&self, key: &str) -> Result<Option<Vec<u8>>, MuError> {
+        let (value,) = QUERY_BLOB
+            .call(self.kv, (self.name.clone(), key.to_owned(), self.owner))
+            .await?;
+        Ok(value)
+    }
+
+    async fn update_blob(&self, key: &str, value: Option<&[u8]>) -> Result<(), MuError> {
+        UPDATE_BLOB
+            .call_write(
+                self.kv,
+                (
+                    self.name.clone(),
+                    key.to_owned(),
+                    value.map(|v| v.to_vec()),
+                    self.owner,
+                ),
+            )
+            .await
+    }
+
+    async fn changes_since(&self, seq: u64, limit: u32) -> Result<MuKvChanges, MuError> {
+        let (changes,) = CHANGES_SINCE
+            .call(self.kv, (self.name.clone(), seq, limit, self.owner))
+            .await?;
+        Ok(changes)
+    }
+
+    async fn usage(&self) -> Result<MuKvUsageReport, MuError> {
+        let (report,) = USAGE.call(self.kv, (self.name.clone(), self.owner)).await?;
+        Ok(report)
+    }
+
+    async fn query_versioned(&self, key: &str) -> Result<Option<MuKvVersioned>, MuError> {
+        let (value,) = QUERY_VERSIONED
+            .call(self.kv, (self.name.clone(), key.to_owned(), self.owner))
+            .await?;
+        Ok(value)
+    }
+
+    async fn compare_and_swap(
+        &self,
+        key: &str,
+        expected_version: Option<u64>,
+        value: Option<&str>,
+    ) -> Result<MuKvSwap, MuError> {
+        let (result,) = COMPARE_AND_SWAP
+            .call(
+                self.kv,
+                (
+                    self.name.clone(),
+                    key.to_owned(),
+                    expected_version,
+                    value.map(|s| s.to_owned()),
+                    self.owner,
+                ),
+            )
+            .await?;
+        match result {
+            SwapResult::Swapped { version } => Ok(MuKvSwap::Swapped { version }),
+            SwapResult::Conflict { current } => Ok(MuKvSwap::Conflict { current }),
+            SwapResult::CollectionNotFound => Err(MuError::NotFound),
+            SwapResult::QuotaExceeded(exceeded) => Err(exceeded.into()),
+        }
+    }
+
+    async fn get_many(&self, keys: &[&str]) -> Result<Vec<Option<String>>, MuError> {
+        let keys = keys.iter().map(|key| key.to_string()).collect::<Vec<_>>();
+        let (values,) = BATCH_GET
+            .call(self.kv, (self.name.clone(), keys, self.owner))
+            .await?;
+        Ok(values)
+    }
+
+    async fn index_lookup(
+        &self,
+        index: &str,
+        value: &str,
+        start_after: Option<&str>,
+        limit: u32,
+    ) -> Result<MuKvPage, MuError> {
+        let (page,) = INDEX_LOOKUP
+            .call(
+                self.kv,
+                (
+                    self.name.clone(),
+                    index.to_owned(),
+                    value.to_owned(),
+                    limit,
+                    self.owner,
+                    start_after.map(str::to_owned),
+                ),
+            )
+            .await?;
+        page.ok_or(MuError::NotFound)
+    }
+
+    async fn update_many(&self, changes: &[(&str, Option<&str>)]) -> Result<(), MuError> {
+        let changes = changes
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.map(|s| s.to_owned())))
+            .collect::<Vec<_>>();
+        BATCH_UPDATE
+            .call_write(self.kv, (self.name.clone(), changes, self.owner))
+            .await
+    }
+
+    async fn scan(
+        &self,
+        prefix: &str,
+        start_after: Option<&str>,
+        limit: u32,
+    ) -> Result<MuKvPage, MuError> {
+        let (page,) = SCAN
+            .call(
+                self.kv,
+                (
+                    self.name.clone(),
+                    prefix.to_owned(),
+                    start_after.map(|s| s.to_owned()),
+                    limit,
+                    self.owner,
+                ),
+            )
+            .await?;
+        Ok(page)
+    }
+
+    async fn range(&self, from: &str, to: &str, limit: u32) -> Result<MuKvPage, MuError> {
+        let (page,) = RANGE
+            .call(
+                self.kv,
+                (
+                    self.name.clone(),
+                    from.to_owned(),
+                    to.to_owned(),
+                    limit,
+                    self.owner,
+                ),
+            )
+            .await?;
+        Ok(page)
     }
 }
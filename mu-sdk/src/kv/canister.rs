@@ -0,0 +1,373 @@
+//! A stand-in for the mu-kv canister, which lets the tests run [`MuIcpKv`]
+//! without a replica. Calls are encoded the way the SDK sends them, checked
+//! against `mu-kv/mu-kv.did` and served by a [`MuMemoryKv`], and replies are
+//! checked against the did file on the way back.
+
+use std::{cell::RefCell, collections::BTreeMap, path::Path, time::Duration};
+
+use candid::{
+    decode_args, encode_args,
+    types::Type,
+    utils::{ArgumentDecoder, ArgumentEncoder},
+    IDLArgs, TypeEnv,
+};
+use candid_parser::utils::CandidSource;
+use ic_cdk::api::call::{CallResult, RejectionCode};
+use ic_principal::Principal;
+
+use super::{
+    MuIcpKv, MuKv, MuKvCollection, MuKvCommit, MuKvIndex, MuKvOp, MuKvSwap, MuMemoryKv,
+    QuotaExceeded, SwapResult, TransactResult, WriteResult,
+};
+use crate::MuError;
+
+thread_local! {
+    /// The types of `mu-kv/mu-kv.did` and its service.
+    static DID: (TypeEnv, Type) = {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("../mu-kv/mu-kv.did");
+        let (env, service) = CandidSource::File(&path).load().unwrap();
+        (env, service.expect("mu-kv.did declares a service"))
+    };
+
+    static CANISTERS: RefCell<BTreeMap<Principal, MuMemoryKv>> = RefCell::default();
+}
+
+/// Installs a stand-in canister serving `kv`, and connects to it.
+pub(super) fn install(kv: MuMemoryKv) -> MuIcpKv {
+    CANISTERS.with_borrow_mut(|canisters| {
+        let principal = Principal::from_slice(&(canisters.len() as u64).to_be_bytes());
+        canisters.insert(principal, kv);
+        MuIcpKv::with_principal(principal)
+    })
+}
+
+/// Calls `method` on the stand-in canister `canister`, like `ic_cdk::call`.
+pub(super) async fn call<A: ArgumentEncoder, R: for<'a> ArgumentDecoder<'a>>(
+    canister: Principal,
+    method: &str,
+    args: A,
+) -> CallResult<R> {
+    let kv = CANISTERS
+        .with_borrow(|canisters| canisters.get(&canister).cloned())
+        .ok_or_else(|| {
+            (
+                RejectionCode::DestinationInvalid,
+                format!("Canister {} not found", canister),
+            )
+        })?;
+    let (args_types, reply_types) = DID.with(|(env, service)| {
+        env.get_method(service, method)
+            .map(|func| (func.args.clone(), func.rets.clone()))
+            .map_err(|_| {
+                (
+                    RejectionCode::DestinationInvalid,
+                    format!("mu-kv has no method {}", method),
+                )
+            })
+    })?;
+
+    let args = encode_args(args)
+        .and_then(|args| with_types(&args, &args_types))
+        .map_err(|e| (RejectionCode::CanisterError, e.to_string()))?;
+    let reply = serve(&kv, method, &args)
+        .await
+        .map_err(|message| (RejectionCode::CanisterError, message))?;
+    with_types(&reply, &reply_types)
+        .and_then(|reply| decode_args(&reply))
+        .map_err(|e| (RejectionCode::CanisterError, e.to_string()))
+}
+
+/// Decodes `bytes` as values of `types` from the did file and encodes them
+/// again, failing if they are not of those types.
+fn with_types(bytes: &[u8], types: &[Type]) -> candid::Result<Vec<u8>> {
+    DID.with(|(env, _)| {
+        IDLArgs::from_bytes_with_types(bytes, env, types)?.to_bytes_with_types(env, types)
+    })
+}
+
+/// Decodes the arguments of a call.
+fn args<T: for<'a> ArgumentDecoder<'a>>(bytes: &[u8]) -> Result<T, String> {
+    decode_args(bytes).map_err(|e| e.to_string())
+}
+
+/// Encodes the reply to a call.
+fn reply<T: ArgumentEncoder>(reply: T) -> Result<Vec<u8>, String> {
+    encode_args(reply).map_err(|e| e.to_string())
+}
+
+/// Rejects calls on another principal's collection, which a single store
+/// does not have.
+fn owned(owner: Option<Principal>) -> Result<(), String> {
+    match owner {
+        Some(owner) => Err(format!("{} has no collections here", owner)),
+        None => Ok(()),
+    }
+}
+
+/// Fails a call the store could not serve.
+fn failed(e: MuError) -> String {
+    format!("{:?}", e)
+}
+
+/// What mu-kv replies to a write.
+fn written(result: Result<(), MuError>) -> Result<WriteResult, String> {
+    match result {
+        Ok(()) => Ok(Ok(())),
+        Err(MuError::QuotaExceeded { quota, usage }) => Ok(Err(QuotaExceeded { quota, usage })),
+        Err(e) => Err(failed(e)),
+    }
+}
+
+/// The changes `batch_update` makes, as keys and their new values.
+type Changes = Vec<(String, Option<String>)>;
+
+/// Serves a call to `method` with the encoded `bytes` the way mu-kv does.
+async fn serve(kv: &MuMemoryKv, method: &str, bytes: &[u8]) -> Result<Vec<u8>, String> {
+    match method {
+        "has_collection" => {
+            let (name, owner): (String, Option<Principal>) = args(bytes)?;
+            owned(owner)?;
+            reply((kv.has_collection(&name),))
+        }
+        "create_collection" => {
+            let (name, indexes): (String, Option<Vec<MuKvIndex>>) = args(bytes)?;
+            // mu-kv replaces an existing collection
+            kv.open(&name).delete().await.map_err(failed)?;
+            kv.collection_with_indexes(&name, &indexes.unwrap_or_default())
+                .await
+                .map_err(failed)?;
+            reply(())
+        }
+        "delete_collection" => {
+            let (name, owner): (String, Option<Principal>) = args(bytes)?;
+            owned(owner)?;
+            kv.open(&name).delete().await.map_err(failed)?;
+            reply(())
+        }
+        "transact" => {
+            let (ops,): (Vec<MuKvOp>,) = args(bytes)?;
+            let missing = ops.iter().find_map(|op| {
+                let (MuKvOp::Put { collection, .. }
+                | MuKvOp::Delete { collection, .. }
+                | MuKvOp::Check { collection, .. }) = op;
+                (!kv.has_collection(collection)).then(|| collection.clone())
+            });
+            let result = match kv.transact(ops).await {
+                Ok(MuKvCommit::Committed { versions }) => TransactResult::Committed { versions },
+                Ok(MuKvCommit::Conflict { op, current }) => TransactResult::Conflict {
+                    op: op as u32,
+                    current,
+                },
+                Err(MuError::NotFound) => TransactResult::CollectionNotFound {
+                    collection: missing.unwrap_or_default(),
+                },
+                Err(MuError::QuotaExceeded { quota, usage }) => {
+                    TransactResult::QuotaExceeded(QuotaExceeded { quota, usage })
+                }
+                Err(e) => return Err(failed(e)),
+            };
+            reply((result,))
+        }
+        "query_collection" => {
+            let (name, key, owner): (String, String, Option<Principal>) = args(bytes)?;
+            owned(owner)?;
+            reply((kv.open(&name).query(&key).await.map_err(failed)?,))
+        }
+        "query_all_keys" => {
+            let (name, owner): (String, Option<Principal>) = args(bytes)?;
+            owned(owner)?;
+            reply((kv.open(&name).keys().await.map_err(failed)?,))
+        }
+        "query_all_values" => {
+            let (name, owner): (String, Option<Principal>) = args(bytes)?;
+            owned(owner)?;
+            reply((kv.open(&name).values().await.map_err(failed)?,))
+        }
+        "update_collection" => {
+            let (name, key, value, owner, ttl): (
+                String,
+                String,
+                Option<String>,
+                Option<Principal>,
+                Option<u64>,
+            ) = args(bytes)?;
+            owned(owner)?;
+            let collection = kv.open(&name);
+            let result = match (value, ttl) {
+                (Some(value), Some(ttl)) => {
+                    collection
+                        .update_with_ttl(&key, &value, Duration::from_secs(ttl))
+                        .await
+                }
+                (value, _) => collection.update(&key, value.as_deref()).await,
+            };
+            reply((written(result)?,))
+        }
+        "query_blob" => {
+            let (name, key, owner): (String, String, Option<Principal>) = args(bytes)?;
+            owned(owner)?;
+            reply((kv.open(&name).query_blob(&key).await.map_err(failed)?,))
+        }
+        "update_blob" => {
+            let (name, key, value, owner): (String, String, Option<Vec<u8>>, Option<Principal>) =
+                args(bytes)?;
+            owned(owner)?;
+            let result = kv.open(&name).update_blob(&key, value.as_deref()).await;
+            reply((written(result)?,))
+        }
+        "changes_since" => {
+            let (name, seq, limit, owner): (String, u64, u32, Option<Principal>) = args(bytes)?;
+            owned(owner)?;
+            reply((kv
+                .open(&name)
+                .changes_since(seq, limit)
+                .await
+                .map_err(failed)?,))
+        }
+        "usage" => {
+            let (name, owner): (String, Option<Principal>) = args(bytes)?;
+            owned(owner)?;
+            reply((kv.open(&name).usage().await.map_err(failed)?,))
+        }
+        "query_versioned" => {
+            let (name, key, owner): (String, String, Option<Principal>) = args(bytes)?;
+            owned(owner)?;
+            reply((kv.open(&name).query_versioned(&key).await.map_err(failed)?,))
+        }
+        "compare_and_swap" => {
+            let (name, key, expected_version, value, owner): (
+                String,
+                String,
+                Option<u64>,
+                Option<String>,
+                Option<Principal>,
+            ) = args(bytes)?;
+            owned(owner)?;
+            let result = match kv
+                .open(&name)
+                .compare_and_swap(&key, expected_version, value.as_deref())
+                .await
+            {
+                Ok(MuKvSwap::Swapped { version }) => SwapResult::Swapped { version },
+                Ok(MuKvSwap::Conflict { current }) => SwapResult::Conflict { current },
+                Err(MuError::NotFound) => SwapResult::CollectionNotFound,
+                Err(MuError::QuotaExceeded { quota, usage }) => {
+                    SwapResult::QuotaExceeded(QuotaExceeded { quota, usage })
+                }
+                Err(e) => return Err(failed(e)),
+            };
+            reply((result,))
+        }
+        "batch_get" => {
+            let (name, keys, owner): (String, Vec<String>, Option<Principal>) = args(bytes)?;
+            owned(owner)?;
+            let keys = keys.iter().map(String::as_str).collect::<Vec<_>>();
+            reply((kv.open(&name).get_many(&keys).await.map_err(failed)?,))
+        }
+        "batch_update" => {
+            let (name, changes, owner): (String, Changes, Option<Principal>) = args(bytes)?;
+            owned(owner)?;
+            let changes = changes
+                .iter()
+                .map(|(key, value)| (key.as_str(), value.as_deref()))
+                .collect::<Vec<_>>();
+            let result = kv.open(&name).update_many(&changes).await;
+            reply((written(result)?,))
+        }
+        "index_lookup" => {
+            let (name, index, value, limit, owner, start_after): (
+                String,
+                String,
+                String,
+                u32,
+                Option<Principal>,
+                Option<String>,
+            ) = args(bytes)?;
+            owned(owner)?;
+            let page = kv
+                .open(&name)
+                .index_lookup(&index, &value, start_after.as_deref(), limit)
+                .await
+                .ok();
+            reply((page,))
+        }
+        "scan" => {
+            let (name, prefix, start_after, limit, owner): (
+                String,
+                String,
+                Option<String>,
+                u32,
+                Option<Principal>,
+            ) = args(bytes)?;
+            owned(owner)?;
+            let page = kv
+                .open(&name)
+                .scan(&prefix, start_after.as_deref(), limit)
+                .await
+                .map_err(failed)?;
+            reply((page,))
+        }
+        "range" => {
+            let (name, from, to, limit, owner): (String, String, String, u32, Option<Principal>) =
+                args(bytes)?;
+            owned(owner)?;
+            reply((kv
+                .open(&name)
+                .range(&from, &to, limit)
+                .await
+                .map_err(failed)?,))
+        }
+        _ => Err(format!("{} is not supported by MuMemoryKv", method)),
+    }
+}
+
+mod tests {
+    use candid::types::{
+        internal::TypeContainer,
+        subtype::{subtype_with_config, OptReport},
+        Function, TypeInner,
+    };
+
+    use super::*;
+    use crate::kv::method_types;
+
+    #[test]
+    fn calls_match_the_did_file() {
+        let mut types = TypeContainer::new();
+        let methods = method_types(&mut types);
+        let calls = TypeInner::Service(
+            methods
+                .iter()
+                .map(|(method, args, rets)| {
+                    let func = Function {
+                        modes: vec![],
+                        args: args.clone(),
+                        rets: rets.clone(),
+                    };
+                    (method.to_string(), TypeInner::Func(func).into())
+                })
+                .collect(),
+        )
+        .into();
+
+        DID.with(|(did, service)| {
+            let mut env = did.clone();
+            let calls = env.merge_type(types.env, calls);
+            for (method, _, _) in methods {
+                let expected = env.get_method(service, method).unwrap();
+                let called = Function {
+                    modes: expected.modes.clone(),
+                    ..env.get_method(&calls, method).unwrap().clone()
+                };
+                subtype_with_config(
+                    OptReport::Error,
+                    &mut Default::default(),
+                    &env,
+                    &TypeInner::Func(expected.clone()).into(),
+                    &TypeInner::Func(called).into(),
+                )
+                .unwrap_or_else(|e| panic!("{} is called with the wrong types: {}", method, e));
+            }
+        });
+    }
+}
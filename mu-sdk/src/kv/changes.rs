@@ -0,0 +1,93 @@
+use candid::CandidType;
+use serde::Deserialize;
+
+use super::MuKvCollection;
+use crate::MuError;
+
+/// The number of changes [`MuKvWatcher`] fetches per call.
+const PAGE_SIZE: u32 = 100;
+
+/// How many changes mu-kv keeps in the log of each collection.
+#[cfg(any(test, feature = "testing"))]
+pub(super) const MAX_CHANGES: u64 = 1000;
+
+#[derive(CandidType, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub enum MuKvChangeKind {
+    /// The text value was written and now has this version.
+    Set {
+        version: u64,
+    },
+    /// The text value was removed, or expired.
+    Removed,
+    BlobSet,
+    BlobRemoved,
+}
+
+/// A change to one key of a collection. Changes of a collection are
+/// numbered in the order they were made, starting at 1.
+#[derive(CandidType, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct MuKvChange {
+    pub seq: u64,
+    pub key: String,
+    pub kind: MuKvChangeKind,
+}
+
+/// Changes returned by [`MuKvCollection::changes_since`].
+#[derive(CandidType, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct MuKvChanges {
+    pub changes: Vec<MuKvChange>,
+    /// The sequence number of the collection's latest change.
+    pub last_seq: u64,
+    /// Some of the requested changes are no longer in the log, or the
+    /// collection was recreated, so anything derived from it has to be
+    /// rebuilt by reading the collection again.
+    pub truncated: bool,
+}
+
+/// Follows the changes of a collection, for example to invalidate a cache.
+pub struct MuKvWatcher<'a, C> {
+    collection: &'a C,
+    seq: u64,
+}
+
+impl<'a, C: MuKvCollection> MuKvWatcher<'a, C> {
+    pub(super) fn new(collection: &'a C, seq: u64) -> Self {
+        Self { collection, seq }
+    }
+
+    /// The sequence number of the last change the watcher returned.
+    pub fn seq(&self) -> u64 {
+        self.seq
+    }
+
+    /// All changes made since the previous poll, oldest first.
+    ///
+    /// If changes were missed, the result is `truncated` and holds no
+    /// changes, and the watcher skips ahead to the latest change.
+    pub async fn poll(&mut self) -> Result<MuKvChanges, MuError> {
+        let mut result = MuKvChanges {
+            last_seq: self.seq,
+            ..Default::default()
+        };
+
+        loop {
+            let page = self.collection.changes_since(self.seq, PAGE_SIZE).await?;
+            result.last_seq = page.last_seq;
+            if page.truncated {
+                self.seq = page.last_seq;
+                result.changes.clear();
+                result.truncated = true;
+                return Ok(result);
+            }
+
+            let Some(last) = page.changes.last() else {
+                return Ok(result);
+            };
+            self.seq = last.seq;
+            result.changes.extend(page.changes);
+            if self.seq >= page.last_seq {
+                return Ok(result);
+            }
+        }
+    }
+}
@@ -0,0 +1,60 @@
+use candid::CandidType;
+use serde::Deserialize;
+use serde_json::{Number, Value};
+
+/// An index on the field at `path` of JSON values, given as object keys
+/// separated by dots; an empty path indexes the whole value. String fields
+/// are indexed by their text, numbers and booleans by their JSON form, with
+/// whole numbers written like integers, so `1.0` is found by `1`. Values
+/// that are not JSON or lack the field are left out.
+#[derive(CandidType, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct MuKvIndex {
+    pub name: String,
+    pub path: String,
+}
+
+impl MuKvIndex {
+    pub fn new(name: &str, path: &str) -> Self {
+        Self {
+            name: name.to_owned(),
+            path: path.to_owned(),
+        }
+    }
+
+    /// What an index maps a field with this value to, if anything. This is
+    /// the value to pass to [`MuKvCollection::index_lookup`](super::MuKvCollection::index_lookup)
+    /// to find the entries whose field equals `field`.
+    pub fn lookup_value(field: &Value) -> Option<String> {
+        index_key(field)
+    }
+}
+
+/// What an index maps a field with this value to, if anything.
+pub(super) fn index_key(field: &Value) -> Option<String> {
+    match field {
+        Value::String(text) => Some(text.clone()),
+        Value::Number(number) => Some(index_number(number)),
+        Value::Bool(_) => Some(field.to_string()),
+        _ => None,
+    }
+}
+
+/// Whole numbers written as floats are indexed like integers, as mu-kv does.
+fn index_number(number: &Number) -> String {
+    match number.as_f64() {
+        Some(float) if number.is_f64() && float.fract() == 0.0 && float.abs() < 2f64.powi(53) => {
+            (float as i64).to_string()
+        }
+        _ => number.to_string(),
+    }
+}
+
+/// What an index on `path` maps a JSON value to, if anything.
+#[cfg(any(test, feature = "testing"))]
+pub(super) fn indexed_value(value: &Value, path: &str) -> Option<String> {
+    let field = path
+        .split('.')
+        .filter(|part| !part.is_empty())
+        .try_fold(value, |value, part| value.get(part))?;
+    index_key(field)
+}
@@ -0,0 +1,982 @@
+use std::{
+    collections::{BTreeMap, VecDeque},
+    ops::Bound,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use super::{
+    changes::MAX_CHANGES, index::indexed_value, scan::MAX_PAGE_SIZE, MuKv, MuKvChange,
+    MuKvChangeKind, MuKvChanges, MuKvCollection, MuKvCommit, MuKvIndex, MuKvOp, MuKvPage,
+    MuKvQuota, MuKvSwap, MuKvUsage, MuKvUsageReport, MuKvVersioned,
+};
+use crate::MuError;
+
+#[derive(Clone)]
+struct Entry {
+    versioned: MuKvVersioned,
+    expires_at: Option<Instant>,
+}
+
+impl Entry {
+    fn is_expired(&self) -> bool {
+        self.expires_at
+            .is_some_and(|expires_at| expires_at <= Instant::now())
+    }
+}
+
+#[derive(Clone, Default)]
+struct Collection {
+    entries: BTreeMap<String, Entry>,
+    blobs: BTreeMap<String, Vec<u8>>,
+    changes: VecDeque<MuKvChange>,
+    last_seq: u64,
+    /// The sequence number at which a collection of the same name was
+    /// deleted, or 0.
+    reset_seq: u64,
+    /// The path of each index, by name.
+    indexes: BTreeMap<String, String>,
+}
+
+impl Collection {
+    fn log_change(&mut self, key: &str, kind: MuKvChangeKind) {
+        self.last_seq += 1;
+        self.changes.push_back(MuKvChange {
+            seq: self.last_seq,
+            key: key.to_owned(),
+            kind,
+        });
+        if self.changes.len() as u64 > MAX_CHANGES {
+            self.changes.pop_front();
+        }
+    }
+
+    /// Counted like mu-kv does: keys and values, including expired entries
+    /// that were not removed yet.
+    fn usage(&self) -> MuKvUsage {
+        let texts = self
+            .entries
+            .iter()
+            .map(|(key, entry)| key.len() + entry.versioned.value.len());
+        let blobs = self
+            .blobs
+            .iter()
+            .map(|(key, value)| key.len() + value.len());
+        MuKvUsage {
+            bytes: texts.chain(blobs).sum::<usize>() as u64,
+            entries: (self.entries.len() + self.blobs.len()) as u64,
+        }
+    }
+}
+
+#[derive(Default)]
+struct Store {
+    collections: BTreeMap<String, Collection>,
+    /// Like mu-kv, the last sequence number of each deleted collection, which
+    /// one created under the same name goes on from.
+    deleted_seqs: BTreeMap<String, u64>,
+    /// Like mu-kv, versions come from a single counter, starting at 1.
+    last_version: u64,
+    quota: Option<MuKvQuota>,
+}
+
+impl Store {
+    /// Writes or removes an entry, returning its new version. Like mu-kv,
+    /// writes to a deleted collection are ignored.
+    fn write(
+        &mut self,
+        collection: &str,
+        key: &str,
+        value: Option<&str>,
+        ttl: Option<Duration>,
+    ) -> Option<u64> {
+        let collection = self.collections.get_mut(collection)?;
+        match value {
+            Some(value) => {
+                self.last_version += 1;
+                collection.log_change(
+                    key,
+                    MuKvChangeKind::Set {
+                        version: self.last_version,
+                    },
+                );
+                collection.entries.insert(
+                    key.to_owned(),
+                    Entry {
+                        versioned: MuKvVersioned {
+                            value: value.to_owned(),
+                            version: self.last_version,
+                        },
+                        expires_at: ttl.map(|ttl| Instant::now() + ttl),
+                    },
+                );
+                Some(self.last_version)
+            }
+            None => {
+                if collection.entries.remove(key).is_some() {
+                    collection.log_change(key, MuKvChangeKind::Removed);
+                }
+                None
+            }
+        }
+    }
+
+    fn write_blob(&mut self, collection: &str, key: &str, value: Option<&[u8]>) {
+        if let Some(collection) = self.collections.get_mut(collection) {
+            match value {
+                Some(value) => {
+                    collection.blobs.insert(key.to_owned(), value.to_vec());
+                    collection.log_change(key, MuKvChangeKind::BlobSet);
+                }
+                None => {
+                    if collection.blobs.remove(key).is_some() {
+                        collection.log_change(key, MuKvChangeKind::BlobRemoved);
+                    }
+                }
+            }
+        }
+    }
+
+    fn usage(&self) -> MuKvUsage {
+        self.collections.values().map(Collection::usage).fold(
+            MuKvUsage::default(),
+            |total, usage| MuKvUsage {
+                bytes: total.bytes + usage.bytes,
+                entries: total.entries + usage.entries,
+            },
+        )
+    }
+
+    fn quota(&self) -> MuKvQuota {
+        self.quota.unwrap_or(MuKvQuota {
+            max_bytes: u64::MAX,
+            max_entries: u64::MAX,
+        })
+    }
+
+    /// Applies the writes made by `f`, unless they grow the usage over the
+    /// quota, in which case they are undone.
+    fn checked<T>(&mut self, f: impl FnOnce(&mut Self) -> T) -> Result<T, MuError> {
+        let Some(quota) = self.quota else {
+            return Ok(f(self));
+        };
+
+        let before = self.usage();
+        let snapshot = (self.collections.clone(), self.last_version);
+        let result = f(self);
+        let usage = self.usage();
+        if (usage.bytes > before.bytes && usage.bytes > quota.max_bytes)
+            || (usage.entries > before.entries && usage.entries > quota.max_entries)
+        {
+            (self.collections, self.last_version) = snapshot;
+            return Err(MuError::QuotaExceeded { quota, usage });
+        }
+        Ok(result)
+    }
+}
+
+/// An in-process [`MuKv`] for unit testing functions without a replica.
+///
+/// Clones share the same data, so a store handed to the code under test can
+/// be inspected afterwards.
+#[derive(Clone, Default)]
+pub struct MuMemoryKv {
+    store: Arc<Mutex<Store>>,
+}
+
+impl MuMemoryKv {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A store that rejects writes beyond `quota`, like mu-kv does. Stores
+    /// created with [`MuMemoryKv::new`] have no quota.
+    pub fn with_quota(quota: MuKvQuota) -> Self {
+        let kv = Self::default();
+        kv.store.lock().unwrap().quota = Some(quota);
+        kv
+    }
+}
+
+pub(super) struct MuMemoryKvCollection {
+    store: Arc<Mutex<Store>>,
+    name: String,
+}
+
+#[cfg(test)]
+impl MuMemoryKv {
+    /// Whether the collection `name` exists.
+    pub(super) fn has_collection(&self, name: &str) -> bool {
+        self.store.lock().unwrap().collections.contains_key(name)
+    }
+
+    /// The collection `name`, without creating it if it does not exist.
+    pub(super) fn open(&self, name: &str) -> MuMemoryKvCollection {
+        MuMemoryKvCollection {
+            store: self.store.clone(),
+            name: name.to_owned(),
+        }
+    }
+}
+
+impl MuKv for MuMemoryKv {
+    async fn collection_with_indexes(
+        &self,
+        name: &str,
+        indexes: &[MuKvIndex],
+    ) -> Result<impl MuKvCollection, MuError> {
+        let mut store = self.store.lock().unwrap();
+        if !store.collections.contains_key(name) {
+            // Skips a sequence number to mark the reset, like mu-kv
+            let reset_seq = store.deleted_seqs.get(name).map_or(0, |seq| seq + 1);
+            store.collections.insert(
+                name.to_owned(),
+                Collection {
+                    last_seq: reset_seq,
+                    reset_seq,
+                    indexes: indexes
+                        .iter()
+                        .map(|index| (index.name.clone(), index.path.clone()))
+                        .collect(),
+                    ..Default::default()
+                },
+            );
+        }
+
+        Ok(MuMemoryKvCollection {
+            store: self.store.clone(),
+            name: name.to_owned(),
+        })
+    }
+
+    async fn transact(&self, ops: Vec<MuKvOp>) -> Result<MuKvCommit, MuError> {
+        let mut store = self.store.lock().unwrap();
+        let target = |op: &MuKvOp| match op {
+            MuKvOp::Put {
+                collection, key, ..
+            }
+            | MuKvOp::Delete { collection, key }
+            | MuKvOp::Check {
+                collection, key, ..
+            } => (collection.clone(), key.clone()),
+        };
+        if ops
+            .iter()
+            .any(|op| !store.collections.contains_key(&target(op).0))
+        {
+            return Err(MuError::NotFound);
+        }
+
+        for (i, op) in ops.iter().enumerate() {
+            if let MuKvOp::Check { version, .. } = op {
+                let (collection, key) = target(op);
+                let current = store.collections[&collection]
+                    .entries
+                    .get(&key)
+                    .filter(|entry| !entry.is_expired())
+                    .map(|entry| entry.versioned.clone());
+                if current.as_ref().map(|entry| entry.version) != *version {
+                    return Ok(MuKvCommit::Conflict { op: i, current });
+                }
+            }
+        }
+
+        let versions = store.checked(|store| {
+            ops.iter()
+                .map(|op| {
+                    let (collection, key) = target(op);
+                    match op {
+                        MuKvOp::Put { value, .. } => {
+                            store.write(&collection, &key, Some(value), None)
+                        }
+                        MuKvOp::Delete { .. } => store.write(&collection, &key, None, None),
+                        MuKvOp::Check { .. } => None,
+                    }
+                })
+                .collect()
+        })?;
+        Ok(MuKvCommit::Committed { versions })
+    }
+}
+
+impl MuMemoryKvCollection {
+    /// Pages through the collection the same way mu-kv's `scan` and `range`
+    /// endpoints do.
+    fn page(&self, start: Bound<&str>, end: Bound<&str>, prefix: &str, limit: u32) -> MuKvPage {
+        self.with(|collection| {
+            let Some(collection) = collection else {
+                return MuKvPage::default();
+            };
+            let mut matching = collection
+                .range::<str, _>((start, end))
+                .take_while(|(key, _)| key.starts_with(prefix))
+                .map(|(key, entry)| (key.clone(), entry.value.clone()));
+
+            let entries = matching
+                .by_ref()
+                .take(limit.clamp(1, MAX_PAGE_SIZE) as usize)
+                .collect();
+            let next = matching.next().map(|(key, _)| key);
+            MuKvPage { entries, next }
+        })
+    }
+
+    /// Calls `f` with the entries of the collection that have not expired,
+    /// or `None` if the collection was deleted.
+    fn with<T>(&self, f: impl FnOnce(Option<&BTreeMap<String, MuKvVersioned>>) -> T) -> T {
+        let store = self.store.lock().unwrap();
+        let live = store.collections.get(&self.name).map(|collection| {
+            collection
+                .entries
+                .iter()
+                .filter(|(_, entry)| !entry.is_expired())
+                .map(|(key, entry)| (key.clone(), entry.versioned.clone()))
+                .collect()
+        });
+        f(live.as_ref())
+    }
+
+    /// Runs `f` on the store, rejecting its writes if they exceed the quota.
+    fn write<T>(&self, f: impl FnOnce(&mut Store) -> T) -> Result<T, MuError> {
+        self.store.lock().unwrap().checked(f)
+    }
+}
+
+impl MuKvCollection for MuMemoryKvCollection {
+    async fn query(&self, key: &str) -> Result<Option<String>, MuError> {
+        Ok(self.query_versioned(key).await?.map(|entry| entry.value))
+    }
+
+    async fn keys(&self) -> Result<Vec<String>, MuError> {
+        Ok(self.with(|collection| {
+            collection
+                .map(|c| c.keys().cloned().collect())
+                .unwrap_or_default()
+        }))
+    }
+
+    async fn values(&self) -> Result<Vec<String>, MuError> {
+        Ok(self.with(|collection| {
+            collection
+                .map(|c| c.values().map(|entry| entry.value.clone()).collect())
+                .unwrap_or_default()
+        }))
+    }
+
+    async fn update(&self, key: &str, value: Option<&str>) -> Result<(), MuError> {
+        self.write(|store| store.write(&self.name, key, value, None))?;
+        Ok(())
+    }
+
+    async fn delete(&self) -> Result<(), MuError> {
+        let mut store = self.store.lock().unwrap();
+        if let Some(collection) = store.collections.remove(&self.name) {
+            store
+                .deleted_seqs
+                .insert(self.name.clone(), collection.last_seq);
+        }
+        Ok(())
+    }
+
+    async fn get_many(&self, keys: &[&str]) -> Result<Vec<Option<String>>, MuError> {
+        Ok(self.with(|collection| {
+            keys.iter()
+                .map(|key| collection.and_then(|c| c.get(*key).map(|entry| entry.value.clone())))
+                .collect()
+        }))
+    }
+
+    async fn index_lookup(
+        &self,
+        index: &str,
+        value: &str,
+        start_after: Option<&str>,
+        limit: u32,
+    ) -> Result<MuKvPage, MuError> {
+        let store = self.store.lock().unwrap();
+        let Some(collection) = store.collections.get(&self.name) else {
+            return Err(MuError::NotFound);
+        };
+        let Some(path) = collection.indexes.get(index) else {
+            return Err(MuError::NotFound);
+        };
+
+        let start = start_after.map_or(Bound::Unbounded, Bound::Excluded);
+        let mut matching = collection
+            .entries
+            .range::<str, _>((start, Bound::Unbounded))
+            .filter(|(_, entry)| !entry.is_expired())
+            .filter(|(_, entry)| {
+                serde_json::from_str(&entry.versioned.value)
+                    .ok()
+                    .and_then(|json| indexed_value(&json, path))
+                    .is_some_and(|indexed| indexed == value)
+            })
+            .map(|(key, entry)| (key.clone(), entry.versioned.value.clone()));
+
+        let entries = matching
+            .by_ref()
+            .take(limit.clamp(1, MAX_PAGE_SIZE) as usize)
+            .collect();
+        let next = matching.next().map(|(key, _)| key);
+        Ok(MuKvPage { entries, next })
+    }
+
+    async fn update_many(&self, changes: &[(&str, Option<&str>)]) -> Result<(), MuError> {
+        self.write(|store| {
+            for (key, value) in changes {
+                store.write(&self.name, key, *value, None);
+            }
+        })
+    }
+
+    async fn scan(
+        &self,
+        prefix: &str,
+        start_after: Option<&str>,
+        limit: u32,
+    ) -> Result<MuKvPage, MuError> {
+        let start = match start_after {
+            Some(key) if key >= prefix => Bound::Excluded(key),
+            _ => Bound::Included(prefix),
+        };
+        Ok(self.page(start, Bound::Unbounded, prefix, limit))
+    }
+
+    async fn range(&self, from: &str, to: &str, limit: u32) -> Result<MuKvPage, MuError> {
+        if from >= to {
+            return Ok(MuKvPage::default());
+        }
+        Ok(self.page(Bound::Included(from), Bound::Excluded(to), "", limit))
+    }
+
+    async fn query_versioned(&self, key: &str) -> Result<Option<MuKvVersioned>, MuError> {
+        Ok(self.with(|collection| collection.and_then(|c| c.get(key).cloned())))
+    }
+
+    async fn compare_and_swap(
+        &self,
+        key: &str,
+        expected_version: Option<u64>,
+        value: Option<&str>,
+    ) -> Result<MuKvSwap, MuError> {
+        let mut store = self.store.lock().unwrap();
+        let Some(collection) = store.collections.get(&self.name) else {
+            return Err(MuError::NotFound);
+        };
+
+        let current = collection
+            .entries
+            .get(key)
+            .filter(|entry| !entry.is_expired())
+            .map(|entry| entry.versioned.clone());
+        if current.as_ref().map(|entry| entry.version) != expected_version {
+            return Ok(MuKvSwap::Conflict { current });
+        }
+        let version = store.checked(|store| store.write(&self.name, key, value, None))?;
+        Ok(MuKvSwap::Swapped { version })
+    }
+
+    async fn update_with_ttl(&self, key: &str, value: &str, ttl: Duration) -> Result<(), MuError> {
+        self.write(|store| store.write(&self.name, key, Some(value), Some(ttl)))?;
+        Ok(())
+    }
+
+    async fn query_blob(&self, key: &str) -> Result<Option<Vec<u8>>, MuError> {
+        let store = self.store.lock().unwrap();
+        Ok(store
+            .collections
+            .get(&self.name)
+            .and_then(|collection| collection.blobs.get(key).cloned()))
+    }
+
+    async fn update_blob(&self, key: &str, value: Option<&[u8]>) -> Result<(), MuError> {
+        self.write(|store| store.write_blob(&self.name, key, value))
+    }
+
+    async fn changes_since(&self, seq: u64, limit: u32) -> Result<MuKvChanges, MuError> {
+        let store = self.store.lock().unwrap();
+        let Some(collection) = store.collections.get(&self.name) else {
+            return Ok(MuKvChanges::default());
+        };
+
+        let oldest_seq = collection
+            .changes
+            .front()
+            .map_or(collection.last_seq + 1, |change| change.seq)
+            .max(collection.reset_seq + 1);
+        Ok(MuKvChanges {
+            changes: collection
+                .changes
+                .iter()
+                .filter(|change| change.seq > seq)
+                .take(limit.clamp(1, MAX_PAGE_SIZE) as usize)
+                .cloned()
+                .collect(),
+            last_seq: collection.last_seq,
+            truncated: seq > collection.last_seq || seq + 1 < oldest_seq,
+        })
+    }
+
+    async fn usage(&self) -> Result<MuKvUsageReport, MuError> {
+        let store = self.store.lock().unwrap();
+        Ok(MuKvUsageReport {
+            collection: store
+                .collections
+                .get(&self.name)
+                .map(Collection::usage)
+                .unwrap_or_default(),
+            owner: store.usage(),
+            quota: store.quota(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        future::Future,
+        pin::pin,
+        task::{Context, Poll, Waker},
+    };
+
+    use serde::{Deserialize, Serialize};
+
+    use super::*;
+
+    /// Runs a test, which never has to wait as it only calls the memory store
+    /// or the stand-in canister serving one.
+    fn block_on<F: Future>(future: F) -> F::Output {
+        match pin!(future).poll(&mut Context::from_waker(Waker::noop())) {
+            Poll::Ready(output) => output,
+            Poll::Pending => panic!("MuMemoryKv calls complete immediately"),
+        }
+    }
+
+    /// Declares each test twice: once on a [`MuMemoryKv`], and once on a
+    /// [`MuIcpKv`] calling a stand-in canister that serves one, so every call
+    /// and reply also goes through mu-kv's Candid interface.
+    macro_rules! tests {
+        ($($test:ident),* $(,)?) => {
+            mod memory {
+                $(
+                    #[test]
+                    fn $test() {
+                        super::block_on(super::$test(|kv| kv));
+                    }
+                )*
+            }
+
+            mod icp {
+                $(
+                    #[test]
+                    fn $test() {
+                        super::block_on(super::$test(crate::kv::canister::install));
+                    }
+                )*
+            }
+        };
+    }
+
+    tests! {
+        query_and_update,
+        collections_are_shared_by_name,
+        keys_and_values_are_in_key_order,
+        entries_expire_after_their_ttl,
+        delete_removes_the_collection,
+        blobs_are_separate_from_text,
+        usage_and_quotas,
+        compare_and_swap_checks_versions,
+        get_many_and_update_many,
+        scans_and_ranges_page_through_entries,
+        changes_are_logged_and_watched,
+        change_sequences_survive_deleting,
+        indexes_and_typed_lookups,
+        decode_errors_name_the_entry,
+        whole_numbers_are_found_by_integers,
+        transactions_apply_all_or_nothing,
+    }
+
+    fn pairs(entries: &[(&str, &str)]) -> Vec<(String, String)> {
+        entries
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect()
+    }
+
+    async fn query_and_update<K: MuKv>(connect: fn(MuMemoryKv) -> K) {
+        let kv = connect(MuMemoryKv::new());
+        let users = kv.collection("users").await.unwrap();
+        assert_eq!(users.query("alice").await.unwrap(), None);
+
+        users.update("alice", Some("admin")).await.unwrap();
+        assert_eq!(
+            users.query("alice").await.unwrap().as_deref(),
+            Some("admin")
+        );
+
+        users.update("alice", None).await.unwrap();
+        assert_eq!(users.query("alice").await.unwrap(), None);
+    }
+
+    async fn collections_are_shared_by_name<K: MuKv>(connect: fn(MuMemoryKv) -> K) {
+        let kv = connect(MuMemoryKv::new());
+        let first = kv.collection("users").await.unwrap();
+        first.update("alice", Some("admin")).await.unwrap();
+
+        let second = kv.collection("users").await.unwrap();
+        assert_eq!(
+            second.query("alice").await.unwrap().as_deref(),
+            Some("admin")
+        );
+        let other = kv.collection("other").await.unwrap();
+        assert_eq!(other.query("alice").await.unwrap(), None);
+    }
+
+    async fn keys_and_values_are_in_key_order<K: MuKv>(connect: fn(MuMemoryKv) -> K) {
+        let kv = connect(MuMemoryKv::new());
+        let users = kv.collection("users").await.unwrap();
+        users.update("bob", Some("2")).await.unwrap();
+        users.update("alice", Some("1")).await.unwrap();
+
+        assert_eq!(users.keys().await.unwrap(), ["alice", "bob"]);
+        assert_eq!(users.values().await.unwrap(), ["1", "2"]);
+    }
+
+    async fn entries_expire_after_their_ttl<K: MuKv>(connect: fn(MuMemoryKv) -> K) {
+        let kv = connect(MuMemoryKv::new());
+        let sessions = kv.collection("sessions").await.unwrap();
+        sessions
+            .update_with_ttl("live", "1", Duration::from_secs(3600))
+            .await
+            .unwrap();
+        sessions
+            .update_with_ttl("dead", "2", Duration::ZERO)
+            .await
+            .unwrap();
+
+        assert_eq!(sessions.query("dead").await.unwrap(), None);
+        assert_eq!(sessions.keys().await.unwrap(), ["live"]);
+    }
+
+    async fn delete_removes_the_collection<K: MuKv>(connect: fn(MuMemoryKv) -> K) {
+        let kv = connect(MuMemoryKv::new());
+        let users = kv.collection("users").await.unwrap();
+        users.update("alice", Some("admin")).await.unwrap();
+        users.delete().await.unwrap();
+
+        assert!(users.keys().await.unwrap().is_empty());
+        // Writes to a deleted collection are ignored
+        users.update("alice", Some("admin")).await.unwrap();
+        assert_eq!(users.query("alice").await.unwrap(), None);
+        assert!(matches!(
+            users.compare_and_swap("alice", None, Some("admin")).await,
+            Err(MuError::NotFound)
+        ));
+    }
+
+    async fn blobs_are_separate_from_text<K: MuKv>(connect: fn(MuMemoryKv) -> K) {
+        let kv = connect(MuMemoryKv::new());
+        let files = kv.collection("files").await.unwrap();
+        files.update("a", Some("text")).await.unwrap();
+        files.update_blob("a", Some(&[0, 255])).await.unwrap();
+
+        assert_eq!(files.query("a").await.unwrap().as_deref(), Some("text"));
+        assert_eq!(files.query_blob("a").await.unwrap(), Some(vec![0, 255]));
+
+        files.update_blob("a", None).await.unwrap();
+        assert_eq!(files.query_blob("a").await.unwrap(), None);
+    }
+
+    async fn usage_and_quotas<K: MuKv>(connect: fn(MuMemoryKv) -> K) {
+        let kv = connect(MuMemoryKv::with_quota(MuKvQuota {
+            max_bytes: 10,
+            max_entries: 2,
+        }));
+        let files = kv.collection("files").await.unwrap();
+        files.update("ab", Some("cde")).await.unwrap();
+        files.update_blob("f", Some(&[1, 2])).await.unwrap();
+
+        let report = files.usage().await.unwrap();
+        assert_eq!(
+            report.collection,
+            MuKvUsage {
+                bytes: 8,
+                entries: 2
+            }
+        );
+        assert_eq!(report.owner, report.collection);
+        assert_eq!(report.quota.max_entries, 2);
+
+        assert!(matches!(
+            files.update("x", Some("y")).await,
+            Err(MuError::QuotaExceeded { .. })
+        ));
+        assert_eq!(files.query("x").await.unwrap(), None);
+    }
+
+    async fn compare_and_swap_checks_versions<K: MuKv>(connect: fn(MuMemoryKv) -> K) {
+        let kv = connect(MuMemoryKv::new());
+        let counters = kv.collection("counters").await.unwrap();
+        let MuKvSwap::Swapped {
+            version: Some(version),
+        } = counters
+            .compare_and_swap("hits", None, Some("1"))
+            .await
+            .unwrap()
+        else {
+            panic!("creating a missing key failed");
+        };
+        assert_eq!(
+            counters.query_versioned("hits").await.unwrap(),
+            Some(MuKvVersioned {
+                value: "1".to_owned(),
+                version
+            })
+        );
+
+        assert!(matches!(
+            counters.compare_and_swap("hits", None, Some("1")).await.unwrap(),
+            MuKvSwap::Conflict { current: Some(ref current) } if current.version == version
+        ));
+        assert!(matches!(
+            counters
+                .compare_and_swap("hits", Some(version), Some("2"))
+                .await
+                .unwrap(),
+            MuKvSwap::Swapped { version: Some(v) } if v > version
+        ));
+    }
+
+    async fn get_many_and_update_many<K: MuKv>(connect: fn(MuMemoryKv) -> K) {
+        let kv = connect(MuMemoryKv::new());
+        let users = kv.collection("users").await.unwrap();
+        users.update("bob", Some("old")).await.unwrap();
+        users
+            .update_many(&[("alice", Some("1")), ("bob", None), ("carol", Some("3"))])
+            .await
+            .unwrap();
+
+        assert_eq!(
+            users.get_many(&["alice", "bob", "carol"]).await.unwrap(),
+            [Some("1".to_owned()), None, Some("3".to_owned())]
+        );
+    }
+
+    async fn scans_and_ranges_page_through_entries<K: MuKv>(connect: fn(MuMemoryKv) -> K) {
+        let kv = connect(MuMemoryKv::new());
+        let users = kv.collection("users").await.unwrap();
+        for key in ["a/1", "a/2", "a/3", "b/1"] {
+            users.update(key, Some(key)).await.unwrap();
+        }
+
+        let page = users.scan("a/", None, 2).await.unwrap();
+        assert_eq!(page.entries, pairs(&[("a/1", "a/1"), ("a/2", "a/2")]));
+        assert_eq!(page.next.as_deref(), Some("a/3"));
+        let page = users.scan("a/", Some("a/2"), 2).await.unwrap();
+        assert_eq!(page.entries, pairs(&[("a/3", "a/3")]));
+        assert_eq!(page.next, None);
+
+        let page = users.range("a/2", "b/1", 10).await.unwrap();
+        assert_eq!(page.entries, pairs(&[("a/2", "a/2"), ("a/3", "a/3")]));
+
+        assert_eq!(users.scan_all("a/").collect().await.unwrap().len(), 3);
+        assert_eq!(
+            users.range_all("a/3", "c").collect().await.unwrap().len(),
+            2
+        );
+    }
+
+    async fn changes_are_logged_and_watched<K: MuKv>(connect: fn(MuMemoryKv) -> K) {
+        let kv = connect(MuMemoryKv::new());
+        let users = kv.collection("users").await.unwrap();
+        let mut watcher = users.watch(0);
+        users.update("alice", Some("1")).await.unwrap();
+        users.update_blob("avatar", Some(&[1])).await.unwrap();
+
+        let changes = users.changes_since(0, 10).await.unwrap();
+        assert_eq!(changes.last_seq, 2);
+        assert!(!changes.truncated);
+        assert!(matches!(
+            changes.changes[0].kind,
+            MuKvChangeKind::Set { .. }
+        ));
+        assert_eq!(changes.changes[1].kind, MuKvChangeKind::BlobSet);
+
+        assert_eq!(watcher.poll().await.unwrap().changes.len(), 2);
+        users.update("alice", None).await.unwrap();
+        let polled = watcher.poll().await.unwrap();
+        assert_eq!(polled.changes.len(), 1);
+        assert_eq!(polled.changes[0].kind, MuKvChangeKind::Removed);
+        assert_eq!(watcher.seq(), 3);
+    }
+
+    async fn change_sequences_survive_deleting<K: MuKv>(connect: fn(MuMemoryKv) -> K) {
+        let kv = connect(MuMemoryKv::new());
+        let users = kv.collection("users").await.unwrap();
+        users.update("alice", Some("1")).await.unwrap();
+        users.delete().await.unwrap();
+
+        let users = kv.collection("users").await.unwrap();
+        users.update("alice", Some("2")).await.unwrap();
+        let changes = users.changes_since(1, 10).await.unwrap();
+        assert_eq!(changes.last_seq, 3);
+        assert!(changes.truncated);
+        assert!(users.changes_since(0, 10).await.unwrap().truncated);
+        assert!(!users.changes_since(2, 10).await.unwrap().truncated);
+    }
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    struct User {
+        name: String,
+        role: String,
+    }
+
+    async fn indexes_and_typed_lookups<K: MuKv>(connect: fn(MuMemoryKv) -> K) {
+        let kv = connect(MuMemoryKv::new());
+        let indexes = [MuKvIndex::new("by_role", "role")];
+        let users = kv
+            .collection_with_indexes("users", &indexes)
+            .await
+            .unwrap()
+            .typed::<&str, User>();
+        for (name, role) in [("alice", "admin"), ("bob", "guest"), ("carol", "admin")] {
+            let user = User {
+                name: name.to_owned(),
+                role: role.to_owned(),
+            };
+            users.set(&name, &user).await.unwrap();
+        }
+
+        let admins = users
+            .inner()
+            .index_lookup("by_role", "admin", None, 1)
+            .await
+            .unwrap();
+        assert_eq!(admins.entries[0].0, "alice");
+        assert_eq!(admins.next.as_deref(), Some("carol"));
+        let admins = users
+            .inner()
+            .index_lookup("by_role", "admin", Some("alice"), 1)
+            .await
+            .unwrap();
+        assert_eq!(admins.entries[0].0, "carol");
+        assert_eq!(admins.next, None);
+        assert_eq!(
+            users
+                .inner()
+                .index_lookup_all("by_role", "admin")
+                .collect()
+                .await
+                .unwrap()
+                .len(),
+            2
+        );
+
+        let admins = users
+            .lookup("by_role", "admin", Some("alice"), 10)
+            .await
+            .unwrap();
+        assert_eq!(admins.len(), 1);
+        assert_eq!(admins[0].1.name, "carol");
+        let mut guests = users.lookup("by_role", "guest", None, 10).await.unwrap();
+        assert_eq!(guests.len(), 1);
+        assert_eq!(guests[0].1.name, "bob");
+        assert_eq!(users.get(&"bob").await.unwrap(), Some(guests.remove(0).1));
+
+        assert!(matches!(
+            users
+                .inner()
+                .index_lookup("missing", "admin", None, 10)
+                .await,
+            Err(MuError::NotFound)
+        ));
+    }
+
+    async fn decode_errors_name_the_entry<K: MuKv>(connect: fn(MuMemoryKv) -> K) {
+        let kv = connect(MuMemoryKv::new());
+        let users = kv.collection("users").await.unwrap();
+        users.update("alice", Some("{}")).await.unwrap();
+        users.update("bob", Some("not json")).await.unwrap();
+
+        let users = users.typed::<&str, serde_json::Value>();
+        assert!(matches!(
+            users.values().await,
+            Err(MuError::Decode { ref key, .. }) if key == "bob"
+        ));
+    }
+
+    async fn whole_numbers_are_found_by_integers<K: MuKv>(connect: fn(MuMemoryKv) -> K) {
+        let kv = connect(MuMemoryKv::new());
+        let indexes = [MuKvIndex::new("by_score", "score")];
+        let scores = kv
+            .collection_with_indexes("scores", &indexes)
+            .await
+            .unwrap()
+            .typed::<&str, serde_json::Value>();
+        scores
+            .set(&"a", &serde_json::json!({ "score": 1.0 }))
+            .await
+            .unwrap();
+        scores
+            .set(&"b", &serde_json::json!({ "score": 1 }))
+            .await
+            .unwrap();
+
+        assert_eq!(
+            scores.lookup("by_score", &1, None, 10).await.unwrap().len(),
+            2
+        );
+        assert_eq!(
+            scores
+                .lookup("by_score", &1.0, None, 10)
+                .await
+                .unwrap()
+                .len(),
+            2
+        );
+    }
+
+    async fn transactions_apply_all_or_nothing<K: MuKv>(connect: fn(MuMemoryKv) -> K) {
+        let kv = connect(MuMemoryKv::new());
+        let orders = kv.collection("orders").await.unwrap();
+        let stock = kv.collection("stock").await.unwrap();
+        stock.update("apple", Some("3")).await.unwrap();
+        let version = stock
+            .query_versioned("apple")
+            .await
+            .unwrap()
+            .unwrap()
+            .version;
+
+        let conflict = kv
+            .transaction()
+            .put("orders", "1", "apple")
+            .check("stock", "apple", Some(version + 1))
+            .commit()
+            .await
+            .unwrap();
+        assert!(matches!(conflict, MuKvCommit::Conflict { op: 1, .. }));
+        assert_eq!(orders.query("1").await.unwrap(), None);
+
+        let committed = kv
+            .transaction()
+            .check("stock", "apple", Some(version))
+            .put("orders", "1", "apple")
+            .put("stock", "apple", "2")
+            .commit()
+            .await
+            .unwrap();
+        assert!(matches!(
+            committed,
+            MuKvCommit::Committed { ref versions } if versions[0].is_none() && versions[2] > Some(version)
+        ));
+        assert_eq!(orders.query("1").await.unwrap().as_deref(), Some("apple"));
+        assert_eq!(stock.query("apple").await.unwrap().as_deref(), Some("2"));
+
+        assert!(matches!(
+            kv.transaction().delete("missing", "1").commit().await,
+            Err(MuError::NotFound)
+        ));
+    }
+}
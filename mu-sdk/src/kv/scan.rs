@@ -0,0 +1,134 @@
+use std::collections::VecDeque;
+
+use candid::CandidType;
+use serde::Deserialize;
+
+use super::MuKvCollection;
+use crate::MuError;
+
+/// The number of entries [`MuKvEntries`] fetches per call.
+const PAGE_SIZE: u32 = 100;
+
+/// The most entries mu-kv returns from a single `scan` or `range` call.
+#[cfg(any(test, feature = "testing"))]
+pub(super) const MAX_PAGE_SIZE: u32 = 1000;
+
+/// One page of entries returned by [`MuKvCollection::scan`],
+/// [`MuKvCollection::range`] or [`MuKvCollection::index_lookup`], in key
+/// order.
+#[derive(CandidType, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct MuKvPage {
+    pub entries: Vec<(String, String)>,
+    /// The key the next page starts at, if there are more entries.
+    pub next: Option<String>,
+}
+
+enum Query {
+    Prefix(String),
+    Range { to: String },
+    Index { index: String, value: String },
+}
+
+/// Entries of a collection fetched from mu-kv one page at a time.
+///
+/// Call [`MuKvEntries::next`] until it returns `None`.
+pub struct MuKvEntries<'a, C> {
+    collection: &'a C,
+    query: Query,
+    buffer: VecDeque<(String, String)>,
+    /// Where the next page starts: the last key returned for prefix scans and
+    /// index lookups, and the first key of the page for ranges. `None` once
+    /// all pages are read.
+    cursor: Option<String>,
+    started: bool,
+}
+
+impl<'a, C: MuKvCollection> MuKvEntries<'a, C> {
+    pub(super) fn prefix(collection: &'a C, prefix: &str) -> Self {
+        Self::new(collection, Query::Prefix(prefix.to_owned()), None)
+    }
+
+    pub(super) fn range(collection: &'a C, from: &str, to: &str) -> Self {
+        Self::new(
+            collection,
+            Query::Range { to: to.to_owned() },
+            Some(from.to_owned()),
+        )
+    }
+
+    pub(super) fn index(
+        collection: &'a C,
+        index: &str,
+        value: &str,
+        start_after: Option<&str>,
+    ) -> Self {
+        Self::new(
+            collection,
+            Query::Index {
+                index: index.to_owned(),
+                value: value.to_owned(),
+            },
+            start_after.map(str::to_owned),
+        )
+    }
+
+    fn new(collection: &'a C, query: Query, cursor: Option<String>) -> Self {
+        Self {
+            collection,
+            query,
+            buffer: VecDeque::new(),
+            cursor,
+            started: false,
+        }
+    }
+
+    /// The next entry, or `None` once all matching entries were returned.
+    pub async fn next(&mut self) -> Result<Option<(String, String)>, MuError> {
+        while self.buffer.is_empty() {
+            if self.started && self.cursor.is_none() {
+                return Ok(None);
+            }
+            self.fetch().await?;
+        }
+        Ok(self.buffer.pop_front())
+    }
+
+    /// Reads all remaining entries.
+    pub async fn collect(mut self) -> Result<Vec<(String, String)>, MuError> {
+        let mut entries = Vec::new();
+        while let Some(entry) = self.next().await? {
+            entries.push(entry);
+        }
+        Ok(entries)
+    }
+
+    async fn fetch(&mut self) -> Result<(), MuError> {
+        let page = match &self.query {
+            Query::Prefix(prefix) => {
+                self.collection
+                    .scan(prefix, self.cursor.as_deref(), PAGE_SIZE)
+                    .await?
+            }
+            Query::Range { to } => {
+                let from = self.cursor.as_deref().unwrap_or_default();
+                self.collection.range(from, to, PAGE_SIZE).await?
+            }
+            Query::Index { index, value } => {
+                self.collection
+                    .index_lookup(index, value, self.cursor.as_deref(), PAGE_SIZE)
+                    .await?
+            }
+        };
+        self.started = true;
+
+        self.cursor = match (&self.query, page.next) {
+            (_, None) => None,
+            (Query::Prefix(_) | Query::Index { .. }, Some(_)) => {
+                page.entries.last().map(|(key, _)| key.clone())
+            }
+            (Query::Range { .. }, next) => next,
+        };
+        self.buffer.extend(page.entries);
+        Ok(())
+    }
+}
@@ -0,0 +1,97 @@
+use candid::CandidType;
+use serde::Deserialize;
+
+use super::{MuKv, MuKvVersioned};
+use crate::MuError;
+
+/// An operation of a transaction, on one of the caller's collections.
+#[derive(CandidType, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub enum MuKvOp {
+    Put {
+        collection: String,
+        key: String,
+        value: String,
+    },
+    Delete {
+        collection: String,
+        key: String,
+    },
+    /// Fails the transaction unless the entry is at `version`, or does not
+    /// exist if `version` is `None`.
+    Check {
+        collection: String,
+        key: String,
+        version: Option<u64>,
+    },
+}
+
+/// The outcome of committing a [`MuKvTransaction`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MuKvCommit {
+    /// All operations were applied. Holds the new version of each put, and
+    /// `None` for the other operations.
+    Committed { versions: Vec<Option<u64>> },
+    /// The check at index `op` failed, and nothing was written; this is the
+    /// entry's current value.
+    Conflict {
+        op: usize,
+        current: Option<MuKvVersioned>,
+    },
+}
+
+/// Writes to several of the caller's collections that take effect together
+/// or not at all. Created with [`MuKv::transaction`].
+///
+/// Checks see the entries as they were before the transaction, so they
+/// guard against concurrent changes rather than the transaction's own puts.
+pub struct MuKvTransaction<'a, K: ?Sized> {
+    kv: &'a K,
+    ops: Vec<MuKvOp>,
+}
+
+impl<'a, K: MuKv + ?Sized> MuKvTransaction<'a, K> {
+    pub(super) fn new(kv: &'a K) -> Self {
+        Self {
+            kv,
+            ops: Vec::new(),
+        }
+    }
+
+    /// Sets `key` to `value`. The entry does not expire, even if it was
+    /// written with a TTL before.
+    pub fn put(mut self, collection: &str, key: &str, value: &str) -> Self {
+        self.ops.push(MuKvOp::Put {
+            collection: collection.to_owned(),
+            key: key.to_owned(),
+            value: value.to_owned(),
+        });
+        self
+    }
+
+    pub fn delete(mut self, collection: &str, key: &str) -> Self {
+        self.ops.push(MuKvOp::Delete {
+            collection: collection.to_owned(),
+            key: key.to_owned(),
+        });
+        self
+    }
+
+    /// Only commits if `key` is still at `version`, as returned by
+    /// [`MuKvCollection::query_versioned`](super::MuKvCollection::query_versioned),
+    /// or does not exist if `version` is `None`.
+    pub fn check(mut self, collection: &str, key: &str, version: Option<u64>) -> Self {
+        self.ops.push(MuKvOp::Check {
+            collection: collection.to_owned(),
+            key: key.to_owned(),
+            version,
+        });
+        self
+    }
+
+    /// Applies the operations. Fails with [`MuError::NotFound`] if one of
+    /// the collections does not exist, and with [`MuError::QuotaExceeded`]
+    /// if the puts would exceed the quota, in which case nothing is written.
+    pub async fn commit(self) -> Result<MuKvCommit, MuError> {
+        self.kv.transact(self.ops).await
+    }
+}
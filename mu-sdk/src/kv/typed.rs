@@ -0,0 +1,157 @@
+use std::marker::PhantomData;
+
+use candid::{CandidType, Decode, Encode};
+use serde::{de::DeserializeOwned, Serialize};
+
+use super::{index::index_key, MuKvCollection, MuKvEntries};
+use crate::MuError;
+
+/// How a [`TypedCollection`] turns values into the strings mu-kv stores.
+pub trait KvEncoding<V> {
+    fn encode(value: &V) -> Result<String, String>;
+    fn decode(encoded: &str) -> Result<V, String>;
+}
+
+/// Stores values as JSON documents.
+pub struct Json;
+
+impl<V: Serialize + DeserializeOwned> KvEncoding<V> for Json {
+    fn encode(value: &V) -> Result<String, String> {
+        serde_json::to_string(value).map_err(|e| e.to_string())
+    }
+
+    fn decode(encoded: &str) -> Result<V, String> {
+        serde_json::from_str(encoded).map_err(|e| e.to_string())
+    }
+}
+
+/// Stores values as hex encoded Candid. Hex doubles the size of the Candid
+/// bytes, so this is usually larger than JSON; use it for types that only
+/// implement `CandidType`, or to keep Candid's type checking on decode.
+pub struct Candid;
+
+impl<V: CandidType + DeserializeOwned> KvEncoding<V> for Candid {
+    fn encode(value: &V) -> Result<String, String> {
+        let bytes = Encode!(value).map_err(|e| e.to_string())?;
+        Ok(bytes.iter().map(|b| format!("{:02x}", b)).collect())
+    }
+
+    fn decode(encoded: &str) -> Result<V, String> {
+        if !encoded.len().is_multiple_of(2) {
+            return Err("odd number of hex digits".to_string());
+        }
+        let bytes = (0..encoded.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&encoded[i..i + 2], 16))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| e.to_string())?;
+        Decode!(&bytes, V).map_err(|e| e.to_string())
+    }
+}
+
+/// A view of a [`MuKvCollection`] with typed keys and values.
+///
+/// Keys are stored using their `ToString` representation and values using
+/// the encoding `E`, JSON by default.
+pub struct TypedCollection<C, K, V, E = Json> {
+    collection: C,
+    _types: PhantomData<(K, V, E)>,
+}
+
+impl<C: MuKvCollection, K: ToString, V, E: KvEncoding<V>> TypedCollection<C, K, V, E> {
+    pub fn new(collection: C) -> Self {
+        Self {
+            collection,
+            _types: PhantomData,
+        }
+    }
+
+    pub async fn get(&self, key: &K) -> Result<Option<V>, MuError> {
+        let key = key.to_string();
+        match self.collection.query(&key).await? {
+            Some(encoded) => E::decode(&encoded)
+                .map(Some)
+                .map_err(|message| MuError::Decode { key, message }),
+            None => Ok(None),
+        }
+    }
+
+    pub async fn set(&self, key: &K, value: &V) -> Result<(), MuError> {
+        let key = key.to_string();
+        let encoded = E::encode(value).map_err(|message| MuError::Encode {
+            key: key.clone(),
+            message,
+        })?;
+        self.collection.update(&key, Some(&encoded)).await
+    }
+
+    pub async fn remove(&self, key: &K) -> Result<(), MuError> {
+        self.collection.update(&key.to_string(), None).await
+    }
+
+    pub async fn keys(&self) -> Result<Vec<String>, MuError> {
+        self.collection.keys().await
+    }
+
+    pub async fn values(&self) -> Result<Vec<V>, MuError> {
+        // Scanned with their keys so a decode error can name the entry
+        self.collection
+            .scan_all("")
+            .collect()
+            .await?
+            .into_iter()
+            .map(|(key, encoded)| {
+                E::decode(&encoded).map_err(|message| MuError::Decode { key, message })
+            })
+            .collect()
+    }
+
+    pub async fn delete(&self) -> Result<(), MuError> {
+        self.collection.delete().await
+    }
+
+    /// The underlying untyped collection.
+    pub fn inner(&self) -> &C {
+        &self.collection
+    }
+}
+
+impl<C: MuKvCollection, K: ToString, V: Serialize + DeserializeOwned>
+    TypedCollection<C, K, V, Json>
+{
+    /// Up to `limit` entries whose field indexed by `index` equals `value`,
+    /// in key order and after `start_after` if given. Fewer are only returned
+    /// once there are no more, so pass the last key returned as `start_after`
+    /// to continue. Only strings, numbers and booleans can be looked up.
+    pub async fn lookup<T: Serialize + ?Sized>(
+        &self,
+        index: &str,
+        value: &T,
+        start_after: Option<&str>,
+        limit: u32,
+    ) -> Result<Vec<(String, V)>, MuError> {
+        let value = serde_json::to_value(value)
+            .map_err(|e| e.to_string())
+            .and_then(|value| {
+                index_key(&value)
+                    .ok_or_else(|| "only strings, numbers and booleans are indexed".to_string())
+            })
+            .map_err(|message| MuError::Encode {
+                key: index.to_owned(),
+                message,
+            })?;
+
+        let mut entries = MuKvEntries::index(&self.collection, index, &value, start_after);
+        let mut found = Vec::new();
+        while found.len() < limit as usize {
+            let Some((key, encoded)) = entries.next().await? else {
+                break;
+            };
+            match Json::decode(&encoded) {
+                Ok(value) => found.push((key, value)),
+                Err(message) => return Err(MuError::Decode { key, message }),
+            }
+        }
+        Ok(found)
+    }
+}
@@ -8,4 +8,30 @@ pub mod kv;
 pub enum MuError {
     NotFound,
     InternalError,
+    /// A call to another canister was rejected, or its reply did not decode
+    /// as the expected type.
+    CallFailed {
+        method: String,
+        message: String,
+    },
+    /// A stored value could not be decoded as the requested type.
+    Decode {
+        key: String,
+        message: String,
+    },
+    /// A value could not be encoded for storage.
+    Encode {
+        key: String,
+        message: String,
+    },
+    /// A write was rejected because it would take the collection owner's
+    /// usage to `usage`, over its `quota`.
+    QuotaExceeded {
+        quota: kv::MuKvQuota,
+        usage: kv::MuKvUsage,
+    },
+    /// The id of the mu-kv canister is missing or invalid.
+    KvNotConfigured {
+        message: String,
+    },
 }